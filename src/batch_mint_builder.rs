@@ -1,18 +1,47 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use anchor_lang::prelude::*;
 
-use mpl_bubblegum::types::{Creator, LeafSchema, MetadataArgs};
+use mpl_bubblegum::types::{Creator, LeafSchema, MetadataArgs, TokenStandard, Version};
+use solana_sdk::instruction::AccountMeta;
 use solana_sdk::signature::Signature;
 
 use crate::errors::BatchMintError;
-use crate::merkle_tree_wrapper::{make_concurrent_merkle_tree, IChangeLog, ITree};
+#[cfg(any(debug_assertions, feature = "strict-checks"))]
+use crate::merkle_tree_wrapper::calc_level_nodes;
+use crate::merkle_tree_wrapper::{
+    calc_level_nodes_from, default_buffer_for, make_concurrent_merkle_tree, IChangeLog, ITree,
+};
+#[cfg(any(debug_assertions, feature = "strict-checks"))]
+use crate::util::node_to_string;
+use crate::util::{keccak_hashv, metadata_hash};
+use spl_concurrent_merkle_tree::node::empty_node;
+
+use crate::model::{
+    BatchMint, BatchMintCheckpoint, BatchMintInstruction, BatchMintSession, ChangeLogEventV1, CollectionConfig,
+    FinalizeArgs, TreeParams,
+};
 
-use crate::model::{BatchMint, BatchMintInstruction, ChangeLogEventV1, CollectionConfig};
-
-use solana_sdk::keccak;
 use solana_sdk::pubkey::Pubkey;
 
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Defines how [BatchMintBuilder::add_signatures_for_verified_creators] treats
+/// a signature received for a creator that is not marked as verified in the asset metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignatureMode {
+    /// Default mode: such a signature is rejected
+    /// with [BatchMintError::CannotAddSignatureForUnverifiedCreator].
+    #[default]
+    Strict,
+    /// Such a signature is silently dropped, it is neither verified nor saved.
+    /// Useful when signatures are collected before the `verified` flags of creators are finalized.
+    Lenient,
+}
+
 /// Builder that allows to easily build an offline compressed NFT,
 /// that can be efficiently (cheap) saved onchain afterward.
 ///
@@ -41,16 +70,28 @@ pub struct BatchMintBuilder {
     pub canopy_leaves: Vec<[u8; 32]>,
     /// config for verifying collection
     pub collection_config: Option<CollectionConfig>,
+    /// how signatures of unverified creators are treated, see [SignatureMode]
+    pub signature_mode: SignatureMode,
+    /// set once the finalization has started, see [BatchMintBuilder::seal]
+    sealed: AtomicBool,
+    /// assets added by [BatchMintBuilder::add_asset_at_nonce] ahead of the next free nonce,
+    /// they are appended to the tree as soon as the gap before them is filled
+    pending_mints: BTreeMap<u64, (Pubkey, Pubkey, MetadataArgs)>,
+    /// remaining accounts of the finalize instruction set by [BatchMintBuilder::override_remaining_accounts]
+    remaining_accounts_override: Option<Vec<AccountMeta>>,
 }
 
 impl BatchMintBuilder {
     /// Create a builder with an empty merkle tree of given depth and max buffer size inside.
     pub(crate) fn new(
         tree_account: Pubkey,
-        max_depth: u32,
-        max_buffer_size: u32,
-        canopy_depth: u32,
+        tree_params: impl Into<TreeParams>,
     ) -> std::result::Result<BatchMintBuilder, BatchMintError> {
+        let TreeParams {
+            max_depth,
+            max_buffer_size,
+            canopy_depth,
+        } = tree_params.into();
         let mut merkle = make_concurrent_merkle_tree(max_depth, max_buffer_size)?;
         merkle.initialize().unwrap();
 
@@ -64,20 +105,265 @@ impl BatchMintBuilder {
             last_leaf_hash: [0; 32],
             canopy_leaves: Vec::new(),
             collection_config: None,
+            signature_mode: SignatureMode::default(),
+            sealed: AtomicBool::new(false),
+            pending_mints: BTreeMap::new(),
+            remaining_accounts_override: None,
         })
     }
 
+    /// Creates a builder for a tree of given depth with the conventional max buffer size for it,
+    /// see [default_buffer_for]. Allows to build a batch mint offline without knowing
+    /// which buffer sizes are supported for the depth.
+    pub fn new_with_default_buffer(
+        tree_account: Pubkey,
+        max_depth: u32,
+        canopy_depth: u32,
+    ) -> std::result::Result<BatchMintBuilder, BatchMintError> {
+        let max_buffer_size = default_buffer_for(max_depth)
+            .ok_or_else(|| BatchMintError::IllegalArgumets(format!("Unsupported tree depth {max_depth}")))?;
+        Self::new(tree_account, (max_depth, max_buffer_size, canopy_depth))
+    }
+
+    /// Creates a builder filled with assets of the given batch mint.
+    ///
+    /// Leaf hash of each asset is recalculated from its metadata and compared with the stored one,
+    /// [BatchMintError::CorruptBatchMint] is returned if they differ.
+    ///
+    /// `max_depth` and `max_buffer_size` declared in the batch mint must match the given tree params
+    /// (i.e. the on-chain tree), otherwise [BatchMintError::GeometryMismatch] is returned.
+    pub(crate) fn restore(
+        batch_mint: &BatchMint,
+        tree_params: impl Into<TreeParams>,
+    ) -> std::result::Result<BatchMintBuilder, BatchMintError> {
+        let tree_params = tree_params.into();
+        if batch_mint.max_depth != tree_params.max_depth || batch_mint.max_buffer_size != tree_params.max_buffer_size {
+            return Err(BatchMintError::GeometryMismatch {
+                max_depth: batch_mint.max_depth,
+                max_buffer_size: batch_mint.max_buffer_size,
+                onchain_max_depth: tree_params.max_depth,
+                onchain_max_buffer_size: tree_params.max_buffer_size,
+            });
+        }
+
+        let mut batch_mint_builder = BatchMintBuilder::new(batch_mint.tree_id, tree_params)?;
+
+        for batch_mint in &batch_mint.batch_mints {
+            let BatchMintInstruction {
+                tree_update: _,
+                leaf_update,
+                mint_args,
+                authority: _,
+                creator_signature,
+            } = batch_mint;
+            ensure_leaf_schema_v1(leaf_update)?;
+
+            let metadata_arg_hash =
+                batch_mint_builder.add_asset(&leaf_update.owner(), &leaf_update.delegate(), mint_args)?;
+
+            if metadata_arg_hash.get_hashed_leaf() != leaf_update.hash() {
+                return Err(BatchMintError::CorruptBatchMint {
+                    nonce: leaf_update.nonce(),
+                });
+            }
+
+            if let Some(creator_signature) = creator_signature {
+                let mut message_and_signature = HashMap::new();
+                message_and_signature.insert(metadata_arg_hash.get_nonce(), creator_signature.clone());
+
+                batch_mint_builder.add_signatures_for_verified_creators(message_and_signature)?;
+            }
+        }
+
+        Ok(batch_mint_builder)
+    }
+
+    /// Size parameters of the tree this builder is created for.
+    pub fn tree_params(&self) -> TreeParams {
+        TreeParams {
+            max_depth: self.max_depth,
+            max_buffer_size: self.max_buffer_size,
+            canopy_depth: self.canopy_depth,
+        }
+    }
+
+    /// Number of assets that can still be added before the tree is full.
+    /// Assets added with [Self::add_asset_at_nonce] that wait for the preceding nonces take their leaves as well.
+    pub fn remaining_capacity(&self) -> u64 {
+        (1u64 << self.max_depth).saturating_sub((self.mints.len() + self.pending_mints.len()) as u64)
+    }
+
+    /// Marks the builder as sealed: adding assets to it fails with [BatchMintError::BuilderSealed].
+    ///
+    /// It is called by [crate::batch_mint_client::BatchMintClient] as soon as the finalization starts,
+    /// since the canopy and the root uploaded to the tree would become stale if more assets were added.
+    pub fn seal(&self) {
+        self.sealed.store(true, Ordering::Relaxed);
+    }
+
+    /// See [Self::seal]
+    pub fn is_sealed(&self) -> bool {
+        self.sealed.load(Ordering::Relaxed)
+    }
+
+    /// Returns by how much the sequence number of the on-chain tree is increased when the batch mint is finalized.
+    ///
+    /// It is always 1, regardless the number of assets: the whole batch mint is applied by a single
+    /// FinalizeTreeWithRoot instruction, i.e. it appears as one change of the on-chain tree,
+    /// while the sequence number of the off-chain tree ([ITree::sequence_number]) is increased by every added asset.
+    pub fn parent_sequence_delta(&self) -> u64 {
+        1
+    }
+
     /// Add an asset to the merkle tree
     /// ## Arguments:
     /// - `owner` - asset owner
     /// - `delegate` - [delegate authority](https://developers.metaplex.com/bubblegum/delegate-cnfts) of the asset allowed to perform actions on behalf of the owner - transferring or burning
     /// - `metadata_args` - asset details as [MetadataArgs]
+    ///
+    /// Note: `metadata_args.edition_nonce` is a part of the data hash, it is serialized
+    /// the same way as the Bubblegum program does it (borsh `Option<u8>`).
+    /// For a standard `TokenStandard::NonFungible` asset it is expected to be `None`,
+    /// `Some(bump)` should only be set when the asset refers to an edition account.
+    ///
+    /// If the collection config is already set up, an asset with verified collection
+    /// that differs from the configured collection mint is rejected with [BatchMintError::CollectionKeyMismatch].
+    ///
+    /// Assets cannot be added once the finalization has started, see [Self::seal].
+    ///
+    /// The asset gets the next free nonce, i.e. nonce == insertion order. The nonce is the leaf index
+    /// in the tree, the asset ID is derived from it (see [mpl_bubblegum::utils::get_asset_id]),
+    /// and the batch mint lists assets in nonce order, which is also the order they are revealed in.
+    /// Use [Self::add_asset_at_nonce] to control the nonce (and therefore the asset ID) of an asset.
     pub fn add_asset(
         &mut self,
         owner: &Pubkey,
         delegate: &Pubkey,
         metadata_args: &MetadataArgs,
     ) -> std::result::Result<MetadataArgsHash, BatchMintError> {
+        self.add_asset_at_nonce(self.mints.len() as u64, owner, delegate, metadata_args)
+    }
+
+    /// Add an asset to the merkle tree at the given nonce, e.g. to follow a pre-planned
+    /// nonce → asset ID mapping of a drop, regardless of the order assets are added in.
+    ///
+    /// Fails with [BatchMintError::NonceAlreadyUsed] if there already is an asset with this nonce.
+    /// An asset added ahead of the next free nonce is kept aside until all the nonces before it are taken,
+    /// [Self::build_batch_mint] fails with [BatchMintError::NonceGap] while there is a gap.
+    /// Note: [Self::merkle], [Self::mints] and the canopy only contain the assets appended to the tree so far.
+    ///
+    /// See [Self::add_asset] for the arguments and other checks.
+    pub fn add_asset_at_nonce(
+        &mut self,
+        nonce: u64,
+        owner: &Pubkey,
+        delegate: &Pubkey,
+        metadata_args: &MetadataArgs,
+    ) -> std::result::Result<MetadataArgsHash, BatchMintError> {
+        self.check_asset_can_be_added(metadata_args)?;
+
+        if nonce < self.mints.len() as u64 || self.pending_mints.contains_key(&nonce) {
+            return Err(BatchMintError::NonceAlreadyUsed { nonce });
+        }
+        if nonce >= 1u64 << self.max_depth {
+            return Err(BatchMintError::IllegalArgumets(format!(
+                "Nonce {nonce} exceeds the capacity of a tree of depth {}",
+                self.max_depth
+            )));
+        }
+
+        if nonce > self.mints.len() as u64 {
+            self.pending_mints
+                .insert(nonce, (*owner, *delegate, metadata_args.clone()));
+            return Ok(hash_metadata_args(
+                nonce,
+                &self.tree_account,
+                owner,
+                delegate,
+                metadata_args,
+            ));
+        }
+
+        let metadata_args_hash = self.append_asset(owner, delegate, metadata_args, true);
+        while let Some((owner, delegate, metadata_args)) = self.pending_mints.remove(&(self.mints.len() as u64)) {
+            self.append_asset(&owner, &delegate, &metadata_args, true);
+        }
+
+        Ok(metadata_args_hash)
+    }
+
+    /// Adds several assets to the merkle tree, the same way as calling [Self::add_asset] for each of them,
+    /// and returns their hashes in the insertion order.
+    ///
+    /// Unlike separate [Self::add_asset] calls, all the assets are checked before the first one is added,
+    /// so on error none of them is added. A canopy leaf node is updated once per canopy subtree,
+    /// after the last asset of the subtree is appended, instead of once per asset.
+    ///
+    /// ## Arguments
+    /// `items` - `(owner, delegate, metadata_args)` of each asset
+    pub fn add_assets(
+        &mut self,
+        items: &[(Pubkey, Pubkey, MetadataArgs)],
+    ) -> std::result::Result<Vec<MetadataArgsHash>, BatchMintError> {
+        if !self.pending_mints.is_empty() {
+            // pending assets are appended in between, it cannot be done in one pass
+            return items
+                .iter()
+                .map(|(owner, delegate, metadata_args)| self.add_asset(owner, delegate, metadata_args))
+                .collect();
+        }
+
+        for (_, _, metadata_args) in items {
+            self.check_asset_can_be_added(metadata_args)?;
+        }
+        if items.len() as u64 > self.remaining_capacity() {
+            return Err(BatchMintError::IllegalArgumets(format!(
+                "{} assets do not fit into the tree, only {} more can be added",
+                items.len(),
+                self.remaining_capacity()
+            )));
+        }
+
+        let assets_per_canopy_leaf = 1u64 << self.max_depth.saturating_sub(self.canopy_depth);
+        Ok(items
+            .iter()
+            .enumerate()
+            .map(|(index, (owner, delegate, metadata_args))| {
+                let nonce = self.mints.len() as u64;
+                let last_in_canopy_subtree = index + 1 == items.len() || (nonce + 1) % assets_per_canopy_leaf == 0;
+                self.append_asset(owner, delegate, metadata_args, last_in_canopy_subtree)
+            })
+            .collect())
+    }
+
+    /// Checks the builder is not sealed and the asset collection matches the collection config.
+    fn check_asset_can_be_added(&self, metadata_args: &MetadataArgs) -> std::result::Result<(), BatchMintError> {
+        if self.is_sealed() {
+            return Err(BatchMintError::BuilderSealed {
+                tree_account: self.tree_account,
+            });
+        }
+
+        if let (Some(collection), Some(collection_config)) = (&metadata_args.collection, &self.collection_config) {
+            if collection.verified && collection.key != collection_config.collection_mint {
+                return Err(BatchMintError::CollectionKeyMismatch {
+                    expected: collection_config.collection_mint,
+                    actual: collection.key,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends an asset to the merkle tree at the next free nonce.
+    /// The canopy leaf node of the asset subtree is updated only if `update_canopy` is set.
+    fn append_asset(
+        &mut self,
+        owner: &Pubkey,
+        delegate: &Pubkey,
+        metadata_args: &MetadataArgs,
+        update_canopy: bool,
+    ) -> MetadataArgsHash {
         let metadata_args_hash = hash_metadata_args(
             self.mints.len() as u64,
             &self.tree_account,
@@ -96,10 +382,10 @@ impl BatchMintBuilder {
         self.merkle.append(hashed_leaf).unwrap();
 
         self.last_leaf_hash = hashed_leaf;
-        let changelog = self.merkle.change_logs(self.merkle.active_index() as usize);
-        let path = make_changelog_path(changelog.as_ref());
+        let changelog = self.merkle.change_log(self.merkle.active_index() as usize);
+        let path = make_changelog_path(changelog);
 
-        if self.canopy_depth > 0 {
+        if self.canopy_depth > 0 && update_canopy {
             let path_slice = changelog.path_slice();
             let path_ind = path_slice.len() - (self.canopy_depth as usize);
             let canopy_ind = changelog.index() >> (self.max_depth - self.canopy_depth);
@@ -130,9 +416,186 @@ impl BatchMintBuilder {
             authority: *owner,
             creator_signature: None,
         };
+        debug_assert!(metadata_args_hash.verify(&batch_mint.leaf_update));
         self.mints.insert(nonce, batch_mint);
 
-        Ok(metadata_args_hash)
+        // checking at geometric checkpoints keeps the total cost linear in the number of assets
+        #[cfg(any(debug_assertions, feature = "strict-checks"))]
+        if self.mints.len().is_power_of_two() {
+            self.check_root();
+        }
+
+        metadata_args_hash
+    }
+
+    /// Checks that canopy leaf nodes maintained by the builder are consistent with the added assets,
+    /// i.e. there is exactly one canopy leaf node for each (maybe partially) filled canopy subtree:
+    /// `ceil(assets count / 2^(max_depth - canopy_depth))`, and none of them is empty.
+    ///
+    /// Allows to detect canopy maintenance bugs before sending AddCanopy transactions.
+    pub fn validate_canopy(&self) -> std::result::Result<(), BatchMintError> {
+        let expected = if self.canopy_depth == 0 {
+            0
+        } else {
+            let assets_per_canopy_leaf = 1usize << self.max_depth.saturating_sub(self.canopy_depth);
+            self.mints.len().div_ceil(assets_per_canopy_leaf)
+        };
+        if self.canopy_leaves.len() != expected {
+            return Err(BatchMintError::CanopyLeavesCountMismatch {
+                expected,
+                actual: self.canopy_leaves.len(),
+            });
+        }
+        if let Some(index) = self.canopy_leaves.iter().position(|leaf| leaf == &[0; 32]) {
+            return Err(BatchMintError::EmptyCanopyLeaf { index });
+        }
+        Ok(())
+    }
+
+    /// Computes the node at the top of the canopy, i.e. the tree root implied by the canopy leaf nodes
+    /// maintained by the builder. That is what the bubblegum program checks the uploaded canopy against
+    /// on finalization (the CanopyRootMismatch error), so it must be equal to the root of the tree.
+    ///
+    /// Returns None if the canopy is disabled.
+    pub fn canopy_root(&self) -> Option<[u8; 32]> {
+        if self.canopy_depth == 0 {
+            return None;
+        }
+        let canopy_level = self.max_depth - self.canopy_depth;
+        let root = calc_level_nodes_from(self.canopy_leaves.clone(), canopy_level, self.max_depth)
+            .first()
+            .copied()
+            .unwrap_or_else(|| empty_node(self.max_depth));
+        Some(root)
+    }
+
+    /// Recomputes the tree root from leaf hashes of all the added assets,
+    /// and panics if it differs from the root of the merkle tree.
+    /// This allows to detect changelog/path construction bugs long before the finalization.
+    ///
+    /// Called in debug builds (or with `strict-checks` feature) each time the number of assets reaches a power of two.
+    #[cfg(any(debug_assertions, feature = "strict-checks"))]
+    fn check_root(&self) {
+        let leaves = self
+            .mints
+            .values()
+            .map(|batch_mint| batch_mint.leaf_update.hash())
+            .collect::<Vec<_>>();
+        let root = self.merkle.get_root();
+        if calc_root(&leaves, self.max_depth) == root {
+            return;
+        }
+        // the root after each append is the last node of the asset changelog path
+        let roots = self
+            .mints
+            .values()
+            .map(|batch_mint| batch_mint.tree_update.path.last().unwrap().node)
+            .collect::<Vec<_>>();
+        let index = first_divergent_index(&leaves, &roots, self.max_depth);
+        panic!(
+            "Merkle tree root {} diverged from the expected {} after asset with index {}",
+            node_to_string(&roots[index]),
+            node_to_string(&calc_root(&leaves[..=index], self.max_depth)),
+            index
+        );
+    }
+
+    /// Returns the values the finalize instruction commits to the tree for this batch mint,
+    /// exactly the ones [crate::batch_mint_client::BatchMintClient::finalize_tree_instruction] uses.
+    /// Allows to log and verify the commitment before the instruction is built.
+    ///
+    /// The rightmost index is the nonce of the last asset appended to the tree.
+    /// Assets added with [Self::add_asset_at_nonce] that still wait for the preceding nonces are not in the tree yet,
+    /// so they do not move it.
+    pub fn finalize_args(&self, metadata_url: &str, metadata_hash: &str) -> FinalizeArgs {
+        FinalizeArgs {
+            root: self.merkle.get_root(),
+            rightmost_leaf: self.last_leaf_hash,
+            rightmost_index: self.mints.keys().next_back().copied().unwrap_or_default() as u32,
+            metadata_url: metadata_url.to_string(),
+            metadata_hash: metadata_hash.to_string(),
+        }
+    }
+
+    /// Returns the part of the rightmost proof that has to be passed to the finalize instruction.
+    ///
+    /// When the tree has a canopy, the program fills in the upper `canopy_depth` proof nodes from it,
+    /// so only the lower `max_depth - canopy_depth` nodes are passed, which also keeps the finalize transaction
+    /// small for deep trees. Without canopy the whole proof is returned.
+    pub fn trimmed_rightmost_proof(&self) -> &[[u8; 32]] {
+        let proof = self.merkle.get_rightmost_proof();
+        &proof[..proof.len().saturating_sub(self.canopy_depth as usize)]
+    }
+
+    /// Returns the remaining accounts the finalize instruction is sent with, i.e. the proof of the rightmost leaf
+    /// trimmed for the canopy (see [BatchMintBuilder::trimmed_rightmost_proof]) as readonly accounts,
+    /// or the accounts set by [BatchMintBuilder::override_remaining_accounts].
+    pub fn remaining_accounts(&self) -> Vec<AccountMeta> {
+        if let Some(ref remaining_accounts) = self.remaining_accounts_override {
+            return remaining_accounts.clone();
+        }
+        self.trimmed_rightmost_proof()
+            .iter()
+            .map(|proof| AccountMeta::new_readonly(Pubkey::new_from_array(*proof), false))
+            .collect()
+    }
+
+    /// Replaces the remaining accounts (see [BatchMintBuilder::remaining_accounts]) the finalize instruction
+    /// is sent with by the given ones, `None` restores the calculated ones.
+    ///
+    /// This is a debugging tool for the rare cases the calculated proof needs a manual adjustment.
+    /// The program verifies the proof against the root of the batch mint,
+    /// so with wrong accounts the finalize transaction fails, after the canopy has been uploaded.
+    pub fn override_remaining_accounts(&mut self, remaining_accounts: Option<Vec<AccountMeta>>) {
+        self.remaining_accounts_override = remaining_accounts;
+    }
+
+    /// Returns a compact snapshot of the current builder progress, see [BatchMintCheckpoint].
+    pub fn to_checkpoint(&self) -> BatchMintCheckpoint {
+        BatchMintCheckpoint {
+            count: self.mints.len() as u64,
+            root: self.merkle.get_root(),
+            last_leaf_hash: self.last_leaf_hash,
+            canopy_leaves: self.canopy_leaves.clone(),
+        }
+    }
+
+    /// Writes a compact JSON snapshot (number of assets, root, last leaf hash and canopy leaves)
+    /// of the current builder progress into given destination.
+    ///
+    /// It is much lighter than the whole batch mint, so it can be saved periodically while building
+    /// a huge batch mint, and later used with [Self::resume_from_checkpoint].
+    pub fn checkpoint(&self, writer: &mut dyn Write) -> std::result::Result<(), BatchMintError> {
+        serde_json::to_writer(writer, &self.to_checkpoint()).map_err(|e| BatchMintError::IoError(e.into()))
+    }
+
+    /// Reads a checkpoint written by [Self::checkpoint] and verifies that this builder,
+    /// after the assets have been re-added to it, is in exactly the same state as the checkpointed one.
+    ///
+    /// Returns [BatchMintError::CheckpointMismatch] with the name of the first differing field otherwise.
+    pub fn resume_from_checkpoint(&self, reader: impl Read) -> std::result::Result<(), BatchMintError> {
+        let checkpoint: BatchMintCheckpoint =
+            serde_json::from_reader(reader).map_err(|e| BatchMintError::IoError(e.into()))?;
+        let current = self.to_checkpoint();
+
+        let mismatch = if current.count != checkpoint.count {
+            Some("count")
+        } else if current.root != checkpoint.root {
+            Some("root")
+        } else if current.last_leaf_hash != checkpoint.last_leaf_hash {
+            Some("last_leaf_hash")
+        } else if current.canopy_leaves != checkpoint.canopy_leaves {
+            Some("canopy_leaves")
+        } else {
+            None
+        };
+
+        match mismatch {
+            Some(field) => Err(BatchMintError::CheckpointMismatch {
+                field: field.to_string(),
+            }),
+            None => Ok(()),
+        }
     }
 
     /// Adds signatures for verified creators.
@@ -142,6 +605,8 @@ impl BatchMintBuilder {
     /// ## Arguments
     /// - `nonce_and_creator_signatures` - hashMap with creators signatures for assets. As a key in first hashMap
     /// asset nonce is using. Nested hashMap contains pairs of creator Pubkey and signature.
+    ///
+    /// A signature of a creator that is not verified is handled according to [Self::signature_mode].
     pub fn add_signatures_for_verified_creators(
         &mut self,
         nonce_and_creator_signatures: HashMap<u64, HashMap<Pubkey, Signature>>,
@@ -153,6 +618,7 @@ impl BatchMintBuilder {
             }
 
             if let Some(batch_mint) = self.mints.get_mut(&asset_nonce) {
+                ensure_leaf_schema_v1(&batch_mint.leaf_update)?;
                 Self::check_extra_creators(&batch_mint.mint_args.creators, &creator_signature)?;
 
                 let mut batch_mint_signatures = batch_mint.creator_signature.clone().unwrap_or_default();
@@ -164,9 +630,14 @@ impl BatchMintBuilder {
                 for creator in batch_mint.mint_args.creators.iter_mut() {
                     if let Some(signature) = creator_signature.get(&creator.address) {
                         if !creator.verified {
-                            return Err(BatchMintError::CannotAddSignatureForUnverifiedCreator(
-                                creator.address.to_string(),
-                            ));
+                            match self.signature_mode {
+                                SignatureMode::Strict => {
+                                    return Err(BatchMintError::CannotAddSignatureForUnverifiedCreator(
+                                        creator.address.to_string(),
+                                    ));
+                                }
+                                SignatureMode::Lenient => continue,
+                            }
                         }
 
                         if !verify_signature(&creator.address, &signed_message, signature) {
@@ -201,52 +672,203 @@ impl BatchMintBuilder {
         Ok(())
     }
 
+    /// Returns nonces of assets that are still waiting for a signature, grouped by verified creator.
+    /// Allows a signing coordinator to send a single signing request to every creator.
+    ///
+    /// [Self::build_batch_mint] succeeds (as far as creator signatures are concerned)
+    /// only when the returned map is empty.
+    pub fn outstanding_by_creator(&self) -> HashMap<Pubkey, Vec<u64>> {
+        let mut outstanding: HashMap<Pubkey, Vec<u64>> = HashMap::new();
+        for (nonce, batch_mint) in &self.mints {
+            for creator in Self::creators_missing_signature(batch_mint) {
+                outstanding.entry(creator.address).or_default().push(*nonce);
+            }
+        }
+        outstanding
+    }
+
+    /// Returns all distinct owners of the added assets, e.g. to cross-check an airdrop against an allowlist.
+    ///
+    /// Iterates over all the added assets, i.e. takes O(n) time.
+    pub fn distinct_owners(&self) -> HashSet<Pubkey> {
+        self.mints.values().map(|mint| mint.leaf_update.owner()).collect()
+    }
+
+    /// Returns all distinct delegates of the added assets, see [Self::distinct_owners].
+    ///
+    /// Iterates over all the added assets, i.e. takes O(n) time.
+    pub fn distinct_delegates(&self) -> HashSet<Pubkey> {
+        self.mints.values().map(|mint| mint.leaf_update.delegate()).collect()
+    }
+
+    /// Verified creators of the asset, whose signatures haven't been added yet.
+    fn creators_missing_signature(batch_mint: &BatchMintInstruction) -> impl Iterator<Item = &Creator> {
+        batch_mint.mint_args.creators.iter().filter(move |creator| {
+            creator.verified
+                && !batch_mint
+                    .creator_signature
+                    .as_ref()
+                    .is_some_and(|signatures| signatures.contains_key(&creator.address))
+        })
+    }
+
     pub fn build_batch_mint(&self) -> std::result::Result<BatchMint, BatchMintError> {
+        if !self.pending_mints.is_empty() {
+            return Err(BatchMintError::NonceGap {
+                nonce: self.mints.len() as u64,
+            });
+        }
         // make sure user did not miss any creator's signature
         for batch_mint in self.mints.values() {
-            for creator in &batch_mint.mint_args.creators {
-                if creator.verified {
-                    if let Some(creator_signatures) = &batch_mint.creator_signature {
-                        if !creator_signatures.contains_key(&creator.address) {
-                            return Err(BatchMintError::MissedSignatureFromCreator(creator.address.to_string()));
-                        }
-                    } else {
-                        return Err(BatchMintError::MissedSignaturesForAsset(
-                            batch_mint.leaf_update.id().to_string(),
-                        ));
-                    }
-                }
+            if let Some(error) = self.asset_completeness_errors(batch_mint).into_iter().next() {
+                return Err(error);
             }
-            if let Some(ref collection) = batch_mint.mint_args.collection {
-                if !collection.verified {
-                    continue;
-                }
-                if let Some(ref collection_config) = self.collection_config {
-                    if collection.key != collection_config.collection_mint {
-                        return Err(BatchMintError::MissingCollectionSignature(collection.key.to_string()));
-                    }
-                    continue;
-                }
-                // no collection_config but collection.verified == true for some mint
-                return Err(BatchMintError::MissingCollectionSignature(collection.key.to_string()));
+        }
+
+        Ok(self.to_batch_mint())
+    }
+
+    /// Same as [Self::build_batch_mint], but instead of returning the first error,
+    /// checks all the assets and returns all the errors found, in the order of assets,
+    /// so everything that is still missing (creator signatures, collection config) can be seen at once.
+    pub fn build_batch_mint_checked(&self) -> std::result::Result<BatchMint, Vec<BatchMintError>> {
+        let mut errors = Vec::new();
+        if !self.pending_mints.is_empty() {
+            errors.push(BatchMintError::NonceGap {
+                nonce: self.mints.len() as u64,
+            });
+        }
+        for batch_mint in self.mints.values() {
+            errors.extend(self.asset_completeness_errors(batch_mint));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(self.to_batch_mint())
+    }
+
+    /// Returns the reasons the asset cannot be included into the batch mint yet:
+    /// missing signatures of verified creators, and a verified collection that doesn't match the collection config.
+    fn asset_completeness_errors(&self, batch_mint: &BatchMintInstruction) -> Vec<BatchMintError> {
+        let mut errors = Vec::new();
+        if batch_mint.creator_signature.is_some() {
+            errors.extend(
+                Self::creators_missing_signature(batch_mint)
+                    .map(|creator| BatchMintError::MissedSignatureFromCreator(creator.address.to_string())),
+            );
+        } else if Self::creators_missing_signature(batch_mint).next().is_some() {
+            errors.push(BatchMintError::MissedSignaturesForAsset(
+                batch_mint.leaf_update.id().to_string(),
+            ));
+        }
+        if let Some(ref collection) = batch_mint.mint_args.collection {
+            // without collection config no asset may have a verified collection
+            let collection_matches = self
+                .collection_config
+                .as_ref()
+                .is_some_and(|collection_config| collection.key == collection_config.collection_mint);
+            if collection.verified && !collection_matches {
+                errors.push(BatchMintError::MissingCollectionSignature(collection.key.to_string()));
             }
         }
+        errors
+    }
+
+    /// Same as [Self::build_batch_mint], but also serializes the batch mint with [BatchMint::write_as_json],
+    /// and returns the JSON bytes together with their hash, see [crate::util::metadata_hash].
+    ///
+    /// Upload exactly the returned bytes and pass the hash to finalize as `metadata_hash`:
+    /// DAS recomputes the hash from the downloaded document.
+    pub fn build_batch_mint_with_hash(&self) -> std::result::Result<(Vec<u8>, String), BatchMintError> {
+        let batch_mint = self.build_batch_mint()?;
+        let mut document = Vec::new();
+        batch_mint
+            .write_as_json(&mut document)
+            .map_err(|e| BatchMintError::IoError(e.into()))?;
+        let metadata_hash = metadata_hash(&document);
+        Ok((document, metadata_hash))
+    }
 
-        Ok(BatchMint {
+    /// Converts the builder into batch mint as is, without checking all the required signatures are present.
+    fn to_batch_mint(&self) -> BatchMint {
+        BatchMint {
             tree_id: self.tree_account,
             raw_metadata_map: HashMap::new(), // TODO: fill? this may be provided by the client for every asset, maybe in add_asset as an optional parameter
+            asset_metadata_map: HashMap::new(),
+            metadata_hash: None,
             max_depth: self.max_depth,
             batch_mints: self.mints.values().cloned().collect(), // TODO: maybe it's better to move out mints not clone all of it
             merkle_root: self.merkle.get_root(),
             last_leaf_hash: self.last_leaf_hash,
             max_buffer_size: self.max_buffer_size,
-        })
+        }
+    }
+
+    /// Writes the full builder state as JSON into given destination: all the assets
+    /// with creator signatures collected so far (which may be incomplete, unlike [Self::build_batch_mint]),
+    /// and the canopy. See [BatchMintSession].
+    ///
+    /// The session can be loaded back with [crate::batch_mint_client::BatchMintClient::load_session].
+    /// Note: the collection config is not saved, since it contains the collection authority signer,
+    /// it should be set up again after loading.
+    pub fn save_session(&self, writer: &mut dyn Write) -> std::result::Result<(), BatchMintError> {
+        let session = BatchMintSession {
+            canopy_depth: self.canopy_depth,
+            canopy_leaves: self.canopy_leaves.clone(),
+            batch_mint: self.to_batch_mint(),
+        };
+        serde_json::to_writer(writer, &session).map_err(|e| BatchMintError::IoError(e.into()))
+    }
+
+    /// Recreates the builder saved with [Self::save_session] for the tree of given size.
+    /// Assets and signatures are restored the same way as by [Self::restore],
+    /// then the resulting root and canopy are compared with the saved ones,
+    /// [BatchMintError::CheckpointMismatch] is returned if they differ.
+    pub(crate) fn from_session(
+        session: &BatchMintSession,
+        tree_params: impl Into<TreeParams>,
+    ) -> std::result::Result<BatchMintBuilder, BatchMintError> {
+        let batch_mint_builder = BatchMintBuilder::restore(&session.batch_mint, tree_params)?;
+
+        let mismatch = if batch_mint_builder.canopy_depth != session.canopy_depth {
+            Some("canopy_depth")
+        } else if batch_mint_builder.merkle.get_root() != session.batch_mint.merkle_root {
+            Some("root")
+        } else if batch_mint_builder.canopy_leaves != session.canopy_leaves {
+            Some("canopy_leaves")
+        } else {
+            None
+        };
+        if let Some(field) = mismatch {
+            return Err(BatchMintError::CheckpointMismatch {
+                field: field.to_string(),
+            });
+        }
+
+        Ok(batch_mint_builder)
     }
 
     #[inline(always)]
     pub fn setup_collection_config(&mut self, collection_config: CollectionConfig) {
         self.collection_config = Some(collection_config)
     }
+
+    #[inline(always)]
+    pub fn set_signature_mode(&mut self, signature_mode: SignatureMode) {
+        self.signature_mode = signature_mode
+    }
+}
+
+/// The SDK hashes assets the V1 leaf schema way, which is the only one supported by Bubblegum batch mints now.
+/// Returns [BatchMintError::UnsupportedLeafSchema] for any other leaf schema,
+/// so adding a new leaf schema to Bubblegum does not lead to silently wrong hashes.
+pub fn ensure_leaf_schema_v1(leaf_schema: &LeafSchema) -> std::result::Result<(), BatchMintError> {
+    match leaf_schema.version() {
+        Version::V1 => Ok(()),
+        #[allow(unreachable_patterns)]
+        version => Err(BatchMintError::UnsupportedLeafSchema(format!("{:?}", version))),
+    }
 }
 
 /// Verifies that received message was signed by pointed signer
@@ -265,16 +887,29 @@ pub struct MetadataArgsHash {
 
 impl MetadataArgsHash {
     /// Creates new MetadataArgsHash object
+    ///
+    /// Hashes are calculated the V1 leaf schema way, see [ensure_leaf_schema_v1].
     pub fn new(leaf_schema: &LeafSchema, tree: &Pubkey, metadata_args: &MetadataArgs) -> Self {
-        match leaf_schema {
-            LeafSchema::V1 {
-                id: _,
-                owner,
-                delegate,
-                nonce,
-                data_hash: _,
-                creator_hash: _,
-            } => hash_metadata_args(*nonce, tree, owner, delegate, metadata_args),
+        hash_metadata_args(
+            leaf_schema.nonce(),
+            tree,
+            &leaf_schema.owner(),
+            &leaf_schema.delegate(),
+            metadata_args,
+        )
+    }
+
+    /// Creates MetadataArgsHash object from the hashes already stored in the leaf schema,
+    /// without recomputing them from the asset metadata.
+    /// Useful for building a message to verify creator's signature when
+    /// the metadata args are not available, or may not match the leaf.
+    pub fn from_leaf(leaf_schema: &LeafSchema, tree: &Pubkey) -> Self {
+        MetadataArgsHash {
+            id: mpl_bubblegum::utils::get_asset_id(tree, leaf_schema.nonce()),
+            nonce: leaf_schema.nonce(),
+            data_hash: leaf_schema.data_hash(),
+            creator_hash: leaf_schema.creator_hash(),
+            hashed_leaf: leaf_schema.hash(),
         }
     }
 
@@ -306,9 +941,46 @@ impl MetadataArgsHash {
     pub fn get_asset_id(&self) -> Pubkey {
         self.id
     }
+
+    /// Returns asset leaf hash
+    pub fn get_hashed_leaf(&self) -> [u8; 32] {
+        self.hashed_leaf
+    }
+
+    /// Checks the leaf hash calculated by the SDK matches the one `mpl-bubblegum` calculates
+    /// for the given leaf schema, i.e. `LeafSchema::hash`.
+    /// A mismatch means the SDK hashing diverged from the bubblegum program one,
+    /// and the batch mint would be rejected by the DAS validation.
+    pub fn verify(&self, leaf_schema: &LeafSchema) -> bool {
+        self.hashed_leaf == leaf_schema.hash()
+    }
+}
+
+/// Algorithm (i.e. the preimage) used to calculate the leaf hash of an asset.
+///
+/// All the token standards currently supported by bubblegum use [LeafHashScheme::V1].
+/// When a new standard (or a metadata extension) requires a different preimage,
+/// a new variant is added here, and [LeafHashScheme::for_metadata] selects it for such assets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafHashScheme {
+    /// Hashing of the V1 leaf schema: keccak of borsh serialized [MetadataArgs] and of creators
+    V1,
+}
+
+impl LeafHashScheme {
+    /// Selects the hashing scheme for the asset by its token standard.
+    pub fn for_metadata(metadata_args: &MetadataArgs) -> Self {
+        match metadata_args.token_standard {
+            None
+            | Some(TokenStandard::NonFungible)
+            | Some(TokenStandard::FungibleAsset)
+            | Some(TokenStandard::Fungible)
+            | Some(TokenStandard::NonFungibleEdition) => LeafHashScheme::V1,
+        }
+    }
 }
 
-/// Hashes given merkle tree leaf asset.
+/// Hashes given merkle tree leaf asset with the scheme selected for it, see [LeafHashScheme].
 ///
 /// ## Arguments
 /// `nonce` - should be `batch_mint_builder.mints.len() as u64`
@@ -322,12 +994,25 @@ fn hash_metadata_args(
     owner: &Pubkey,
     delegate: &Pubkey,
     metadata_args: &MetadataArgs,
+) -> MetadataArgsHash {
+    match LeafHashScheme::for_metadata(metadata_args) {
+        LeafHashScheme::V1 => hash_metadata_args_v1(nonce, tree_account, owner, delegate, metadata_args),
+    }
+}
+
+/// [LeafHashScheme::V1] implementation of [hash_metadata_args].
+fn hash_metadata_args_v1(
+    nonce: u64,
+    tree_account: &Pubkey,
+    owner: &Pubkey,
+    delegate: &Pubkey,
+    metadata_args: &MetadataArgs,
 ) -> MetadataArgsHash {
     let id: Pubkey = mpl_bubblegum::utils::get_asset_id(tree_account, nonce);
 
-    let metadata_args_hash = keccak::hashv(&[metadata_args.try_to_vec().unwrap().as_slice()]);
-    let data_hash = keccak::hashv(&[
-        &metadata_args_hash.to_bytes(),
+    let metadata_args_hash = keccak_hashv(&[metadata_args.try_to_vec().unwrap().as_slice()]);
+    let data_hash = keccak_hashv(&[
+        &metadata_args_hash,
         &metadata_args.seller_fee_basis_points.to_le_bytes(),
     ]);
     let creator_data = metadata_args
@@ -335,7 +1020,7 @@ fn hash_metadata_args(
         .iter()
         .map(|c| [c.address.as_ref(), &[c.verified as u8], &[c.share]].concat())
         .collect::<Vec<_>>();
-    let creator_hash = keccak::hashv(
+    let creator_hash = keccak_hashv(
         creator_data
             .iter()
             .map(|c| c.as_slice())
@@ -343,7 +1028,7 @@ fn hash_metadata_args(
             .as_ref(),
     );
 
-    let hashed_leaf = keccak::hashv(&[
+    let hashed_leaf = keccak_hashv(&[
         &[1], // FIXME: What to specify here? self.version().to_bytes()?
         id.as_ref(),
         owner.as_ref(),
@@ -351,18 +1036,65 @@ fn hash_metadata_args(
         nonce.to_le_bytes().as_ref(),
         data_hash.as_ref(),
         creator_hash.as_ref(),
-    ])
-    .to_bytes();
+    ]);
 
     MetadataArgsHash {
         id,
         nonce,
-        data_hash: data_hash.to_bytes(),
-        creator_hash: creator_hash.to_bytes(),
+        data_hash,
+        creator_hash,
         hashed_leaf,
     }
 }
 
+/// Computes leaf hashes for the given assets the same way as if they were added
+/// by [BatchMintBuilder::add_asset] one by one, i.e. the nonce of an asset is its index in the slice.
+/// Useful when only leaf hashes are needed, without building the whole batch mint.
+///
+/// With the `rayon` feature enabled the hashes are computed in parallel.
+///
+/// ## Arguments
+/// `tree_id` - pubkey of the tree account the assets reside in
+/// `assets` - `(owner, delegate, metadata_args)` of each asset
+pub fn hash_leaves(tree_id: &Pubkey, assets: &[(Pubkey, Pubkey, MetadataArgs)]) -> Vec<[u8; 32]> {
+    #[cfg(feature = "rayon")]
+    let assets_iter = assets.par_iter().enumerate();
+    #[cfg(not(feature = "rayon"))]
+    let assets_iter = assets.iter().enumerate();
+
+    assets_iter
+        .map(|(nonce, (owner, delegate, metadata_args))| {
+            hash_metadata_args(nonce as u64, tree_id, owner, delegate, metadata_args).hashed_leaf
+        })
+        .collect()
+}
+
+/// Root of a tree of the given depth with the given leaves.
+#[cfg(any(debug_assertions, feature = "strict-checks"))]
+fn calc_root(leaves: &[[u8; 32]], max_depth: u32) -> [u8; 32] {
+    calc_level_nodes(leaves.to_vec(), max_depth)
+        .first()
+        .copied()
+        .unwrap_or_else(|| empty_node(max_depth))
+}
+
+/// Bisects for the first asset after which the root recorded by the merkle tree (`roots[i]`)
+/// differs from the one recomputed from `leaves[..=i]`.
+/// Expects the last root to be already known as a divergent one, and divergence to persist once it appears.
+#[cfg(any(debug_assertions, feature = "strict-checks"))]
+fn first_divergent_index(leaves: &[[u8; 32]], roots: &[[u8; 32]], max_depth: u32) -> usize {
+    let (mut low, mut high) = (0, roots.len() - 1);
+    while low < high {
+        let mid = (low + high) / 2;
+        if calc_root(&leaves[..=mid], max_depth) == roots[mid] {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
 /// Takes the changelog entry and constructs the path from the leaf (the asset,
 /// the changelog entry is created for) up to the root of the merkel tree.
 pub fn make_changelog_path(changelog: &dyn IChangeLog) -> Vec<spl_account_compression::state::PathNode> {
@@ -384,14 +1116,15 @@ pub fn make_changelog_path(changelog: &dyn IChangeLog) -> Vec<spl_account_compre
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::model::BatchMint;
+    use crate::model::{BatchMint, BatchMintDiff, BatchMintSession, MetadataArgsBuilder};
+    use solana_sdk::keccak;
     use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
     use std::{io::BufWriter, str::FromStr};
 
     #[test]
     fn test_create_empty_batch_mint() {
         // Creating batch mint builder
-        let builder = BatchMintBuilder::new(Pubkey::new_unique(), 10, 32, 0).unwrap();
+        let builder = BatchMintBuilder::new(Pubkey::new_unique(), (10, 32, 0)).unwrap();
 
         // converting into batch mint without adding any assets
         let batch_mint = builder.build_batch_mint().unwrap();
@@ -407,21 +1140,284 @@ mod test {
     }
 
     #[test]
-    fn test_canopy_depth_4_for_tree_depth_5() {
-        let owner = Pubkey::new_unique();
-        let delegate = Pubkey::new_unique();
+    fn test_asset_metadata_map_json() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (10, 32, 0)).unwrap();
+        // two assets sharing the same URI
+        let metadata_args = test_metadata_args(1, vec![]);
+        let asset_1 = batch_mint_builder
+            .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+            .unwrap()
+            .get_asset_id();
+        let asset_2 = batch_mint_builder
+            .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+            .unwrap()
+            .get_asset_id();
+
+        let mut batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+
+        // the map is not serialized when empty
+        let mut buffer = BufWriter::new(Vec::new());
+        batch_mint.write_as_json(&mut buffer).unwrap();
+        assert!(!String::from_utf8_lossy(buffer.buffer()).contains("asset_metadata_map"));
+        assert!(BatchMint::read_as_json(buffer.buffer())
+            .unwrap()
+            .asset_metadata_map
+            .is_empty());
+
+        batch_mint.asset_metadata_map.insert(
+            asset_1,
+            serde_json::value::RawValue::from_string(r#"{"name":"1","edition":1}"#.to_string()).unwrap(),
+        );
+        batch_mint.asset_metadata_map.insert(
+            asset_2,
+            serde_json::value::RawValue::from_string(r#"{"name":"1","edition":2}"#.to_string()).unwrap(),
+        );
 
-        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), 5, 8, 4).unwrap();
+        let mut buffer = BufWriter::new(Vec::new());
+        batch_mint.write_as_json(&mut buffer).unwrap();
+        let restored_batch_mint = BatchMint::read_as_json(buffer.buffer()).unwrap();
 
-        for i in 1u8..=32 {
-            let ma = test_metadata_args(i, vec![]);
-            batch_mint_builder.add_asset(&owner, &delegate, &ma).unwrap();
-        }
+        assert_eq!(restored_batch_mint.asset_metadata_map.len(), 2);
+        assert_eq!(
+            restored_batch_mint.asset_metadata_map[&asset_2].get(),
+            r#"{"name":"1","edition":2}"#
+        );
+    }
 
-        let canopy_4 = &batch_mint_builder.canopy_leaves;
-        assert_eq!(canopy_4.len(), 16);
+    #[test]
+    fn test_metadata_args_builder() {
+        assert_eq!(
+            MetadataArgsBuilder::new("1", "symbol-1", "https://immutable-storage/asset/1").build(),
+            test_metadata_args(1, vec![])
+        );
 
-        let leaf_1_hash = hash_metadata_args(
+        let creators = vec![Creator {
+            address: Pubkey::new_unique(),
+            verified: false,
+            share: 100,
+        }];
+        let metadata_args = MetadataArgsBuilder::new("1", "symbol-1", "https://immutable-storage/asset/1")
+            .with_seller_fee_basis_points(500)
+            .with_is_mutable(true)
+            .with_creators(creators.clone())
+            .build();
+        assert_eq!(metadata_args.seller_fee_basis_points, 500);
+        assert!(metadata_args.is_mutable);
+        assert_eq!(metadata_args.creators, creators);
+        assert_eq!(
+            metadata_args.token_standard,
+            Some(mpl_bubblegum::types::TokenStandard::NonFungible)
+        );
+    }
+
+    #[test]
+    fn test_sealed_builder() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        batch_mint_builder
+            .add_asset(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &test_metadata_args(1, vec![]),
+            )
+            .unwrap();
+        assert!(!batch_mint_builder.is_sealed());
+
+        batch_mint_builder.seal();
+        assert!(batch_mint_builder.is_sealed());
+        match batch_mint_builder.add_asset(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &test_metadata_args(2, vec![]),
+        ) {
+            Err(BatchMintError::BuilderSealed { tree_account }) => {
+                assert_eq!(tree_account, batch_mint_builder.tree_account)
+            }
+            r => panic!("Unexpected result: {:?}", r.map(|h| h.get_nonce())),
+        }
+        assert_eq!(batch_mint_builder.mints.len(), 1);
+        batch_mint_builder.build_batch_mint().unwrap();
+    }
+
+    #[test]
+    fn test_remaining_accounts() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 3)).unwrap();
+        for i in 1u8..=5 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+
+        let remaining_accounts = batch_mint_builder.remaining_accounts();
+        assert_eq!(remaining_accounts.len(), 2);
+        for (account, proof) in remaining_accounts
+            .iter()
+            .zip(batch_mint_builder.trimmed_rightmost_proof())
+        {
+            assert_eq!(account.pubkey.to_bytes(), *proof);
+            assert!(!account.is_signer && !account.is_writable);
+        }
+
+        let overridden = vec![AccountMeta::new_readonly(Pubkey::new_unique(), false)];
+        batch_mint_builder.override_remaining_accounts(Some(overridden.clone()));
+        assert_eq!(batch_mint_builder.remaining_accounts(), overridden);
+
+        batch_mint_builder.override_remaining_accounts(None);
+        assert_eq!(batch_mint_builder.remaining_accounts(), remaining_accounts);
+    }
+
+    #[test]
+    fn test_trimmed_rightmost_proof() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 3)).unwrap();
+        for i in 1u8..=5 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+        let proof = batch_mint_builder.merkle.get_rightmost_proof().to_vec();
+        assert_eq!(batch_mint_builder.trimmed_rightmost_proof(), &proof[..2]);
+
+        let batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        assert_eq!(batch_mint_builder.trimmed_rightmost_proof().len(), 5);
+    }
+
+    #[test]
+    fn test_finalize_args() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        assert_eq!(batch_mint_builder.finalize_args("url", "hash").rightmost_index, 0);
+
+        for i in 1u8..=5 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+        let batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+        assert_eq!(
+            batch_mint_builder.finalize_args("url", "hash"),
+            FinalizeArgs {
+                root: batch_mint.merkle_root,
+                rightmost_leaf: batch_mint.last_leaf_hash,
+                rightmost_index: 4,
+                metadata_url: "url".to_string(),
+                metadata_hash: "hash".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_tree_params() {
+        let builder = BatchMintBuilder::new(Pubkey::new_unique(), (10, 32, 3)).unwrap();
+        assert_eq!(
+            builder.tree_params(),
+            TreeParams {
+                max_depth: 10,
+                max_buffer_size: 32,
+                canopy_depth: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_remaining_capacity() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        assert_eq!(batch_mint_builder.remaining_capacity(), 32);
+
+        for i in 1u8..=32 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+            assert_eq!(batch_mint_builder.remaining_capacity(), 32 - i as u64);
+        }
+
+        // an asset waiting for the preceding nonces occupies its leaf too
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        batch_mint_builder
+            .add_asset_at_nonce(
+                5,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &test_metadata_args(1, vec![]),
+            )
+            .unwrap();
+        assert_eq!(batch_mint_builder.remaining_capacity(), 31);
+    }
+
+    #[test]
+    fn test_add_asset_with_wrong_verified_collection() {
+        let collection_mint = Pubkey::new_unique();
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        batch_mint_builder.setup_collection_config(CollectionConfig {
+            collection_authority: Keypair::new().into(),
+            collection_authority_record_pda: None,
+            collection_mint,
+            collection_metadata: Pubkey::new_unique(),
+            edition_account: Pubkey::new_unique(),
+        });
+
+        let mut metadata_args = test_metadata_args(1, vec![]);
+        let other_collection = Pubkey::new_unique();
+        metadata_args.collection = Some(mpl_bubblegum::types::Collection {
+            verified: true,
+            key: other_collection,
+        });
+        match batch_mint_builder.add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args) {
+            Err(BatchMintError::CollectionKeyMismatch { expected, actual }) => {
+                assert_eq!(expected, collection_mint);
+                assert_eq!(actual, other_collection);
+            }
+            r => panic!("Unexpected result: {:?}", r.map(|h| h.get_nonce())),
+        }
+        assert!(batch_mint_builder.mints.is_empty());
+
+        // unverified collection is not checked
+        metadata_args.collection = Some(mpl_bubblegum::types::Collection {
+            verified: false,
+            key: other_collection,
+        });
+        batch_mint_builder
+            .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+            .unwrap();
+
+        metadata_args.collection = Some(mpl_bubblegum::types::Collection {
+            verified: true,
+            key: collection_mint,
+        });
+        batch_mint_builder
+            .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_canopy_depth_4_for_tree_depth_5() {
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 4)).unwrap();
+
+        for i in 1u8..=32 {
+            let ma = test_metadata_args(i, vec![]);
+            batch_mint_builder.add_asset(&owner, &delegate, &ma).unwrap();
+        }
+
+        let canopy_4 = &batch_mint_builder.canopy_leaves;
+        assert_eq!(canopy_4.len(), 16);
+
+        let leaf_1_hash = hash_metadata_args(
             0,
             &batch_mint_builder.tree_account,
             &owner,
@@ -458,12 +1454,407 @@ mod test {
         assert_eq!(canopy_4[15], keccak::hashv(&[&leaf_31_hash, &leaf_32_hash]).to_bytes());
     }
 
+    #[test]
+    fn test_validate_canopy() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 3)).unwrap();
+        batch_mint_builder.validate_canopy().unwrap();
+
+        // each canopy leaf node covers 4 assets
+        for i in 1u8..=17 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+            batch_mint_builder.validate_canopy().unwrap();
+        }
+        assert_eq!(batch_mint_builder.canopy_leaves.len(), 5);
+
+        batch_mint_builder.canopy_leaves[2] = [0; 32];
+        match batch_mint_builder.validate_canopy() {
+            Err(BatchMintError::EmptyCanopyLeaf { index }) => assert_eq!(index, 2),
+            _ => panic!("Empty canopy leaf is not detected"),
+        }
+
+        batch_mint_builder.canopy_leaves.pop();
+        match batch_mint_builder.validate_canopy() {
+            Err(BatchMintError::CanopyLeavesCountMismatch { expected, actual }) => {
+                assert_eq!(expected, 5);
+                assert_eq!(actual, 4);
+            }
+            _ => panic!("Canopy leaves count mismatch is not detected"),
+        }
+    }
+
+    #[test]
+    fn test_canopy_root() {
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        let batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        assert_eq!(batch_mint_builder.canopy_root(), None);
+
+        for (canopy_depth, assets) in [(3, 0u8), (3, 17), (4, 32), (1, 1)] {
+            let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, canopy_depth)).unwrap();
+            for i in 1u8..=assets {
+                batch_mint_builder
+                    .add_asset(&owner, &delegate, &test_metadata_args(i, vec![]))
+                    .unwrap();
+            }
+            assert_eq!(
+                batch_mint_builder.canopy_root(),
+                Some(batch_mint_builder.merkle.get_root())
+            );
+        }
+    }
+
+    #[test]
+    fn test_rebuild_canopy() {
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        for (canopy_depth, assets) in [(3, 17u8), (4, 32), (1, 1)] {
+            let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, canopy_depth)).unwrap();
+            for i in 1u8..=assets {
+                batch_mint_builder
+                    .add_asset(&owner, &delegate, &test_metadata_args(i, vec![]))
+                    .unwrap();
+            }
+
+            let batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+            assert_eq!(
+                batch_mint.rebuild_canopy(canopy_depth),
+                batch_mint_builder.canopy_leaves
+            );
+        }
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let tree_account = Pubkey::new_unique();
+        let assets = (1u8..=20)
+            .map(|i| {
+                (
+                    Pubkey::new_unique(),
+                    Pubkey::new_unique(),
+                    test_metadata_args(i, vec![]),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut batch_mint_builder = BatchMintBuilder::new(tree_account, (5, 8, 3)).unwrap();
+        for (owner, delegate, metadata_args) in &assets[..12] {
+            batch_mint_builder.add_asset(owner, delegate, metadata_args).unwrap();
+        }
+        let mut checkpoint = Vec::new();
+        batch_mint_builder.checkpoint(&mut checkpoint).unwrap();
+
+        // resumed build re-adds the same assets
+        let mut resumed_builder = BatchMintBuilder::new(tree_account, (5, 8, 3)).unwrap();
+        for (owner, delegate, metadata_args) in &assets[..11] {
+            resumed_builder.add_asset(owner, delegate, metadata_args).unwrap();
+        }
+        match resumed_builder.resume_from_checkpoint(checkpoint.as_slice()) {
+            Err(BatchMintError::CheckpointMismatch { field }) => assert_eq!(field, "count"),
+            _ => panic!("Checkpoint mismatch is not detected"),
+        }
+
+        let (owner, delegate, metadata_args) = &assets[11];
+        resumed_builder.add_asset(owner, delegate, metadata_args).unwrap();
+        resumed_builder.resume_from_checkpoint(checkpoint.as_slice()).unwrap();
+
+        // same number of assets, but a different one
+        let mut diverged_builder = BatchMintBuilder::new(tree_account, (5, 8, 3)).unwrap();
+        for (owner, delegate, metadata_args) in &assets[1..13] {
+            diverged_builder.add_asset(owner, delegate, metadata_args).unwrap();
+        }
+        match diverged_builder.resume_from_checkpoint(checkpoint.as_slice()) {
+            Err(BatchMintError::CheckpointMismatch { field }) => assert_eq!(field, "root"),
+            _ => panic!("Checkpoint mismatch is not detected"),
+        }
+    }
+
+    #[test]
+    fn test_build_batch_mint_with_hash() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for i in 0u8..3 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+
+        let (document, document_hash) = batch_mint_builder.build_batch_mint_with_hash().unwrap();
+        // the hash is the one of the returned bytes, that are the JSON of the batch mint
+        assert_eq!(document_hash, metadata_hash(&document));
+        let mut expected_document = Vec::new();
+        let batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+        batch_mint.write_as_json(&mut expected_document).unwrap();
+        assert_eq!(document, expected_document);
+        assert_eq!(BatchMint::read_as_json(document.as_slice()).unwrap(), batch_mint);
+
+        assert_eq!(
+            batch_mint_builder.build_batch_mint_with_hash().unwrap(),
+            (document, document_hash)
+        );
+    }
+
+    #[test]
+    fn test_batch_mint_diff() {
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let tree_account = Pubkey::new_unique();
+
+        let mut old_builder = BatchMintBuilder::new(tree_account, (5, 8, 0)).unwrap();
+        for i in 0u8..5 {
+            old_builder
+                .add_asset(&owner, &delegate, &test_metadata_args(i, vec![]))
+                .unwrap();
+        }
+        let old_batch_mint = old_builder.build_batch_mint().unwrap();
+        assert!(old_batch_mint.diff(&old_batch_mint).is_empty());
+
+        // asset 1 gets new metadata, asset 3 a new owner, asset 5 is added
+        let mut new_builder = BatchMintBuilder::new(tree_account, (5, 8, 0)).unwrap();
+        for i in 0u8..6 {
+            let metadata_args = test_metadata_args(if i == 1 { 100 } else { i }, vec![]);
+            let asset_owner = if i == 3 { Pubkey::new_unique() } else { owner };
+            new_builder.add_asset(&asset_owner, &delegate, &metadata_args).unwrap();
+        }
+        let new_batch_mint = new_builder.build_batch_mint().unwrap();
+
+        let diff = old_batch_mint.diff(&new_batch_mint);
+        assert_eq!(
+            diff,
+            BatchMintDiff {
+                added: vec![5],
+                removed: vec![],
+                changed: vec![1, 3],
+            }
+        );
+
+        let reverse_diff = new_batch_mint.diff(&old_batch_mint);
+        assert_eq!(reverse_diff.added, Vec::<u64>::new());
+        assert_eq!(reverse_diff.removed, vec![5]);
+        assert_eq!(reverse_diff.changed, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_add_assets() {
+        let tree_account = Pubkey::new_unique();
+        let items = (0u8..20)
+            .map(|i| {
+                (
+                    Pubkey::new_unique(),
+                    Pubkey::new_unique(),
+                    test_metadata_args(i, vec![]),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut loop_builder = BatchMintBuilder::new(tree_account, (5, 8, 3)).unwrap();
+        let loop_hashes = items
+            .iter()
+            .map(|(owner, delegate, metadata_args)| loop_builder.add_asset(owner, delegate, metadata_args).unwrap())
+            .collect::<Vec<_>>();
+
+        // added in two portions, the first one ends in the middle of a canopy subtree
+        let mut builder = BatchMintBuilder::new(tree_account, (5, 8, 3)).unwrap();
+        let mut hashes = builder.add_assets(&items[..6]).unwrap();
+        hashes.extend(builder.add_assets(&items[6..]).unwrap());
+
+        assert_eq!(
+            hashes.iter().map(|h| h.get_hashed_leaf()).collect::<Vec<_>>(),
+            loop_hashes.iter().map(|h| h.get_hashed_leaf()).collect::<Vec<_>>()
+        );
+        assert_eq!(builder.canopy_leaves, loop_builder.canopy_leaves);
+        assert_eq!(
+            builder.build_batch_mint().unwrap(),
+            loop_builder.build_batch_mint().unwrap()
+        );
+
+        // the whole portion is rejected if it does not fit
+        assert!(matches!(
+            builder.add_assets(&items),
+            Err(BatchMintError::IllegalArgumets(_))
+        ));
+        assert_eq!(builder.mints.len(), 20);
+    }
+
+    #[test]
+    fn test_add_asset_at_nonce() {
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let tree_account = Pubkey::new_unique();
+
+        let mut sequential_builder = BatchMintBuilder::new(tree_account, (5, 8, 3)).unwrap();
+        for i in 0u8..4 {
+            sequential_builder
+                .add_asset(&owner, &delegate, &test_metadata_args(i, vec![]))
+                .unwrap();
+        }
+
+        // assets added in the reveal order 2, 0, 3, 1
+        let mut builder = BatchMintBuilder::new(tree_account, (5, 8, 3)).unwrap();
+        for i in [2u8, 0, 3] {
+            let metadata_args_hash = builder
+                .add_asset_at_nonce(i as u64, &owner, &delegate, &test_metadata_args(i, vec![]))
+                .unwrap();
+            assert_eq!(metadata_args_hash.get_nonce(), i as u64);
+        }
+        assert!(matches!(
+            builder.add_asset_at_nonce(0, &owner, &delegate, &test_metadata_args(0, vec![])),
+            Err(BatchMintError::NonceAlreadyUsed { nonce: 0 })
+        ));
+        assert!(matches!(
+            builder.add_asset_at_nonce(2, &owner, &delegate, &test_metadata_args(2, vec![])),
+            Err(BatchMintError::NonceAlreadyUsed { nonce: 2 })
+        ));
+        assert!(matches!(
+            builder.add_asset_at_nonce(32, &owner, &delegate, &test_metadata_args(32, vec![])),
+            Err(BatchMintError::IllegalArgumets(_))
+        ));
+        assert!(matches!(
+            builder.build_batch_mint(),
+            Err(BatchMintError::NonceGap { nonce: 1 })
+        ));
+
+        // filling the gap appends the pending assets
+        builder
+            .add_asset_at_nonce(1, &owner, &delegate, &test_metadata_args(1, vec![]))
+            .unwrap();
+        assert_eq!(builder.mints.len(), 4);
+        assert_eq!(builder.merkle.get_root(), sequential_builder.merkle.get_root());
+        assert_eq!(builder.canopy_leaves, sequential_builder.canopy_leaves);
+        assert_eq!(
+            builder.build_batch_mint().unwrap(),
+            sequential_builder.build_batch_mint().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_finalize_args_rightmost_index_with_pending_assets() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for i in 1u8..=5 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+        batch_mint_builder
+            .add_asset_at_nonce(
+                10,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &test_metadata_args(10, vec![]),
+            )
+            .unwrap();
+        assert_eq!(batch_mint_builder.finalize_args("url", "hash").rightmost_index, 4);
+
+        // the pending asset is appended as soon as the gap is filled
+        for i in 5u8..10 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+        assert_eq!(batch_mint_builder.finalize_args("url", "hash").rightmost_index, 10);
+    }
+
+    #[test]
+    fn test_verify_external_proof() {
+        use crate::merkle_tree_wrapper::calc_level_nodes;
+        use spl_concurrent_merkle_tree::node::empty_node;
+
+        const DEPTH: u32 = 5;
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (DEPTH, 8, 0)).unwrap();
+        for i in 1u8..=11 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+        let batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+        let leaves = batch_mint
+            .batch_mints
+            .iter()
+            .map(|batch_mint| batch_mint.leaf_update.hash())
+            .collect::<Vec<_>>();
+
+        // the proof the same way DAS builds it: sibling nodes from the leaf level up to the root
+        let make_proof = |index: u32| {
+            (0..DEPTH)
+                .map(|level| {
+                    let sibling = ((index >> level) ^ 1) as usize;
+                    calc_level_nodes(leaves.clone(), level)
+                        .get(sibling)
+                        .copied()
+                        .unwrap_or_else(|| empty_node(level))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for index in [0u32, 5, 10] {
+            let asset = &batch_mint.batch_mints[index as usize].leaf_update;
+            let proof = make_proof(index);
+            assert!(batch_mint.verify_external_proof(asset.id(), asset.hash(), &proof, index));
+
+            // wrong leaf
+            assert!(!batch_mint.verify_external_proof(asset.id(), [1; 32], &proof, index));
+            // proof of another asset
+            assert!(!batch_mint.verify_external_proof(asset.id(), asset.hash(), &make_proof(index + 1), index));
+            // asset ID does not match the index
+            let other_asset = &batch_mint.batch_mints[index as usize + 1].leaf_update;
+            assert!(!batch_mint.verify_external_proof(other_asset.id(), asset.hash(), &proof, index));
+            // trimmed proof
+            assert!(!batch_mint.verify_external_proof(asset.id(), asset.hash(), &proof[..4], index));
+        }
+    }
+
+    #[test]
+    fn test_to_das_ingestion() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for i in 1u8..=3 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+        let batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+
+        let payload = batch_mint.to_das_ingestion();
+        assert_eq!(payload.schema_version, crate::model::DAS_INGESTION_SCHEMA_VERSION);
+        assert_eq!(payload.tree_id, batch_mint.tree_id);
+        assert_eq!(payload.merkle_root, batch_mint.merkle_root);
+        assert_eq!(payload.instructions.len(), 3);
+        for (index, instruction) in payload.instructions.iter().enumerate() {
+            assert_eq!(instruction.tree_update.as_ref().unwrap().index, index as u32);
+        }
+    }
+
     #[test]
     fn test_get_canopy_on_patially_filled_tree() {
         let owner = Pubkey::new_unique();
         let delegate = Pubkey::new_unique();
 
-        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), 5, 8, 4).unwrap();
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 4)).unwrap();
 
         for i in 1u8..=((1u8 << 5) / 2) {
             let ma = test_metadata_args(i, vec![]);
@@ -512,6 +1903,164 @@ mod test {
         assert_eq!(nonce_from_message, nonce);
     }
 
+    #[test]
+    fn test_metadata_arg_hash_verify() {
+        let tree_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let creators = vec![
+            Creator {
+                address: Pubkey::new_unique(),
+                verified: true,
+                share: 40,
+            },
+            Creator {
+                address: Pubkey::new_unique(),
+                verified: false,
+                share: 60,
+            },
+        ];
+
+        let mut collection_metadata = test_metadata_args(3, vec![]);
+        collection_metadata.collection = Some(mpl_bubblegum::types::Collection {
+            verified: false,
+            key: Pubkey::new_unique(),
+        });
+        let mut mutable_metadata = test_metadata_args(4, creators.clone());
+        mutable_metadata.is_mutable = true;
+        mutable_metadata.seller_fee_basis_points = 500;
+        mutable_metadata.primary_sale_happened = true;
+        let mut no_token_standard_metadata = test_metadata_args(5, vec![]);
+        no_token_standard_metadata.token_standard = None;
+
+        let metadata_configurations = [
+            test_metadata_args(1, vec![]),
+            test_metadata_args(2, creators),
+            collection_metadata,
+            mutable_metadata,
+            no_token_standard_metadata,
+        ];
+
+        let mut batch_mint_builder = BatchMintBuilder::new(tree_account, (5, 8, 0)).unwrap();
+        for metadata_args in metadata_configurations.iter() {
+            batch_mint_builder.add_asset(&owner, &delegate, metadata_args).unwrap();
+        }
+
+        for batch_mint in batch_mint_builder.mints.values() {
+            let metadata_args_hash =
+                MetadataArgsHash::new(&batch_mint.leaf_update, &tree_account, &batch_mint.mint_args);
+            assert!(metadata_args_hash.verify(&batch_mint.leaf_update));
+            assert_eq!(metadata_args_hash.get_hashed_leaf(), batch_mint.leaf_update.hash());
+        }
+
+        // a leaf with a different data hash doesn't match
+        let batch_mint = batch_mint_builder.mints.get(&0).unwrap();
+        let metadata_args_hash = MetadataArgsHash::new(&batch_mint.leaf_update, &tree_account, &batch_mint.mint_args);
+        let other_leaf = LeafSchema::V1 {
+            id: batch_mint.leaf_update.id(),
+            owner,
+            delegate,
+            nonce: 0,
+            data_hash: [0; 32],
+            creator_hash: batch_mint.leaf_update.creator_hash(),
+        };
+        assert!(!metadata_args_hash.verify(&other_leaf));
+    }
+
+    #[test]
+    fn test_metadata_arg_hash_from_leaf() {
+        let tree_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        let mut batch_mint_builder = BatchMintBuilder::new(tree_account, (5, 8, 0)).unwrap();
+        for i in 1u8..=3 {
+            batch_mint_builder
+                .add_asset(&owner, &delegate, &test_metadata_args(i, vec![]))
+                .unwrap();
+        }
+
+        for batch_mint in batch_mint_builder.mints.values() {
+            let from_args = MetadataArgsHash::new(&batch_mint.leaf_update, &tree_account, &batch_mint.mint_args);
+            let from_leaf = MetadataArgsHash::from_leaf(&batch_mint.leaf_update, &tree_account);
+
+            assert_eq!(from_leaf.get_message(), from_args.get_message());
+            assert_eq!(from_leaf.get_asset_id(), from_args.get_asset_id());
+            assert_eq!(from_leaf.get_nonce(), from_args.get_nonce());
+        }
+    }
+
+    #[test]
+    fn test_restore_batch_mint_builder() {
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 3)).unwrap();
+        for i in 1u8..=10 {
+            batch_mint_builder
+                .add_asset(&owner, &delegate, &test_metadata_args(i, vec![]))
+                .unwrap();
+        }
+        let mut batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+
+        let restored = BatchMintBuilder::restore(&batch_mint, (5, 8, 3)).unwrap();
+        assert_eq!(restored.merkle.get_root(), batch_mint.merkle_root);
+        assert_eq!(restored.canopy_leaves, batch_mint_builder.canopy_leaves);
+
+        // stored data hash does not match the metadata of the asset
+        let corrupt_leaf = &mut batch_mint.batch_mints[4].leaf_update;
+        *corrupt_leaf = LeafSchema::V1 {
+            id: corrupt_leaf.id(),
+            owner: corrupt_leaf.owner(),
+            delegate: corrupt_leaf.delegate(),
+            nonce: corrupt_leaf.nonce(),
+            data_hash: [1; 32],
+            creator_hash: corrupt_leaf.creator_hash(),
+        };
+
+        match BatchMintBuilder::restore(&batch_mint, (5, 8, 3)) {
+            Err(BatchMintError::CorruptBatchMint { nonce }) => assert_eq!(nonce, 4),
+            _ => panic!("Corrupt batch mint is not detected"),
+        }
+    }
+
+    #[test]
+    fn test_restore_with_mismatched_geometry() {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for i in 1u8..=3 {
+            batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, vec![]),
+                )
+                .unwrap();
+        }
+        let mut buffer = BufWriter::new(Vec::new());
+        batch_mint_builder
+            .build_batch_mint()
+            .unwrap()
+            .write_as_json(&mut buffer)
+            .unwrap();
+        let batch_mint = BatchMint::read_as_json(buffer.buffer()).unwrap();
+
+        // JSON of a tree with different geometry
+        for (max_depth, max_buffer_size) in [(14, 8), (5, 16)] {
+            match BatchMintBuilder::restore(&batch_mint, (max_depth, max_buffer_size, 0)) {
+                Err(BatchMintError::GeometryMismatch {
+                    max_depth: 5,
+                    max_buffer_size: 8,
+                    onchain_max_depth,
+                    onchain_max_buffer_size,
+                }) => {
+                    assert_eq!(onchain_max_depth, max_depth);
+                    assert_eq!(onchain_max_buffer_size, max_buffer_size);
+                }
+                _ => panic!("Geometry mismatch is not detected"),
+            }
+        }
+    }
+
     #[test]
     fn test_verify_one_creator() {
         let tree_account = Pubkey::new_unique();
@@ -528,7 +2077,7 @@ mod test {
 
         let metadata_args = test_metadata_args(1u8, asset_creators.clone());
 
-        let mut batch_mint_builder = BatchMintBuilder::new(tree_account, 5, 8, 4).unwrap();
+        let mut batch_mint_builder = BatchMintBuilder::new(tree_account, (5, 8, 4)).unwrap();
 
         let metadata_arg_hash = batch_mint_builder.add_asset(&owner, &delegate, &metadata_args).unwrap();
 
@@ -630,6 +2179,340 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_lenient_signature_mode() {
+        let verified_creator = Keypair::new();
+        let unverified_creator = Keypair::new();
+
+        let asset_creators = vec![
+            Creator {
+                address: verified_creator.pubkey(),
+                verified: true,
+                share: 50,
+            },
+            Creator {
+                address: unverified_creator.pubkey(),
+                verified: false,
+                share: 50,
+            },
+        ];
+
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        assert_eq!(batch_mint_builder.signature_mode, SignatureMode::Strict);
+        batch_mint_builder.set_signature_mode(SignatureMode::Lenient);
+
+        let metadata_hash = batch_mint_builder
+            .add_asset(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &test_metadata_args(1u8, asset_creators),
+            )
+            .unwrap();
+
+        let mut creators_signatures = HashMap::new();
+        creators_signatures.insert(
+            verified_creator.pubkey(),
+            verified_creator.sign_message(&metadata_hash.get_message()),
+        );
+        creators_signatures.insert(
+            unverified_creator.pubkey(),
+            unverified_creator.sign_message(&metadata_hash.get_message()),
+        );
+
+        let mut message_and_signatures = HashMap::new();
+        message_and_signatures.insert(metadata_hash.get_nonce(), creators_signatures);
+
+        batch_mint_builder
+            .add_signatures_for_verified_creators(message_and_signatures)
+            .unwrap();
+
+        // signature of the unverified creator is dropped
+        let saved_signatures = batch_mint_builder.mints[&metadata_hash.get_nonce()]
+            .creator_signature
+            .clone()
+            .unwrap();
+        assert_eq!(saved_signatures.len(), 1);
+        assert!(saved_signatures.contains_key(&verified_creator.pubkey()));
+
+        batch_mint_builder.build_batch_mint().unwrap();
+    }
+
+    #[test]
+    fn test_new_with_default_buffer() {
+        let batch_mint_builder = BatchMintBuilder::new_with_default_buffer(Pubkey::new_unique(), 14, 3).unwrap();
+        assert_eq!(
+            (
+                batch_mint_builder.max_depth,
+                batch_mint_builder.max_buffer_size,
+                batch_mint_builder.canopy_depth
+            ),
+            (14, 64, 3)
+        );
+
+        assert!(matches!(
+            BatchMintBuilder::new_with_default_buffer(Pubkey::new_unique(), 4, 0),
+            Err(BatchMintError::IllegalArgumets(_))
+        ));
+    }
+
+    #[test]
+    fn test_distinct_owners_and_delegates() {
+        let owners = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let delegate = Pubkey::new_unique();
+
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        assert!(batch_mint_builder.distinct_owners().is_empty());
+
+        for i in 0u8..5 {
+            batch_mint_builder
+                .add_asset(&owners[i as usize % 2], &delegate, &test_metadata_args(i, vec![]))
+                .unwrap();
+        }
+
+        assert_eq!(batch_mint_builder.distinct_owners(), HashSet::from(owners));
+        assert_eq!(batch_mint_builder.distinct_delegates(), HashSet::from([delegate]));
+    }
+
+    #[test]
+    fn test_outstanding_by_creator() {
+        let creator_1 = Keypair::new();
+        let creator_2 = Keypair::new();
+
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+
+        let creators = |verified_2: bool| {
+            vec![
+                Creator {
+                    address: creator_1.pubkey(),
+                    verified: true,
+                    share: 50,
+                },
+                Creator {
+                    address: creator_2.pubkey(),
+                    verified: verified_2,
+                    share: 50,
+                },
+            ]
+        };
+
+        let mut hashes = Vec::new();
+        for i in 0u8..3 {
+            hashes.push(
+                batch_mint_builder
+                    .add_asset(
+                        &Pubkey::new_unique(),
+                        &Pubkey::new_unique(),
+                        &test_metadata_args(i, creators(i != 1)),
+                    )
+                    .unwrap(),
+            );
+        }
+        // asset without creators does not require any signatures
+        batch_mint_builder
+            .add_asset(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &test_metadata_args(3, vec![]),
+            )
+            .unwrap();
+
+        let outstanding = batch_mint_builder.outstanding_by_creator();
+        assert_eq!(outstanding.len(), 2);
+        assert_eq!(outstanding[&creator_1.pubkey()], vec![0, 1, 2]);
+        assert_eq!(outstanding[&creator_2.pubkey()], vec![0, 2]);
+
+        // creator 1 signs all the assets, creator 2 signs only the first one
+        let mut message_and_signatures = HashMap::new();
+        for hash in &hashes {
+            let mut creators_signatures = HashMap::new();
+            creators_signatures.insert(creator_1.pubkey(), creator_1.sign_message(&hash.get_message()));
+            if hash.get_nonce() == 0 {
+                creators_signatures.insert(creator_2.pubkey(), creator_2.sign_message(&hash.get_message()));
+            }
+            message_and_signatures.insert(hash.get_nonce(), creators_signatures);
+        }
+        batch_mint_builder
+            .add_signatures_for_verified_creators(message_and_signatures)
+            .unwrap();
+
+        let outstanding = batch_mint_builder.outstanding_by_creator();
+        assert_eq!(outstanding.len(), 1);
+        assert_eq!(outstanding[&creator_2.pubkey()], vec![2]);
+        assert!(matches!(
+            batch_mint_builder.build_batch_mint(),
+            Err(BatchMintError::MissedSignatureFromCreator(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_batch_mint_checked() {
+        let creator_1 = Keypair::new();
+        let creator_2 = Keypair::new();
+        let creators = vec![
+            Creator {
+                address: creator_1.pubkey(),
+                verified: true,
+                share: 50,
+            },
+            Creator {
+                address: creator_2.pubkey(),
+                verified: true,
+                share: 50,
+            },
+        ];
+        let collection = Pubkey::new_unique();
+
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        // no signatures at all
+        let unsigned = batch_mint_builder
+            .add_asset(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &test_metadata_args(0, creators.clone()),
+            )
+            .unwrap();
+        // complete asset
+        batch_mint_builder
+            .add_asset(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &test_metadata_args(1, vec![]),
+            )
+            .unwrap();
+        // signed only by the first creator, and has a verified collection without collection config
+        let mut metadata_args = test_metadata_args(2, creators);
+        metadata_args.collection = Some(mpl_bubblegum::types::Collection {
+            verified: true,
+            key: collection,
+        });
+        let partially_signed = batch_mint_builder
+            .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+            .unwrap();
+        batch_mint_builder
+            .add_signatures_for_verified_creators(HashMap::from([(
+                partially_signed.get_nonce(),
+                HashMap::from([(
+                    creator_1.pubkey(),
+                    creator_1.sign_message(&partially_signed.get_message()),
+                )]),
+            )]))
+            .unwrap();
+
+        let errors = batch_mint_builder.build_batch_mint_checked().err().unwrap();
+        let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                BatchMintError::MissedSignaturesForAsset(unsigned.get_asset_id().to_string()).to_string(),
+                BatchMintError::MissedSignatureFromCreator(creator_2.pubkey().to_string()).to_string(),
+                BatchMintError::MissingCollectionSignature(collection.to_string()).to_string(),
+            ]
+        );
+        // the first error is the one build_batch_mint returns
+        assert_eq!(
+            batch_mint_builder.build_batch_mint().err().unwrap().to_string(),
+            errors[0]
+        );
+
+        batch_mint_builder
+            .add_signatures_for_verified_creators(HashMap::from([
+                (
+                    unsigned.get_nonce(),
+                    HashMap::from([
+                        (creator_1.pubkey(), creator_1.sign_message(&unsigned.get_message())),
+                        (creator_2.pubkey(), creator_2.sign_message(&unsigned.get_message())),
+                    ]),
+                ),
+                (
+                    partially_signed.get_nonce(),
+                    HashMap::from([(
+                        creator_2.pubkey(),
+                        creator_2.sign_message(&partially_signed.get_message()),
+                    )]),
+                ),
+            ]))
+            .unwrap();
+        batch_mint_builder.setup_collection_config(CollectionConfig {
+            collection_authority: Pubkey::new_unique().into(),
+            collection_authority_record_pda: None,
+            collection_mint: collection,
+            collection_metadata: Pubkey::new_unique(),
+            edition_account: Pubkey::new_unique(),
+        });
+        let batch_mint = batch_mint_builder.build_batch_mint_checked().ok().unwrap();
+        assert_eq!(batch_mint.batch_mints.len(), 3);
+    }
+
+    #[test]
+    fn test_save_and_load_session() {
+        let creator_1 = Keypair::new();
+        let creator_2 = Keypair::new();
+        let asset_creators = vec![
+            Creator {
+                address: creator_1.pubkey(),
+                verified: true,
+                share: 50,
+            },
+            Creator {
+                address: creator_2.pubkey(),
+                verified: true,
+                share: 50,
+            },
+        ];
+
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 3)).unwrap();
+        let mut message_and_signatures = HashMap::new();
+        for i in 1u8..=6 {
+            let metadata_hash = batch_mint_builder
+                .add_asset(
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &test_metadata_args(i, asset_creators.clone()),
+                )
+                .unwrap();
+            // only the first creator has signed so far
+            let mut creators_signatures = HashMap::new();
+            creators_signatures.insert(creator_1.pubkey(), creator_1.sign_message(&metadata_hash.get_message()));
+            message_and_signatures.insert(metadata_hash.get_nonce(), creators_signatures);
+        }
+        batch_mint_builder
+            .add_signatures_for_verified_creators(message_and_signatures)
+            .unwrap();
+        assert!(batch_mint_builder.build_batch_mint().is_err());
+
+        let mut buffer = Vec::new();
+        batch_mint_builder.save_session(&mut buffer).unwrap();
+
+        let session: BatchMintSession = serde_json::from_slice(&buffer).unwrap();
+        let mut restored = BatchMintBuilder::from_session(&session, (5, 8, 3)).unwrap();
+        assert_eq!(restored.merkle.get_root(), batch_mint_builder.merkle.get_root());
+        assert_eq!(restored.canopy_leaves, batch_mint_builder.canopy_leaves);
+        assert_eq!(
+            restored.outstanding_by_creator(),
+            batch_mint_builder.outstanding_by_creator()
+        );
+
+        // the second creator signs after resuming
+        let mut message_and_signatures = HashMap::new();
+        for batch_mint in restored.mints.values() {
+            let message = MetadataArgsHash::new(&batch_mint.leaf_update, &restored.tree_account, &batch_mint.mint_args)
+                .get_message();
+            let mut creators_signatures = HashMap::new();
+            creators_signatures.insert(creator_2.pubkey(), creator_2.sign_message(&message));
+            message_and_signatures.insert(batch_mint.leaf_update.nonce(), creators_signatures);
+        }
+        restored
+            .add_signatures_for_verified_creators(message_and_signatures)
+            .unwrap();
+        restored.build_batch_mint().unwrap();
+
+        // session saved for a tree with a different canopy
+        match BatchMintBuilder::from_session(&session, (5, 8, 2)) {
+            Err(BatchMintError::CheckpointMismatch { field }) => assert_eq!(field, "canopy_depth"),
+            _ => panic!("Canopy mismatch is not detected"),
+        }
+    }
+
     #[test]
     fn test_verify_few_creators() {
         let tree_account = Pubkey::new_unique();
@@ -652,7 +2535,7 @@ mod test {
             },
         ];
 
-        let mut batch_mint_builder = BatchMintBuilder::new(tree_account, 5, 8, 4).unwrap();
+        let mut batch_mint_builder = BatchMintBuilder::new(tree_account, (5, 8, 4)).unwrap();
 
         let metadata_args = test_metadata_args(1u8, asset_creators.clone());
 
@@ -747,6 +2630,174 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_edition_nonce_hash_matches_bubblegum() {
+        let tree_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        for edition_nonce in [None, Some(0u8), Some(1u8), Some(254u8), Some(255u8)] {
+            let mut metadata_args = test_metadata_args(1u8, vec![]);
+            metadata_args.edition_nonce = edition_nonce;
+
+            let metadata_args_hash = hash_metadata_args(7, &tree_account, &owner, &delegate, &metadata_args);
+
+            // data hash is calculated exactly the same way the Bubblegum program does it
+            let expected_data_hash = mpl_bubblegum::hash::hash_metadata(&metadata_args).unwrap();
+            assert_eq!(metadata_args_hash.data_hash, expected_data_hash);
+
+            let leaf_schema = LeafSchema::V1 {
+                id: metadata_args_hash.id,
+                owner,
+                delegate,
+                nonce: 7,
+                data_hash: expected_data_hash,
+                creator_hash: mpl_bubblegum::hash::hash_creators(&metadata_args.creators),
+            };
+            assert_eq!(metadata_args_hash.hashed_leaf, leaf_schema.hash());
+        }
+
+        // edition nonce is a part of the hash
+        let mut with_nonce = test_metadata_args(1u8, vec![]);
+        with_nonce.edition_nonce = Some(1);
+        let without_nonce = test_metadata_args(1u8, vec![]);
+        assert_ne!(
+            hash_metadata_args(0, &tree_account, &owner, &delegate, &with_nonce).data_hash,
+            hash_metadata_args(0, &tree_account, &owner, &delegate, &without_nonce).data_hash
+        );
+    }
+
+    #[test]
+    fn test_leaf_hash_scheme_dispatch() {
+        let tree_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        for token_standard in [
+            None,
+            Some(TokenStandard::NonFungible),
+            Some(TokenStandard::FungibleAsset),
+            Some(TokenStandard::Fungible),
+            Some(TokenStandard::NonFungibleEdition),
+        ] {
+            let mut metadata_args = test_metadata_args(1u8, vec![]);
+            metadata_args.token_standard = token_standard;
+
+            assert_eq!(LeafHashScheme::for_metadata(&metadata_args), LeafHashScheme::V1);
+            assert_eq!(
+                hash_metadata_args(3, &tree_account, &owner, &delegate, &metadata_args).hashed_leaf,
+                hash_metadata_args_v1(3, &tree_account, &owner, &delegate, &metadata_args).hashed_leaf
+            );
+        }
+    }
+
+    #[test]
+    fn test_seller_fee_basis_points_hash_matches_bubblegum() {
+        let tree_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        for seller_fee_basis_points in [0u16, 9999, 10000] {
+            let mut metadata_args = test_metadata_args(1u8, vec![]);
+            metadata_args.seller_fee_basis_points = seller_fee_basis_points;
+
+            let metadata_args_hash = hash_metadata_args(0, &tree_account, &owner, &delegate, &metadata_args);
+            assert_eq!(
+                metadata_args_hash.data_hash,
+                mpl_bubblegum::hash::hash_metadata(&metadata_args).unwrap()
+            );
+
+            // seller_fee_basis_points is a part of the serialized metadata args, and is also appended to its hash
+            let metadata_args_bytes = metadata_args.try_to_vec().unwrap();
+            let expected_data_hash = keccak::hashv(&[
+                &keccak::hashv(&[&metadata_args_bytes]).to_bytes(),
+                &seller_fee_basis_points.to_le_bytes(),
+            ]);
+            assert_eq!(metadata_args_hash.data_hash, expected_data_hash.to_bytes());
+        }
+    }
+
+    #[test]
+    fn test_hash_leaves() {
+        let tree_account = Pubkey::new_unique();
+        let mut batch_mint_builder = BatchMintBuilder::new(tree_account, (5, 8, 0)).unwrap();
+
+        let assets = (1u8..=10)
+            .map(|i| {
+                (
+                    Pubkey::new_unique(),
+                    Pubkey::new_unique(),
+                    test_metadata_args(i, vec![]),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let expected_hashes = assets
+            .iter()
+            .map(|(owner, delegate, metadata_args)| {
+                batch_mint_builder
+                    .add_asset(owner, delegate, metadata_args)
+                    .unwrap()
+                    .hashed_leaf
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(hash_leaves(&tree_account, &assets), expected_hashes);
+    }
+
+    #[test]
+    fn test_unverified_collections_do_not_require_collection_config() {
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (10, 32, 0)).unwrap();
+
+        for i in 0..1000 {
+            let mut metadata_args = test_metadata_args((i % 256) as u8, vec![]);
+            metadata_args.collection = Some(mpl_bubblegum::types::Collection {
+                verified: false,
+                key: Pubkey::new_unique(),
+            });
+            batch_mint_builder.add_asset(&owner, &delegate, &metadata_args).unwrap();
+        }
+
+        assert!(batch_mint_builder.collection_config.is_none());
+        let batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+        assert_eq!(batch_mint.batch_mints.len(), 1000);
+    }
+
+    #[cfg(any(debug_assertions, feature = "strict-checks"))]
+    #[test]
+    fn test_first_divergent_index() {
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for i in 0..10 {
+            batch_mint_builder
+                .add_asset(&owner, &delegate, &test_metadata_args(i, vec![]))
+                .unwrap();
+        }
+        let leaves = batch_mint_builder
+            .mints
+            .values()
+            .map(|batch_mint| batch_mint.leaf_update.hash())
+            .collect::<Vec<_>>();
+        let mut roots = batch_mint_builder
+            .mints
+            .values()
+            .map(|batch_mint| batch_mint.tree_update.path.last().unwrap().node)
+            .collect::<Vec<_>>();
+        assert_eq!(calc_root(&leaves, 5), roots[9]);
+
+        roots[9] = [1; 32];
+        assert_eq!(first_divergent_index(&leaves, &roots, 5), 9);
+
+        for root in roots.iter_mut().skip(6) {
+            *root = [1; 32];
+        }
+        assert_eq!(first_divergent_index(&leaves, &roots, 5), 6);
+    }
+
     fn test_metadata_args(i: u8, creators: Vec<Creator>) -> MetadataArgs {
         MetadataArgs {
             name: format!("{i}"),
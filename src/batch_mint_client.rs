@@ -1,37 +1,263 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::io::{BufWriter, Read};
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use borsh::BorshDeserialize;
+use futures::{Stream, StreamExt, TryStreamExt};
 
 use mpl_bubblegum::accounts::MerkleTree;
 use mpl_bubblegum::instructions::{
-    AddCanopyBuilder, FinalizeTreeWithRootAndCollectionBuilder, FinalizeTreeWithRootBuilder, PrepareTreeBuilder,
+    AddCanopyBuilder, FinalizeTreeWithRootAndCollectionBuilder, FinalizeTreeWithRootAndCollectionInstructionArgs,
+    FinalizeTreeWithRootBuilder, FinalizeTreeWithRootInstructionArgs, PrepareTreeBuilder,
 };
-use mpl_bubblegum::types::{ConcurrentMerkleTreeHeaderData, LeafSchema};
+use mpl_bubblegum::types::ConcurrentMerkleTreeHeaderData;
+#[cfg(feature = "offchain-metadata")]
+use mpl_bubblegum::types::MetadataArgs;
 use mpl_common_constants::constants::FEE_RECEIVER;
+use mplx_staking_states::state::{LockupKind, Voter, REGISTRAR_DISCRIMINATOR};
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_rpc_client_api::config::{RpcAccountInfoConfig, RpcSimulateTransactionConfig};
 use solana_sdk::account::{Account, ReadableAccount};
 use solana_sdk::compute_budget::ComputeBudgetInstruction;
-use solana_sdk::instruction::AccountMeta;
+use solana_sdk::hash::hash;
+use solana_sdk::instruction::{AccountMeta, InstructionError};
+use solana_sdk::message::Message;
 use solana_sdk::signature::Signature;
 use solana_sdk::signer::keypair::Keypair;
 use solana_sdk::signer::Signer;
-use solana_sdk::transaction::Transaction;
+use solana_sdk::transaction::{Transaction, TransactionError};
 use spl_merkle_tree_reference::Node;
 
 use crate::batch_mint_builder::BatchMintBuilder;
-use crate::errors::BatchMintError;
+use crate::errors::{classify_finalize_failure, BatchMintError, FinalizeFailureReason};
 use crate::merkle_tree_wrapper::{
     calc_merkle_tree_size, calc_tree_data_account_size, restore_canopy_depth_from_buffer,
 };
-use crate::model::{BatchMint, BatchMintInstruction};
+use crate::model::{BatchMint, BatchMintSession, FinalizeArgs, FinalizeCheckpoint, TreeParams};
 use crate::pubkey_util;
-use crate::tree_data_acc::TreeDataInfo;
+use crate::storage::BatchMintStorage;
+use crate::tree_config_acc::TreeConfigInfo;
+use crate::tree_data_acc::{check_header_version, check_tree_account_size, TreeDataInfo};
+use crate::util::node_to_string;
 
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::system_instruction::MAX_PERMITTED_DATA_LENGTH;
 use solana_sdk::{system_instruction, system_program};
 
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_program::instruction::Instruction;
 
-const CANOPY_NODES_PER_TX: usize = 24;
+/// Maximum number of canopy leaf nodes a single AddCanopy instruction accepts,
+/// also the default chunk size, see [BatchMintClient::with_canopy_chunk_size].
+pub const MAX_CANOPY_NODES_PER_TX: usize = 24;
+
+/// Maximum number of metadata JSON requests sent at once by [BatchMintClient::verify_offchain_metadata]
+#[cfg(feature = "offchain-metadata")]
+pub const OFFCHAIN_METADATA_CONCURRENCY: usize = 16;
+
+/// Default compute unit limit of the finalize transaction, see [BatchMintClient::with_compute_unit_limit].
+pub const DEFAULT_COMPUTE_UNIT_LIMIT: u32 = 1_000_000;
+
+/// Default compute unit limit of AddCanopy transactions, see [BatchMintClient::with_canopy_compute_unit_limit].
+/// Adding a full chunk of canopy leaf nodes takes much less than that.
+pub const DEFAULT_CANOPY_COMPUTE_UNIT_LIMIT: u32 = 200_000;
+
+/// Default number of times a failed AddCanopy transaction is retried, see [BatchMintClient::with_canopy_retries].
+pub const DEFAULT_CANOPY_RETRIES: u32 = 3;
+
+/// Default number of AddCanopy transactions in flight at once, see [BatchMintClient::with_canopy_concurrency].
+pub const DEFAULT_CANOPY_CONCURRENCY: usize = 4;
+
+/// Minimum weighted stake of the staker required by the Bubblegum program to finalize a batch mint,
+/// in base units of MPLX (6 decimals), i.e. 30,000,000 weighted MPLX, see [BatchMintClient::required_stake].
+pub const MINIMUM_WEIGHTED_STAKE: u64 = 30_000_000_000_000;
+
+/// Maximum length (in bytes) of the `metadata_url` argument of FinalizeTreeWithRoot,
+/// the same as the maximum URI length of a Metaplex asset.
+/// Prefer short content-addressed URLs, e.g. `https://arweave.net/<transaction id>`.
+pub const MAX_METADATA_URL_LEN: usize = 200;
+
+/// Maximum length (in bytes) of the `metadata_hash` argument of FinalizeTreeWithRoot.
+/// The hash of the uploaded document returned by [crate::util::metadata_hash] takes 16 bytes.
+pub const MAX_METADATA_HASH_LEN: usize = 64;
+
+/// Progress events of [BatchMintClient::finalize_tree_stream].
+#[derive(Debug, Clone, PartialEq)]
+pub enum FinalizeEvent {
+    /// AddCanopy transaction for the chunk number `index` has been confirmed
+    CanopyChunkConfirmed { index: usize, signature: Signature },
+    /// The final FinalizeTreeWithRoot transaction has been confirmed
+    Finalized { signature: Signature },
+}
+
+/// Signatures of all the transactions sent by [BatchMintClient::finalize_tree_with_report].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalizeReport {
+    /// AddCanopy transactions in the order of canopy chunks,
+    /// each paired with the canopy leaf index the chunk starts from
+    pub canopy_signatures: Vec<(u32, Signature)>,
+    /// the final FinalizeTreeWithRoot transaction
+    pub finalize_signature: Signature,
+}
+
+/// Outcome of a simulated transaction, see [BatchMintClient::simulate_finalize_tree].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionSimulation {
+    /// program logs of the transaction
+    pub logs: Vec<String>,
+    /// None if the transaction would succeed
+    pub err: Option<TransactionError>,
+    pub units_consumed: Option<u64>,
+}
+
+/// Result of [BatchMintClient::simulate_finalize_tree].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalizeSimulation {
+    /// AddCanopy transactions in the order of canopy chunks,
+    /// each paired with the canopy leaf index the chunk starts from
+    pub canopy: Vec<(u32, TransactionSimulation)>,
+    /// the final FinalizeTreeWithRoot transaction
+    pub finalize: TransactionSimulation,
+}
+
+impl FinalizeSimulation {
+    /// Returns true if none of the simulated transactions failed.
+    pub fn is_success(&self) -> bool {
+        self.canopy.iter().all(|(_, simulation)| simulation.err.is_none()) && self.finalize.err.is_none()
+    }
+
+    /// Classifies the failure of the simulated FinalizeTreeWithRoot transaction, if it failed,
+    /// the same way as [BatchMintError::finalize_failure_reason] does for a sent one.
+    pub fn finalize_failure_reason(&self) -> Option<FinalizeFailureReason> {
+        self.finalize.err.as_ref()?;
+        classify_finalize_failure(&self.finalize.logs, self.finalize.err.as_ref())
+    }
+}
+
+/// Result of [BatchMintClient::verify_finalized]: outcome of each check of a finalized tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FinalizeVerification {
+    /// the on-chain root equals the builder root
+    pub root_matches: bool,
+    /// the sequence number of the on-chain tree is 1, i.e. the batch mint is appended as a single change
+    pub sequence_number_matches: bool,
+    /// the on-chain rightmost leaf equals the last leaf of the builder
+    pub rightmost_leaf_matches: bool,
+    /// the canopy of the tree account is zeroed, see [BatchMintClient::verify_canopy_cleared]
+    pub canopy_cleared: bool,
+}
+
+impl FinalizeVerification {
+    /// Returns true if all the checks have passed.
+    pub fn is_valid(&self) -> bool {
+        self.root_matches && self.sequence_number_matches && self.rightmost_leaf_matches && self.canopy_cleared
+    }
+}
+
+/// Stake of a staker compared to the requirement of the Bubblegum program,
+/// see [BatchMintClient::check_staker_eligibility].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StakeStatus {
+    /// current weighted stake of the staker, 0 if the staker has no voter account
+    pub weighted_stake: u64,
+    /// minimum weighted stake required to finalize a batch mint, see [BatchMintClient::required_stake]
+    pub required_stake: u64,
+}
+
+impl StakeStatus {
+    /// Returns true if the staker is allowed to finalize a batch mint.
+    pub fn is_eligible(&self) -> bool {
+        self.weighted_stake >= self.required_stake
+    }
+
+    /// Returns how much weighted stake the staker lacks to finalize a batch mint, 0 if eligible.
+    pub fn shortfall(&self) -> u64 {
+        self.required_stake.saturating_sub(self.weighted_stake)
+    }
+}
+
+/// Batch mint instructions supported by the deployed Bubblegum program,
+/// see [BatchMintClient::probe_program_features].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramFeatures {
+    pub prepare_tree: bool,
+    pub add_canopy: bool,
+    pub finalize_tree_with_root: bool,
+    pub finalize_tree_with_root_and_collection: bool,
+}
+
+impl ProgramFeatures {
+    /// Returns [BatchMintError::BatchMintNotSupported] listing missing instructions
+    /// if any of the instructions required for a batch mint is not supported.
+    pub fn check_batch_mint_supported(&self) -> std::result::Result<(), BatchMintError> {
+        let missing = [
+            ("PrepareTree", self.prepare_tree),
+            ("AddCanopy", self.add_canopy),
+            ("FinalizeTreeWithRoot", self.finalize_tree_with_root),
+            (
+                "FinalizeTreeWithRootAndCollection",
+                self.finalize_tree_with_root_and_collection,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, supported)| !supported)
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(BatchMintError::BatchMintNotSupported(missing.join(", ")))
+        }
+    }
+}
+
+/// A single tree to finalize with [BatchMintClient::finalize_many].
+/// Fields have the same meaning as the corresponding arguments of [BatchMintClient::finalize_tree].
+pub struct FinalizeJob<'a> {
+    pub metadata_url: &'a str,
+    pub metadata_hash: &'a str,
+    pub batch_mint_builder: &'a BatchMintBuilder,
+    pub tree_creator: &'a Keypair,
+    pub extra_signers: &'a [&'a Keypair],
+}
+
+enum FinalizeStreamState {
+    Start,
+    Canopy(std::iter::Enumerate<std::vec::IntoIter<(u32, Vec<Node>)>>),
+    Finalize,
+    Done,
+}
+
+/// Result of checking the metadata JSON, an asset `uri` points to, against the asset on-chain metadata.
+#[cfg(feature = "offchain-metadata")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataCheck {
+    /// JSON is fetched, its name and symbol match the on-chain ones
+    Valid,
+    /// the URI cannot be fetched or responds with a non-success status
+    Unreachable(String),
+    /// the response is not a valid JSON
+    InvalidJson(String),
+    /// JSON `field` differs from the on-chain value (`actual` is None if the field is missing)
+    Mismatch {
+        field: String,
+        expected: String,
+        actual: Option<String>,
+    },
+}
+
+/// Defines how [BatchMintClient] waits for sent transactions to be confirmed.
+/// Instead of the default (potentially very long) confirmation of the RPC client,
+/// the transaction status is polled every `poll_interval`, until `max_wait` is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfirmStrategy {
+    pub max_wait: Duration,
+    pub poll_interval: Duration,
+}
 
 /// The main controll point for batch mint creation flows.
 /// It allows to:
@@ -42,26 +268,170 @@ const CANOPY_NODES_PER_TX: usize = 24;
 /// TODO: add link to batch mint documentation page.
 pub struct BatchMintClient {
     client: Arc<RpcClient>,
+    /// account size -> minimum balance for rent exemption
+    rent_cache: Mutex<HashMap<usize, u64>>,
+    /// if not set, the default confirmation of the RPC client is used
+    confirm_strategy: Option<ConfirmStrategy>,
+    /// how many times a failed AddCanopy transaction is retried before giving up
+    canopy_retries: u32,
+    /// compute unit limit requested by the finalize transaction
+    compute_unit_limit: u32,
+    /// compute unit limit requested by AddCanopy transactions
+    canopy_compute_unit_limit: u32,
+    /// number of canopy leaf nodes sent in a single AddCanopy transaction
+    canopy_chunk_size: usize,
+    /// maximum number of AddCanopy transactions in flight at once
+    canopy_concurrency: usize,
+    /// compute unit price (in micro-lamports) of AddCanopy and finalize transactions, no price is set if None
+    priority_fee: Option<u64>,
 }
 
 impl BatchMintClient {
     /// Creates a new instance that allows to create batch mints.
     pub fn new(client: Arc<RpcClient>) -> BatchMintClient {
-        BatchMintClient { client }
+        BatchMintClient {
+            client,
+            rent_cache: Mutex::new(HashMap::new()),
+            confirm_strategy: None,
+            canopy_retries: DEFAULT_CANOPY_RETRIES,
+            compute_unit_limit: DEFAULT_COMPUTE_UNIT_LIMIT,
+            canopy_compute_unit_limit: DEFAULT_CANOPY_COMPUTE_UNIT_LIMIT,
+            canopy_chunk_size: MAX_CANOPY_NODES_PER_TX,
+            canopy_concurrency: DEFAULT_CANOPY_CONCURRENCY,
+            priority_fee: None,
+        }
+    }
+
+    /// Sets the strategy for transaction confirmation,
+    /// see [ConfirmStrategy] for details.
+    pub fn with_confirm_strategy(mut self, confirm_strategy: ConfirmStrategy) -> Self {
+        self.confirm_strategy = Some(confirm_strategy);
+        self
+    }
+
+    /// Sets how many times a failed AddCanopy transaction is retried (with a fresh blockhash)
+    /// before the canopy upload gives up with [BatchMintError::CanopyUploadFailed].
+    /// Default is [DEFAULT_CANOPY_RETRIES], 0 disables retries.
+    pub fn with_canopy_retries(mut self, canopy_retries: u32) -> Self {
+        self.canopy_retries = canopy_retries;
+        self
+    }
+
+    /// Sets the compute unit limit requested by both AddCanopy and finalize transactions.
+    /// Small trees need less than the default [DEFAULT_COMPUTE_UNIT_LIMIT] of the finalize transaction,
+    /// while finalizing a tree with a long proof passed as remaining accounts may need more.
+    ///
+    /// AddCanopy transactions request [DEFAULT_CANOPY_COMPUTE_UNIT_LIMIT] by default,
+    /// call [BatchMintClient::with_canopy_compute_unit_limit] after this one to keep a separate limit for them.
+    pub fn with_compute_unit_limit(mut self, compute_unit_limit: u32) -> Self {
+        self.compute_unit_limit = compute_unit_limit;
+        self.canopy_compute_unit_limit = compute_unit_limit;
+        self
+    }
+
+    /// Sets the compute unit limit requested by AddCanopy transactions,
+    /// separately from the limit of the final transaction, see [BatchMintClient::with_compute_unit_limit].
+    /// Default is [DEFAULT_CANOPY_COMPUTE_UNIT_LIMIT].
+    pub fn with_canopy_compute_unit_limit(mut self, canopy_compute_unit_limit: u32) -> Self {
+        self.canopy_compute_unit_limit = canopy_compute_unit_limit;
+        self
+    }
+
+    /// Sets how many canopy leaf nodes are sent in a single AddCanopy transaction.
+    /// Smaller chunks leave more room in the transaction for other instructions,
+    /// e.g. compute budget ones. Default is [MAX_CANOPY_NODES_PER_TX].
+    ///
+    /// Returns [BatchMintError::IllegalArgumets] if the size is not in `1..=MAX_CANOPY_NODES_PER_TX`.
+    pub fn with_canopy_chunk_size(mut self, canopy_chunk_size: usize) -> Result<Self, BatchMintError> {
+        if !(1..=MAX_CANOPY_NODES_PER_TX).contains(&canopy_chunk_size) {
+            return Err(BatchMintError::IllegalArgumets(format!(
+                "Canopy chunk size must be between 1 and {MAX_CANOPY_NODES_PER_TX}, got {canopy_chunk_size}"
+            )));
+        }
+        self.canopy_chunk_size = canopy_chunk_size;
+        Ok(self)
+    }
+
+    /// Sets how many AddCanopy transactions are sent concurrently by [BatchMintClient::upload_canopy]
+    /// (and so by [BatchMintClient::finalize_tree]) and by [BatchMintClient::finalize_tree_resumable].
+    /// Canopy chunks cover different canopy ranges, so they can be added in any order,
+    /// but the final FinalizeTreeWithRoot transaction is always sent after all of them are confirmed.
+    /// Default is [DEFAULT_CANOPY_CONCURRENCY], 0 is treated as 1, i.e. sequential upload.
+    pub fn with_canopy_concurrency(mut self, canopy_concurrency: usize) -> Self {
+        self.canopy_concurrency = canopy_concurrency.max(1);
+        self
+    }
+
+    /// Sets the priority fee of AddCanopy and finalize transactions: the compute unit price in micro-lamports,
+    /// that is set with ComputeBudget SetComputeUnitPrice instruction, to help the transactions land during congestion.
+    /// By default no compute unit price is set.
+    ///
+    /// The price instruction is placed after the compute unit limit one,
+    /// so the index of the finalize instruction in the transaction becomes 2 instead of 1.
+    pub fn with_priority_fee(mut self, micro_lamports: u64) -> Self {
+        self.priority_fee = Some(micro_lamports);
+        self
     }
 
     pub fn client(&self) -> &RpcClient {
         &self.client
     }
 
+    /// Sends the transaction and waits for it to be confirmed according to the confirm strategy.
+    /// Returns [BatchMintError::ConfirmationTimeout] with the signature of the transaction,
+    /// if it is not confirmed in `max_wait`, so the caller is able to check its state later.
+    async fn send_and_confirm_transaction(&self, tx: &Transaction) -> std::result::Result<Signature, BatchMintError> {
+        let Some(confirm_strategy) = self.confirm_strategy else {
+            return Ok(self.client.send_and_confirm_transaction(tx).await?);
+        };
+
+        let signature = self.client.send_transaction(tx).await?;
+        let started_at = Instant::now();
+        loop {
+            match self.client.get_signature_status(&signature).await? {
+                Some(Ok(())) => return Ok(signature),
+                Some(Err(tx_err)) => return Err(solana_rpc_client_api::client_error::Error::from(tx_err).into()),
+                None => {}
+            }
+            if started_at.elapsed() >= confirm_strategy.max_wait {
+                return Err(BatchMintError::ConfirmationTimeout { signature });
+            }
+            tokio::time::sleep(confirm_strategy.poll_interval).await;
+        }
+    }
+
+    /// Returns the minimum balance required to make an account of given size rent exempt.
+    /// The value is requested from the RPC only once per account size, and then is taken from the cache.
+    ///
+    /// Note: rent parameters change rarely, but they are not strictly constant,
+    /// use [BatchMintClient::clear_rent_cache] to force re-fetching them.
+    pub async fn minimum_balance_for_rent_exemption(&self, size: usize) -> std::result::Result<u64, BatchMintError> {
+        let cached = self.rent_cache.lock().unwrap().get(&size).copied();
+        if let Some(lamports) = cached {
+            return Ok(lamports);
+        }
+        let lamports = self.client.get_minimum_balance_for_rent_exemption(size).await?;
+        self.rent_cache.lock().unwrap().insert(size, lamports);
+        Ok(lamports)
+    }
+
+    /// Drops all the cached rent exemption values.
+    pub fn clear_rent_cache(&self) {
+        self.rent_cache.lock().unwrap().clear();
+    }
+
     /// Prepares solana accounts (space) for future merkle tree.
     /// This is the first step of the flow of creating a compressed NFT aka BatchMint.
     /// See https://developers.metaplex.com/bubblegum/create-trees
     /// for detail about merle tree.
     ///
     /// # Arguments
-    /// * `payer` - account that pays for the operation
-    /// * `tree_creator` - owner of tree account to be created
+    /// * `payer` - account that pays for the operation: the rent of both the tree data account
+    ///   and the tree config account, as well as the transaction fee
+    /// * `tree_creator` - owner of tree account to be created, it only signs the PrepareTree
+    ///   instruction and becomes the authority of the tree, but doesn't pay for anything.
+    ///   The same tree creator must be passed to `finalize_tree` later.
+    ///   Can be the same keypair as `payer`.
     /// * `tree_data_account` - a desired address for the account that will be created by the call
     ///   and used to store the merkle tree
     /// * `max_depth` - depth of desired merkle tree. Should be in range: TODO: add
@@ -80,67 +450,317 @@ impl BatchMintClient {
         max_buf_size: u32,
         canopy_depth: u32,
     ) -> std::result::Result<Signature, BatchMintError> {
-        if canopy_depth >= max_depth {
-            return Err(BatchMintError::IllegalArgumets(
-                "Canopy depth should be less than tree maximum depth".to_string(),
-            ));
-        }
-
-        let required_canopy = max_depth.saturating_sub(mpl_bubblegum::MAX_ACC_PROOFS_SIZE);
-        if canopy_depth < required_canopy {
-            return Err(BatchMintError::IllegalArgumets(format!(
-                "Three of depth={max_depth} requires as least canopy={required_canopy}"
-            )));
-        }
-
-        let merkle_tree_size = calc_tree_data_account_size(max_depth, max_buf_size, canopy_depth)
-            .ok_or(BatchMintError::UnexpectedTreeSize(max_depth, max_buf_size))?;
-
-        let tree_config_account = pubkey_util::derive_tree_config_account(&tree_data_account.pubkey());
+        let merkle_tree_size = checked_tree_data_account_size(max_depth, max_buf_size, canopy_depth)?;
 
         let tx = Transaction::new_signed_with_payer(
-            &[
-                system_instruction::create_account(
-                    // acquire space for future merkle tree
-                    &payer.pubkey(),
-                    &tree_data_account.pubkey(),
-                    self.client
-                        .get_minimum_balance_for_rent_exemption(merkle_tree_size)
-                        .await?,
-                    merkle_tree_size as u64,
-                    &spl_account_compression::id(),
-                ),
-                PrepareTreeBuilder::new()
-                    .payer(tree_creator.pubkey())
-                    .tree_creator(tree_creator.pubkey())
-                    .max_depth(max_depth)
-                    .max_buffer_size(max_buf_size)
-                    .merkle_tree(tree_data_account.pubkey())
-                    .tree_config(tree_config_account)
-                    .log_wrapper(spl_noop::id())
-                    .compression_program(spl_account_compression::id())
-                    .system_program(system_program::id())
-                    .instruction(),
-            ],
+            &self.build_prepare_tree_instructions(
+                payer.pubkey(),
+                tree_creator.pubkey(),
+                tree_data_account.pubkey(),
+                (max_depth, max_buf_size, canopy_depth),
+                self.minimum_balance_for_rent_exemption(merkle_tree_size).await?,
+            )?,
             Some(&payer.pubkey()),
             &[payer, tree_creator, tree_data_account],
             self.client.get_latest_blockhash().await?,
         );
 
-        let tx_signature = self.client.send_and_confirm_transaction(&tx).await?;
+        let tx_signature = self.send_and_confirm_transaction(&tx).await?;
 
         // PrepareTree is a well tested functionality, but still the call can use the signature
         // to check the transaction state and be sure it has been applied successfully.
         Ok(tx_signature)
     }
 
+    /// Builds unsigned instructions of [BatchMintClient::prepare_tree]: creation of the tree data account
+    /// and PrepareTree, so the tree can be prepared by an externally signed transaction (e.g. through a multisig).
+    /// The transaction must be signed by the payer, the tree creator and the tree data account.
+    ///
+    /// Tree params are checked the same way as by [BatchMintClient::prepare_tree].
+    ///
+    /// ## Arguments
+    /// * `payer` - pays for the tree data account and the transaction
+    /// * `tree_creator` - tree creator (the tree owner)
+    /// * `tree_data_account` - new account the merkle tree is stored in
+    /// * `tree_params` - size of the tree, see [TreeParams]
+    /// * `rent_lamports` - balance of the tree data account, at least the rent exemption for its size,
+    ///   which is [calc_tree_data_account_size] bytes
+    pub fn build_prepare_tree_instructions(
+        &self,
+        payer: Pubkey,
+        tree_creator: Pubkey,
+        tree_data_account: Pubkey,
+        tree_params: impl Into<TreeParams>,
+        rent_lamports: u64,
+    ) -> std::result::Result<Vec<Instruction>, BatchMintError> {
+        let TreeParams {
+            max_depth,
+            max_buffer_size,
+            canopy_depth,
+        } = tree_params.into();
+        let merkle_tree_size = checked_tree_data_account_size(max_depth, max_buffer_size, canopy_depth)?;
+
+        let tree_config_account = pubkey_util::derive_tree_config_account(&tree_data_account);
+
+        Ok(vec![
+            system_instruction::create_account(
+                // acquire space for future merkle tree
+                &payer,
+                &tree_data_account,
+                rent_lamports,
+                merkle_tree_size as u64,
+                &spl_account_compression::id(),
+            ),
+            PrepareTreeBuilder::new()
+                .payer(payer)
+                .tree_creator(tree_creator)
+                .max_depth(max_depth)
+                .max_buffer_size(max_buffer_size)
+                .merkle_tree(tree_data_account)
+                .tree_config(tree_config_account)
+                .log_wrapper(spl_noop::id())
+                .compression_program(spl_account_compression::id())
+                .system_program(system_program::id())
+                .instruction(),
+        ])
+    }
+
     /// Creates a batch mint builder object - a convenient wrapper for adding assets to batch mints.
     pub async fn create_batch_mint_builder(
         &self,
         tree_account: &Pubkey,
     ) -> std::result::Result<BatchMintBuilder, BatchMintError> {
-        let (max_depth, max_buffer_size, canopy_depth) = read_prepared_tree_size(&self.client, tree_account).await?;
-        BatchMintBuilder::new(*tree_account, max_depth, max_buffer_size, canopy_depth)
+        let tree_params = read_prepared_tree_size(&self.client, tree_account).await?;
+        BatchMintBuilder::new(*tree_account, tree_params)
+    }
+
+    /// Checks the tree is prepared and can be used to create a batch mint:
+    /// both the tree data account (owned by the account compression program)
+    /// and its bubblegum tree config account (owned by the bubblegum program) exist.
+    ///
+    /// Useful for polling until the [BatchMintClient::prepare_tree] transaction is visible
+    /// with the commitment of the RPC client.
+    pub async fn is_tree_ready(&self, tree_account: &Pubkey) -> std::result::Result<bool, BatchMintError> {
+        let tree_config_account = pubkey_util::derive_tree_config_account(tree_account);
+        let accounts = self
+            .client
+            .get_multiple_accounts_with_commitment(&[*tree_account, tree_config_account], self.client.commitment())
+            .await?
+            .value;
+        let has_owner = |account: &Option<Account>, owner: &Pubkey| account.as_ref().is_some_and(|a| a.owner == *owner);
+        Ok(has_owner(&accounts[0], &spl_account_compression::id()) && has_owner(&accounts[1], &mpl_bubblegum::ID))
+    }
+
+    /// Fetches and parses the bubblegum tree config account of the given tree.
+    /// After finalization, it allows to verify that the on-chain state matches the batch mint,
+    /// e.g. `num_minted` equals to the number of assets in the batch mint.
+    pub async fn read_tree_config(&self, tree_account: &Pubkey) -> std::result::Result<TreeConfigInfo, BatchMintError> {
+        let tree_config_account = pubkey_util::derive_tree_config_account(tree_account);
+        let account = self
+            .client
+            .get_account_with_commitment(&tree_config_account, self.client.commitment())
+            .await?
+            .value
+            .ok_or(BatchMintError::TreeNotPrepared {
+                tree_account: *tree_account,
+            })?;
+        TreeConfigInfo::from_bytes(account.data())
+    }
+
+    /// Detects which batch mint instructions are supported by the Bubblegum program deployed to the cluster,
+    /// so that older deployments can be detected before sending an instruction they do not support.
+    ///
+    /// Each instruction is simulated without accounts and arguments: the program rejects an unknown instruction
+    /// right away (Anchor InstructionFallbackNotFound, or InvalidInstructionData), while a supported one fails later,
+    /// on the missing arguments or accounts. Nothing is signed or sent.
+    /// The detection is best-effort: a program that answers an unknown instruction with some other error
+    /// is reported as supporting it, and the instruction itself may still fail when it is actually sent.
+    /// Use [ProgramFeatures::check_batch_mint_supported] to turn the result into an error.
+    ///
+    /// ## Arguments
+    /// `fee_payer` - any existing account with lamports, the simulated transactions are paid by
+    pub async fn probe_program_features(
+        &self,
+        fee_payer: &Pubkey,
+    ) -> std::result::Result<ProgramFeatures, BatchMintError> {
+        let program_id = mpl_bubblegum::ID;
+        self.client
+            .get_account_with_commitment(&program_id, self.client.commitment())
+            .await?
+            .value
+            .ok_or(BatchMintError::ProgramNotDeployed { program_id })?;
+
+        Ok(ProgramFeatures {
+            prepare_tree: self.probe_instruction(fee_payer, "prepare_tree").await?,
+            add_canopy: self.probe_instruction(fee_payer, "add_canopy").await?,
+            finalize_tree_with_root: self.probe_instruction(fee_payer, "finalize_tree_with_root").await?,
+            finalize_tree_with_root_and_collection: self
+                .probe_instruction(fee_payer, "finalize_tree_with_root_and_collection")
+                .await?,
+        })
+    }
+
+    /// Simulates the Bubblegum instruction with the given anchor name without accounts and arguments,
+    /// see [BatchMintClient::probe_program_features].
+    async fn probe_instruction(
+        &self,
+        fee_payer: &Pubkey,
+        instruction_name: &str,
+    ) -> std::result::Result<bool, BatchMintError> {
+        let instruction = Instruction::new_with_bytes(
+            mpl_bubblegum::ID,
+            &anchor_instruction_discriminator(instruction_name),
+            Vec::new(),
+        );
+        let tx = Transaction::new_unsigned(Message::new(&[instruction], Some(fee_payer)));
+        let simulation = self
+            .client
+            .simulate_transaction_with_config(
+                &tx,
+                RpcSimulateTransactionConfig {
+                    sig_verify: false,
+                    replace_recent_blockhash: true,
+                    commitment: Some(self.client.commitment()),
+                    ..Default::default()
+                },
+            )
+            .await?
+            .value;
+        instruction_supported(simulation.err).map_err(|err| BatchMintError::ProgramProbeFailed {
+            instruction: instruction_name.to_string(),
+            err,
+        })
+    }
+
+    /// Returns the minimum weighted stake the staker must have to finalize a batch mint,
+    /// i.e. the amount of deposited MPLX tokens multiplied by the lockup period multiplier
+    /// (see [mplx_staking_states::state::LockupPeriod::multiplier]), in the smallest units.
+    ///
+    /// The requirement is currently a constant compiled into the Bubblegum program ([MINIMUM_WEIGHTED_STAKE]),
+    /// it is not stored in the registrar or any other account, so nothing is read from chain yet.
+    pub fn required_stake(&self) -> Result<u64, BatchMintError> {
+        Ok(MINIMUM_WEIGHTED_STAKE)
+    }
+
+    /// Checks whether the staker has enough stake to finalize a batch mint,
+    /// so the lack of stake is detected before any transaction is sent,
+    /// instead of failing the finalize transaction with NotEnoughStakeForOperation (custom error 6042).
+    ///
+    /// Reads the registrar and the voter account of the staker,
+    /// and calculates the weighted stake of its deposits the same way the Bubblegum program does, see [weighted_stake].
+    /// A staker without a voter account has no stake.
+    ///
+    /// Returns [BatchMintError::InvalidStakingAccount] if the registrar does not exist,
+    /// or if the registrar or the voter account is not a valid staking program account.
+    pub async fn check_staker_eligibility(&self, staker: &Pubkey) -> std::result::Result<StakeStatus, BatchMintError> {
+        let registrar_key = pubkey_util::get_registrar_key();
+        let voter_key = pubkey_util::get_voter_key(&registrar_key, staker);
+
+        let mut accounts = self
+            .client
+            .get_multiple_accounts_with_commitment(&[registrar_key, voter_key], self.client.commitment())
+            .await?
+            .value
+            .into_iter();
+        let (registrar_account, voter_account) = (accounts.next().flatten(), accounts.next().flatten());
+
+        let registrar_valid = registrar_account.is_some_and(|account| {
+            account.owner == mplx_staking_states::ID && account.data.starts_with(&REGISTRAR_DISCRIMINATOR)
+        });
+        if !registrar_valid {
+            return Err(BatchMintError::InvalidStakingAccount {
+                account: registrar_key,
+                kind: "registrar".to_string(),
+            });
+        }
+
+        let weighted_stake = match voter_account {
+            Some(account) => {
+                let voter = parse_voter(&account)
+                    .filter(|voter| voter.voter_authority == *staker && voter.registrar == registrar_key)
+                    .ok_or_else(|| BatchMintError::InvalidStakingAccount {
+                        account: voter_key,
+                        kind: "voter".to_string(),
+                    })?;
+                weighted_stake(&voter)
+            }
+            None => 0,
+        };
+
+        Ok(StakeStatus {
+            weighted_stake,
+            required_stake: self.required_stake()?,
+        })
+    }
+
+    /// Checks that all the canopy bytes of the tree data account are zeroed.
+    ///
+    /// The canopy is only used to upload the batch mint tree, and FinalizeTreeWithRoot clears it,
+    /// so this method can be used to audit a tree after [BatchMintClient::finalize_tree].
+    /// For a tree without canopy `true` is returned.
+    pub async fn verify_canopy_cleared(&self, tree_account: &Pubkey) -> std::result::Result<bool, BatchMintError> {
+        let account = get_tree_account(&self.client, tree_account).await?;
+        let tree_data_info = TreeDataInfo::from_bytes(account.data())?;
+        Ok(tree_data_info.is_canopy_cleared())
+    }
+
+    /// Checks that the size of the tree data account is exactly the one expected for the geometry in its header,
+    /// so a truncated account, or one with foreign data, is not parsed as a valid tree.
+    /// Returns [BatchMintError::MalformedTreeAccount] with the expected and the actual size otherwise.
+    pub async fn verify_tree_account_size(&self, tree_account: &Pubkey) -> std::result::Result<(), BatchMintError> {
+        let account = get_tree_account(&self.client, tree_account).await?;
+        check_tree_account_size(account.data())
+    }
+
+    /// Audits the tree after [BatchMintClient::finalize_tree] using a single account fetch:
+    /// checks the root, the sequence number, the rightmost leaf and the canopy of the on-chain tree
+    /// against the given builder, see [FinalizeVerification].
+    pub async fn verify_finalized(
+        &self,
+        batch_mint_builder: &BatchMintBuilder,
+    ) -> std::result::Result<FinalizeVerification, BatchMintError> {
+        let account = get_tree_account(&self.client, &batch_mint_builder.tree_account).await?;
+        let tree_data_info = TreeDataInfo::from_bytes(account.data())?;
+        let merkle_tree = MerkleTree::from_bytes(account.data())?;
+        let tree_state = parse_tree_state(
+            &merkle_tree.serialized_tree,
+            tree_data_info.max_depth,
+            tree_data_info.max_buffer_size,
+        )?;
+
+        Ok(FinalizeVerification {
+            root_matches: tree_state.root == batch_mint_builder.merkle.get_root(),
+            sequence_number_matches: tree_state.sequence_number == batch_mint_builder.parent_sequence_delta(),
+            rightmost_leaf_matches: tree_state.rightmost_leaf == batch_mint_builder.last_leaf_hash,
+            canopy_cleared: tree_data_info.is_canopy_cleared(),
+        })
+    }
+
+    /// Creates a batch mint builder for the tree, taking into account the current on-chain state of the tree.
+    ///
+    /// A batch mint can be finalized only into a tree that has been prepared with [BatchMintClient::prepare_tree]
+    /// and has not been initialized yet, since FinalizeTreeWithRoot initializes the tree with the batch mint root.
+    /// So if leaves have already been minted to the tree individually (or it is already finalized),
+    /// no batch mint can be appended to it, and [BatchMintError::TreeAlreadyInitialized] is returned.
+    /// Otherwise, the result is the same as of [BatchMintClient::create_batch_mint_builder].
+    pub async fn builder_from_onchain_state(
+        &self,
+        tree_account: &Pubkey,
+    ) -> std::result::Result<BatchMintBuilder, BatchMintError> {
+        let account = get_tree_account(&self.client, tree_account).await?;
+        let tree_params = parse_tree_size(&account)?;
+
+        let merkle_tree = MerkleTree::from_bytes(account.data())?;
+        let (sequence_number, buffer_size) = parse_tree_counters(&merkle_tree.serialized_tree)?;
+        // an initialized tree always tracks at least one root in its changelog buffer
+        if buffer_size > 0 {
+            return Err(BatchMintError::TreeAlreadyInitialized {
+                tree_account: *tree_account,
+                sequence_number,
+            });
+        }
+
+        BatchMintBuilder::new(*tree_account, tree_params)
     }
 
     /// Turns a BatchMint object into a batch mint builder, so it can be filled with additional assets.
@@ -150,50 +770,67 @@ impl BatchMintClient {
         &self,
         batch_mint: &BatchMint,
     ) -> std::result::Result<BatchMintBuilder, BatchMintError> {
-        let (max_depth, max_buffer_size, canopy_depth) =
-            read_prepared_tree_size(&self.client, &batch_mint.tree_id).await?;
-        let mut batch_mint_builder =
-            BatchMintBuilder::new(batch_mint.tree_id, max_depth, max_buffer_size, canopy_depth)?;
-
-        for batch_mint in &batch_mint.batch_mints {
-            let BatchMintInstruction {
-                tree_update: _,
-                leaf_update,
-                mint_args,
-                authority: _,
-                creator_signature,
-            } = batch_mint;
-            let LeafSchema::V1 {
-                id: _,
-                owner,
-                delegate,
-                nonce: _,
-                data_hash: _,
-                creator_hash: _,
-            } = leaf_update;
-
-            let metadata_arg_hash = batch_mint_builder.add_asset(owner, delegate, mint_args)?;
-
-            if let Some(creator_signature) = creator_signature {
-                let mut message_and_signature = HashMap::new();
-                message_and_signature.insert(metadata_arg_hash.get_nonce(), creator_signature.clone());
-
-                batch_mint_builder.add_signatures_for_verified_creators(message_and_signature)?;
-            }
-        }
+        let tree_params = read_prepared_tree_size(&self.client, &batch_mint.tree_id).await?;
+        BatchMintBuilder::restore(batch_mint, tree_params)
+    }
+
+    /// Loads a batch mint builder saved with [BatchMintBuilder::save_session],
+    /// including creator signatures collected before the session was saved.
+    /// The saved session must match the size of the prepared on-chain tree.
+    ///
+    /// Note: the collection config is not a part of the session, it should be set up again.
+    pub async fn load_session(&self, reader: impl Read) -> std::result::Result<BatchMintBuilder, BatchMintError> {
+        let session: BatchMintSession =
+            serde_json::from_reader(reader).map_err(|e| BatchMintError::IoError(e.into()))?;
+        let tree_params = read_prepared_tree_size(&self.client, &session.batch_mint.tree_id).await?;
+        BatchMintBuilder::from_session(&session, tree_params)
+    }
 
-        Ok(batch_mint_builder)
+    /// Fetches metadata JSON of each asset added to the builder by its `uri`,
+    /// and checks that `name` and `symbol` of the JSON match the asset on-chain metadata.
+    /// Results are ordered by asset nonce.
+    ///
+    /// Note: this is a network-heavy operation, an HTTP request is sent for every asset
+    /// (at most [OFFCHAIN_METADATA_CONCURRENCY] at once).
+    #[cfg(feature = "offchain-metadata")]
+    pub async fn verify_offchain_metadata(
+        &self,
+        batch_mint_builder: &BatchMintBuilder,
+        http_client: &reqwest::Client,
+    ) -> Result<Vec<(u64, MetadataCheck)>, BatchMintError> {
+        let mut checks = futures::stream::iter(batch_mint_builder.mints.iter())
+            .map(|(nonce, mint)| async move { (*nonce, check_offchain_metadata(http_client, &mint.mint_args).await) })
+            .buffer_unordered(OFFCHAIN_METADATA_CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+        checks.sort_by_key(|(nonce, _)| *nonce);
+
+        Ok(checks)
     }
 
     /// Writes given batch mint to the solana tree account.
     ///
     /// ## Arguments
-    /// * `payer` - account that pays for the operation
-    /// * `metadata_url` - URL of the batch mint JSON representation stored in an immutable storage
-    /// * `metadata_hash` - hash of metadata uploaded to an immutable storage
+    /// * `payer` - account that pays for the operation, it is also the fee payer
+    ///   of all the transactions sent during finalization (AddCanopy ones and the final one),
+    ///   so it can differ from the payer used in `prepare_tree`
+    /// * `metadata_url` - URL of the batch mint JSON representation stored in an immutable storage,
+    ///   at most [MAX_METADATA_URL_LEN] bytes
+    /// * `metadata_hash` - hash of metadata uploaded to an immutable storage, at most [MAX_METADATA_HASH_LEN] bytes
     /// * `batch_mint_builder` - batch mint builder object created after prepare_tree
     /// * `tree_creator` - same tree creator that was used to prepare_tree
     /// * `staker` - can be same as payer
+    /// * `extra_signers` - additional signers of the finalize transaction required by non-standard
+    ///   program deployments (e.g. a reward pool co-signer), usually empty.
+    ///   A signer that is not an account of the finalize instruction yet, is appended to it
+    ///   as a readonly signer account after the rightmost proof
+    ///
+    /// Canopy leaf nodes that are already present in the tree account are not uploaded again,
+    /// so if a previous run crashed after the canopy upload, a builder restored from the batch mint JSON
+    /// (see [BatchMintClient::restore_batch_mint_builder]) goes straight to the final transaction.
+    ///
+    /// The final transaction is sent with [BatchMintBuilder::remaining_accounts],
+    /// which can be overridden for debugging with [BatchMintBuilder::override_remaining_accounts].
     pub async fn finalize_tree(
         &self,
         payer: &Keypair,
@@ -202,160 +839,1201 @@ impl BatchMintClient {
         batch_mint_builder: &BatchMintBuilder,
         tree_creator: &Keypair,
         staker: &Keypair,
+        extra_signers: &[&Keypair],
     ) -> Result<Signature, BatchMintError> {
-        let tree_config_account = pubkey_util::derive_tree_config_account(&batch_mint_builder.tree_account);
-
-        let tree_data_account = &self.client.get_account(&batch_mint_builder.tree_account).await?;
-        let tree_data_info = TreeDataInfo::from_bytes(tree_data_account.data())?;
-
-        if tree_data_info.canopy_depth > 0 {
-            let (canopy_to_add, canopy_offset) = calc_canopy_to_add(&tree_data_info, batch_mint_builder)?;
-
-            let compute_budget = ComputeBudgetInstruction::set_compute_unit_limit(1000000);
-            for (ind, chunk) in canopy_to_add.chunks(CANOPY_NODES_PER_TX).enumerate() {
-                let add_canopy_inst = AddCanopyBuilder::new()
-                    .tree_config(tree_config_account)
-                    .merkle_tree(batch_mint_builder.tree_account)
-                    .tree_creator_or_delegate(tree_creator.pubkey()) // Correct?
-                    .canopy_nodes(chunk.to_vec())
-                    .start_index((canopy_offset + ind * CANOPY_NODES_PER_TX) as u32)
-                    .log_wrapper(spl_noop::id())
-                    .compression_program(spl_account_compression::id())
-                    .system_program(system_program::id())
-                    .instruction();
-
-                let tx = Transaction::new_signed_with_payer(
-                    &[compute_budget.clone(), add_canopy_inst],
-                    Some(&tree_creator.pubkey()),
-                    &[tree_creator],
-                    self.client.get_latest_blockhash().await?,
-                );
-
-                self.client.send_and_confirm_transaction(&tx).await?;
-            }
-        }
-
-        // We're just using remaining_accounts to send proofs because they are of the same type
-        let remaining_accounts = batch_mint_builder
-            .merkle
-            .get_rightmost_proof()
-            .iter()
-            .map(|proof| AccountMeta {
-                pubkey: Pubkey::new_from_array(*proof),
-                is_signer: false,
-                is_writable: false,
-            })
-            .collect::<Vec<_>>();
-        let finalize_instruction = self.finalize_tree_instruction(
+        self.finalize_tree_with_report(
             payer,
-            batch_mint_builder,
             metadata_url,
             metadata_hash,
-            remaining_accounts.as_slice(),
-            tree_config_account,
-            staker.pubkey(),
-            tree_creator.pubkey(),
-        )?;
-        let mut signing_keypairs = [payer, tree_creator, staker].to_vec();
-        if let Some(ref collection_config) = batch_mint_builder.collection_config {
-            signing_keypairs.push(&collection_config.collection_authority);
-        }
+            batch_mint_builder,
+            tree_creator,
+            staker,
+            extra_signers,
+        )
+        .await
+        .map(|report| report.finalize_signature)
+    }
+
+    /// Does the same as [BatchMintClient::finalize_tree], but returns signatures of all the sent transactions,
+    /// see [FinalizeReport].
+    ///
+    /// If the canopy upload fails with [BatchMintError::CanopyUploadFailed],
+    /// all the chunks before its `chunk_index` have been confirmed.
+    pub async fn finalize_tree_with_report(
+        &self,
+        payer: &Keypair,
+        metadata_url: &str,
+        metadata_hash: &str,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        staker: &Keypair,
+        extra_signers: &[&Keypair],
+    ) -> Result<FinalizeReport, BatchMintError> {
+        // fail before uploading the canopy if the finalize transaction cannot be signed or would be rejected
+        check_metadata_fields(metadata_url, metadata_hash)?;
+        collection_authority_signer(batch_mint_builder)?;
+        self.check_mining_account(&staker.pubkey()).await?;
+        let canopy_signatures = self
+            .upload_canopy_chunks(payer, batch_mint_builder, tree_creator)
+            .await?;
 
-        let compute_budget = ComputeBudgetInstruction::set_compute_unit_limit(1000000);
+        let finalize_signature = self
+            .send_finalize(
+                payer,
+                metadata_url,
+                metadata_hash,
+                batch_mint_builder,
+                tree_creator,
+                staker,
+                extra_signers,
+            )
+            .await?;
+        Ok(FinalizeReport {
+            canopy_signatures,
+            finalize_signature,
+        })
+    }
 
-        let tx = Transaction::new_signed_with_payer(
-            &[compute_budget, finalize_instruction],
-            Some(&tree_creator.pubkey()),
-            signing_keypairs.as_slice(),
-            self.client.get_latest_blockhash().await?,
-        );
+    /// Builds the same transactions as [BatchMintClient::finalize_tree] does for the given arguments,
+    /// but instead of sending them, runs each one through the transaction simulation,
+    /// so nothing is spent. Allows to catch e.g. [FinalizeFailureReason::NotEnoughStake]
+    /// before committing real SOL, see [FinalizeSimulation::finalize_failure_reason].
+    ///
+    /// Every transaction is simulated against the current state of the accounts:
+    /// simulated AddCanopy transactions do not change it, so for a tree with canopy
+    /// the FinalizeTreeWithRoot simulation only succeeds if the canopy has been already uploaded
+    /// (e.g. with [BatchMintClient::upload_canopy]), otherwise it fails with
+    /// [FinalizeFailureReason::CanopyRootMismatch].
+    ///
+    /// Errors that `finalize_tree` returns before sending anything are returned the same way,
+    /// e.g. [BatchMintError::MiningAccountNotInitialized].
+    ///
+    /// Unlike `finalize_tree` the builder is not sealed, so assets can still be added after a simulation.
+    pub async fn simulate_finalize_tree(
+        &self,
+        payer: &Keypair,
+        metadata_url: &str,
+        metadata_hash: &str,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        staker: &Keypair,
+        extra_signers: &[&Keypair],
+    ) -> Result<FinalizeSimulation, BatchMintError> {
+        check_metadata_fields(metadata_url, metadata_hash)?;
+        collection_authority_signer(batch_mint_builder)?;
+        self.check_mining_account(&staker.pubkey()).await?;
 
-        let signature = self.client.send_and_confirm_transaction(&tx).await?;
+        let mut canopy = Vec::new();
+        for (start_index, chunk) in self.pending_canopy_chunks(batch_mint_builder).await? {
+            let tx = self
+                .add_canopy_transaction(payer, batch_mint_builder, tree_creator, start_index, &chunk)
+                .await?;
+            canopy.push((start_index, self.simulate_transaction(&tx).await?));
+        }
 
-        Ok(signature)
+        let tx = self
+            .finalize_tree_transaction(
+                payer,
+                metadata_url,
+                metadata_hash,
+                batch_mint_builder,
+                tree_creator,
+                staker,
+                extra_signers,
+            )
+            .await?;
+        let finalize = self.simulate_transaction(&tx).await?;
+
+        Ok(FinalizeSimulation { canopy, finalize })
+    }
+
+    async fn simulate_transaction(
+        &self,
+        tx: &Transaction,
+    ) -> std::result::Result<TransactionSimulation, BatchMintError> {
+        let simulation = self.client.simulate_transaction(tx).await?.value;
+        Ok(TransactionSimulation {
+            logs: simulation.logs.unwrap_or_default(),
+            err: simulation.err,
+            units_consumed: simulation.units_consumed,
+        })
     }
 
+    /// Does the same as [BatchMintClient::finalize_tree], but records the canopy upload progress
+    /// to the `checkpoint_path` file after each confirmed AddCanopy transaction,
+    /// so a multi-hour finalization can be continued after a process restart by calling this method again
+    /// with the same file and a builder restored from the batch mint JSON.
+    ///
+    /// On restart the upload continues from the checkpointed canopy leaf index.
+    /// Only the canopy leaf nodes of the tree account are fetched to cross-check it:
+    /// if fewer leaf nodes than the checkpoint states match the builder ones,
+    /// [BatchMintError::FinalizeCheckpointMismatch] is returned,
+    /// and a checkpoint of another tree results in [BatchMintError::FinalizeCheckpointTreeMismatch].
+    ///
+    /// The checkpoint file is removed once the tree is finalized.
     #[allow(clippy::too_many_arguments)]
-    pub fn finalize_tree_instruction(
+    pub async fn finalize_tree_resumable(
         &self,
         payer: &Keypair,
-        batch_mint_builder: &BatchMintBuilder,
         metadata_url: &str,
         metadata_hash: &str,
-        remaining_accounts: &[AccountMeta],
-        tree_config_account: Pubkey,
-        staker: Pubkey,
-        tree_creator: Pubkey,
-    ) -> std::result::Result<Instruction, BatchMintError> {
-        let fee_receiver_key = Pubkey::new_from_array(FEE_RECEIVER);
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        staker: &Keypair,
+        extra_signers: &[&Keypair],
+        checkpoint_path: &Path,
+    ) -> Result<Signature, BatchMintError> {
+        // fail before uploading the canopy if the finalize transaction cannot be signed or would be rejected
+        check_metadata_fields(metadata_url, metadata_hash)?;
+        collection_authority_signer(batch_mint_builder)?;
+        self.check_mining_account(&staker.pubkey()).await?;
 
-        let batch_mint = batch_mint_builder.build_batch_mint()?;
-        if let Some(ref collection_config) = batch_mint_builder.collection_config {
-            return Ok(FinalizeTreeWithRootAndCollectionBuilder::new()
-                .merkle_tree(batch_mint.tree_id)
-                .tree_config(tree_config_account)
-                .staker(staker)
-                .fee_receiver(fee_receiver_key)
-                .tree_creator_or_delegate(tree_creator) // Correct?
-                .registrar(pubkey_util::get_registrar_key())
-                .voter(pubkey_util::get_voter_key(
-                    &pubkey_util::get_registrar_key(),
-                    &payer.pubkey(),
-                ))
-                .root(batch_mint.merkle_root)
-                .rightmost_leaf(batch_mint.last_leaf_hash)
-                .rightmost_index((batch_mint.batch_mints.len() as u32).saturating_sub(1))
-                .metadata_url(metadata_url.to_string())
-                .metadata_hash(metadata_hash.to_string())
-                .add_remaining_accounts(remaining_accounts)
-                .log_wrapper(spl_noop::id())
-                .compression_program(spl_account_compression::id())
-                .system_program(system_program::id())
-                .collection_authority(collection_config.collection_authority.pubkey())
-                .collection_mint(collection_config.collection_mint)
-                .collection_edition(collection_config.edition_account)
-                .collection_metadata(collection_config.collection_metadata)
-                .collection_authority_record_pda(collection_config.collection_authority_record_pda)
-                .mining(pubkey_util::get_mining_key(&staker))
-                .payer(payer.pubkey())
-                .instruction());
+        let chunks = match read_finalize_checkpoint(checkpoint_path)? {
+            Some(checkpoint) => {
+                if checkpoint.tree_id != batch_mint_builder.tree_account {
+                    return Err(BatchMintError::FinalizeCheckpointTreeMismatch {
+                        expected: batch_mint_builder.tree_account,
+                        actual: checkpoint.tree_id,
+                    });
+                }
+                batch_mint_builder.seal();
+                let uploaded = self.count_uploaded_canopy_leaves(batch_mint_builder).await?;
+                if uploaded < checkpoint.next_canopy_index as usize {
+                    return Err(BatchMintError::FinalizeCheckpointMismatch {
+                        checkpoint_uploaded: checkpoint.next_canopy_index as usize,
+                        onchain_uploaded: uploaded,
+                    });
+                }
+                batch_mint_builder.canopy_leaves[uploaded..]
+                    .chunks(self.canopy_chunk_size)
+                    .enumerate()
+                    .map(|(ind, chunk)| ((uploaded + ind * self.canopy_chunk_size) as u32, chunk.to_vec()))
+                    .collect()
+            }
+            None => self.canopy_chunks_to_add(batch_mint_builder).await?,
+        };
+
+        // chunks are confirmed in order, so the checkpoint always points right after a contiguous uploaded range
+        let mut confirmed_chunks = futures::stream::iter(chunks.iter().enumerate())
+            .map(|(index, (start_index, chunk))| async move {
+                self.add_canopy_chunk_with_retries(payer, batch_mint_builder, tree_creator, index, *start_index, chunk)
+                    .await
+                    .map(|_| *start_index + chunk.len() as u32)
+            })
+            .buffered(self.canopy_concurrency);
+        while let Some(next_canopy_index) = confirmed_chunks.next().await {
+            write_finalize_checkpoint(
+                checkpoint_path,
+                &FinalizeCheckpoint {
+                    tree_id: batch_mint_builder.tree_account,
+                    next_canopy_index: next_canopy_index?,
+                },
+            )?;
         }
-        Ok(FinalizeTreeWithRootBuilder::new()
+
+        let signature = self
+            .send_finalize(
+                payer,
+                metadata_url,
+                metadata_hash,
+                batch_mint_builder,
+                tree_creator,
+                staker,
+                extra_signers,
+            )
+            .await?;
+
+        match std::fs::remove_file(checkpoint_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(BatchMintError::IoError(e)),
+            _ => {}
+        }
+        Ok(signature)
+    }
+
+    /// Builds the batch mint, uploads its JSON to the given immutable storage,
+    /// and finalizes the tree with the URL and the hash returned by the storage,
+    /// i.e. does the whole flow after all the assets and signatures are added to the builder.
+    ///
+    /// Arguments are the same as for [BatchMintClient::finalize_tree],
+    /// except `metadata_url` and `metadata_hash`, that are provided by the `storage`.
+    pub async fn upload_and_finalize(
+        &self,
+        storage: &dyn BatchMintStorage,
+        payer: &Keypair,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        staker: &Keypair,
+        extra_signers: &[&Keypair],
+    ) -> Result<Signature, BatchMintError> {
+        let batch_mint = batch_mint_builder.build_batch_mint()?;
+        let mut document = Vec::new();
+        batch_mint
+            .write_as_json(&mut document)
+            .map_err(|e| BatchMintError::IoError(e.into()))?;
+
+        let (metadata_url, metadata_hash) = storage.upload(&document).await?;
+
+        self.finalize_tree(
+            payer,
+            &metadata_url,
+            &metadata_hash,
+            batch_mint_builder,
+            tree_creator,
+            staker,
+            extra_signers,
+        )
+        .await
+    }
+
+    /// Finalizes several trees (e.g. a large collection split across multiple prepared trees) concurrently,
+    /// running at most `max_concurrency` [BatchMintClient::finalize_tree] flows at once.
+    ///
+    /// A failure of one job does not affect the others.
+    /// Returns the result of each job, in the same order as `jobs`.
+    pub async fn finalize_many(
+        &self,
+        payer: &Keypair,
+        staker: &Keypair,
+        jobs: Vec<FinalizeJob<'_>>,
+        max_concurrency: usize,
+    ) -> Vec<Result<Signature, BatchMintError>> {
+        futures::stream::iter(jobs)
+            .map(|job| {
+                self.finalize_tree(
+                    payer,
+                    job.metadata_url,
+                    job.metadata_hash,
+                    job.batch_mint_builder,
+                    job.tree_creator,
+                    staker,
+                    job.extra_signers,
+                )
+            })
+            .buffered(max_concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Adds canopy leaf nodes of the batch mint to the prepared tree account using AddCanopy transactions.
+    /// Canopy chunks that are already present in the tree account are skipped,
+    /// so the call can be repeated after a failure.
+    ///
+    /// This is the first (and the most expensive) phase of [BatchMintClient::finalize_tree],
+    /// it can be run separately from [BatchMintClient::send_finalize].
+    ///
+    /// Up to [BatchMintClient::with_canopy_concurrency] AddCanopy transactions are in flight at once.
+    /// If one of them fails, the rest are not awaited, and the repeated call uploads
+    /// all the chunks after the first missing one.
+    ///
+    /// Returns signatures of sent AddCanopy transactions, in the order of canopy chunks.
+    pub async fn upload_canopy(
+        &self,
+        payer: &Keypair,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+    ) -> Result<Vec<Signature>, BatchMintError> {
+        Ok(self
+            .upload_canopy_chunks(payer, batch_mint_builder, tree_creator)
+            .await?
+            .into_iter()
+            .map(|(_, signature)| signature)
+            .collect())
+    }
+
+    /// Implementation of [BatchMintClient::upload_canopy],
+    /// returns each signature paired with the canopy leaf index the chunk starts from.
+    async fn upload_canopy_chunks(
+        &self,
+        payer: &Keypair,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+    ) -> Result<Vec<(u32, Signature)>, BatchMintError> {
+        let chunks = self.canopy_chunks_to_add(batch_mint_builder).await?;
+        futures::stream::iter(chunks.iter().enumerate())
+            .map(|(index, (start_index, chunk))| async move {
+                self.add_canopy_chunk_with_retries(payer, batch_mint_builder, tree_creator, index, *start_index, chunk)
+                    .await
+                    .map(|signature| (*start_index, signature))
+            })
+            .buffered(self.canopy_concurrency)
+            .try_collect()
+            .await
+    }
+
+    /// Sends the final FinalizeTreeWithRoot transaction, that is the last phase of [BatchMintClient::finalize_tree].
+    /// The canopy is expected to be already uploaded with [BatchMintClient::upload_canopy],
+    /// if it is not, [BatchMintError::CanopyIncomplete] is returned without sending the transaction.
+    /// The same way [BatchMintError::MiningAccountNotInitialized] is returned if the staker has no mining account.
+    ///
+    /// Arguments are the same as for [BatchMintClient::finalize_tree].
+    pub async fn send_finalize(
+        &self,
+        payer: &Keypair,
+        metadata_url: &str,
+        metadata_hash: &str,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        staker: &Keypair,
+        extra_signers: &[&Keypair],
+    ) -> Result<Signature, BatchMintError> {
+        self.check_mining_account(&staker.pubkey()).await?;
+
+        let tree_data_account = get_tree_account(&self.client, &batch_mint_builder.tree_account).await?;
+        let tree_data_info = TreeDataInfo::from_bytes(tree_data_account.data())?;
+        if tree_data_info.canopy_depth > 0 {
+            let existing_canopy = tree_data_info.non_empty_canopy_leaves()?;
+            let uploaded = existing_canopy
+                .iter()
+                .zip(batch_mint_builder.canopy_leaves.iter())
+                .take_while(|(existing, expected)| **existing == *expected)
+                .count();
+            if uploaded != batch_mint_builder.canopy_leaves.len() || existing_canopy.len() != uploaded {
+                return Err(BatchMintError::CanopyIncomplete {
+                    uploaded,
+                    expected: batch_mint_builder.canopy_leaves.len(),
+                });
+            }
+        }
+
+        self.send_finalize_tree_transaction(
+            payer,
+            metadata_url,
+            metadata_hash,
+            batch_mint_builder,
+            tree_creator,
+            staker,
+            extra_signers,
+        )
+        .await
+    }
+
+    /// Does the same as [BatchMintClient::finalize_tree], but instead of awaiting for the whole flow,
+    /// returns a stream of events that allows to observe each transaction as soon as it is confirmed:
+    /// [FinalizeEvent::CanopyChunkConfirmed] for each AddCanopy transaction,
+    /// and [FinalizeEvent::Finalized] for the final FinalizeTreeWithRoot transaction.
+    ///
+    /// The stream ends after the first error. Errors that `finalize_tree` returns before sending anything,
+    /// e.g. [BatchMintError::MiningAccountNotInitialized], are returned as the first item of the stream.
+    pub fn finalize_tree_stream<'a>(
+        &'a self,
+        payer: &'a Keypair,
+        metadata_url: &'a str,
+        metadata_hash: &'a str,
+        batch_mint_builder: &'a BatchMintBuilder,
+        tree_creator: &'a Keypair,
+        staker: &'a Keypair,
+        extra_signers: &'a [&'a Keypair],
+    ) -> impl Stream<Item = std::result::Result<FinalizeEvent, BatchMintError>> + 'a {
+        futures::stream::unfold(FinalizeStreamState::Start, move |mut state| async move {
+            loop {
+                match state {
+                    FinalizeStreamState::Start => {
+                        // the same checks finalize_tree does before sending anything
+                        let chunks = async {
+                            check_metadata_fields(metadata_url, metadata_hash)?;
+                            collection_authority_signer(batch_mint_builder)?;
+                            self.check_mining_account(&staker.pubkey()).await?;
+                            self.canopy_chunks_to_add(batch_mint_builder).await
+                        }
+                        .await;
+                        match chunks {
+                            Ok(chunks) => state = FinalizeStreamState::Canopy(chunks.into_iter().enumerate()),
+                            Err(e) => return Some((Err(e), FinalizeStreamState::Done)),
+                        }
+                    }
+                    FinalizeStreamState::Canopy(mut chunks) => match chunks.next() {
+                        Some((index, (start_index, chunk))) => {
+                            return match self
+                                .add_canopy_chunk_with_retries(
+                                    payer,
+                                    batch_mint_builder,
+                                    tree_creator,
+                                    index,
+                                    start_index,
+                                    &chunk,
+                                )
+                                .await
+                            {
+                                Ok(signature) => Some((
+                                    Ok(FinalizeEvent::CanopyChunkConfirmed { index, signature }),
+                                    FinalizeStreamState::Canopy(chunks),
+                                )),
+                                Err(e) => Some((Err(e), FinalizeStreamState::Done)),
+                            };
+                        }
+                        None => state = FinalizeStreamState::Finalize,
+                    },
+                    FinalizeStreamState::Finalize => {
+                        let result = self
+                            .send_finalize_tree_transaction(
+                                payer,
+                                metadata_url,
+                                metadata_hash,
+                                batch_mint_builder,
+                                tree_creator,
+                                staker,
+                                extra_signers,
+                            )
+                            .await
+                            .map(|signature| FinalizeEvent::Finalized { signature });
+                        return Some((result, FinalizeStreamState::Done));
+                    }
+                    FinalizeStreamState::Done => return None,
+                }
+            }
+        })
+    }
+
+    /// Returns canopy leaf nodes that are not yet added to the tree account,
+    /// split into chunks that fit a single AddCanopy transaction.
+    /// Each chunk is paired with the canopy index it starts from.
+    async fn canopy_chunks_to_add(
+        &self,
+        batch_mint_builder: &BatchMintBuilder,
+    ) -> std::result::Result<Vec<(u32, Vec<Node>)>, BatchMintError> {
+        // canopy of the builder is going to be uploaded, it must not be changed anymore
+        batch_mint_builder.seal();
+        self.pending_canopy_chunks(batch_mint_builder).await
+    }
+
+    /// Same as [Self::canopy_chunks_to_add] but leaves the builder unsealed,
+    /// used when nothing is going to be sent.
+    async fn pending_canopy_chunks(
+        &self,
+        batch_mint_builder: &BatchMintBuilder,
+    ) -> std::result::Result<Vec<(u32, Vec<Node>)>, BatchMintError> {
+        let tree_data_account = get_tree_account(&self.client, &batch_mint_builder.tree_account).await?;
+        let tree_data_info = TreeDataInfo::from_bytes(tree_data_account.data())?;
+
+        if tree_data_info.canopy_depth == 0 {
+            return Ok(Vec::new());
+        }
+
+        let (canopy_to_add, canopy_offset) = calc_canopy_to_add(&tree_data_info, batch_mint_builder)?;
+        Ok(canopy_to_add
+            .chunks(self.canopy_chunk_size)
+            .enumerate()
+            .map(|(ind, chunk)| ((canopy_offset + ind * self.canopy_chunk_size) as u32, chunk.to_vec()))
+            .collect())
+    }
+
+    /// Makes sure the mining account of the staker, required by the finalize instruction, exists:
+    /// it is owned by the rewards program and belongs to the staker.
+    /// Otherwise the finalize transaction would fail with an obscure error.
+    async fn check_mining_account(&self, staker: &Pubkey) -> std::result::Result<(), BatchMintError> {
+        let mining_account = pubkey_util::get_mining_key(staker);
+        let initialized = self
+            .client
+            .get_account_with_commitment(&mining_account, self.client.commitment())
+            .await?
+            .value
+            .is_some_and(|account| {
+                account.owner == mplx_rewards::ID
+                    && account
+                        .data
+                        .get(pubkey_util::MINING_OWNER_OFFSET..pubkey_util::MINING_OWNER_OFFSET + 32)
+                        == Some(staker.as_ref())
+            });
+        if !initialized {
+            return Err(BatchMintError::MiningAccountNotInitialized {
+                mining_account,
+                staker: *staker,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns how many leading canopy leaf nodes of the tree account match the builder ones.
+    /// Unlike [TreeDataInfo], it fetches only the canopy leaf nodes, not the whole tree account.
+    async fn count_uploaded_canopy_leaves(
+        &self,
+        batch_mint_builder: &BatchMintBuilder,
+    ) -> std::result::Result<usize, BatchMintError> {
+        let canopy_leaves = &batch_mint_builder.canopy_leaves;
+        if canopy_leaves.is_empty() {
+            return Ok(0);
+        }
+        let TreeParams {
+            max_depth,
+            max_buffer_size,
+            canopy_depth,
+        } = batch_mint_builder.tree_params();
+        let account_size = calc_tree_data_account_size(max_depth, max_buffer_size, canopy_depth)
+            .ok_or(BatchMintError::UnexpectedTreeSize(max_depth, max_buffer_size))?;
+        let canopy_leaves_offset = account_size - (1usize << canopy_depth) * size_of::<Node>();
+
+        let account = self
+            .client
+            .get_account_with_config(
+                &batch_mint_builder.tree_account,
+                RpcAccountInfoConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    data_slice: Some(UiDataSliceConfig {
+                        offset: canopy_leaves_offset,
+                        length: canopy_leaves.len() * size_of::<Node>(),
+                    }),
+                    commitment: Some(self.client.commitment()),
+                    min_context_slot: None,
+                },
+            )
+            .await?
+            .value
+            .ok_or(BatchMintError::TreeNotPrepared {
+                tree_account: batch_mint_builder.tree_account,
+            })?;
+
+        Ok(account
+            .data()
+            .chunks_exact(size_of::<Node>())
+            .zip(canopy_leaves.iter())
+            .take_while(|(existing, expected)| *existing == expected.as_slice())
+            .count())
+    }
+
+    /// Sends AddCanopy transaction for the chunk number `chunk_index` of the canopy leaf nodes,
+    /// retrying it up to the configured number of times, see [BatchMintClient::with_canopy_retries].
+    /// The error of the last attempt is wrapped into [BatchMintError::CanopyUploadFailed].
+    async fn add_canopy_chunk_with_retries(
+        &self,
+        payer: &Keypair,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        chunk_index: usize,
+        start_index: u32,
+        canopy_nodes: &[Node],
+    ) -> std::result::Result<Signature, BatchMintError> {
+        let mut attempt = 0;
+        loop {
+            match self
+                .add_canopy_chunk(payer, batch_mint_builder, tree_creator, start_index, canopy_nodes)
+                .await
+            {
+                Ok(signature) => return Ok(signature),
+                Err(e) if attempt >= self.canopy_retries => {
+                    return Err(BatchMintError::CanopyUploadFailed {
+                        chunk_index,
+                        source: Box::new(e),
+                    })
+                }
+                Err(_) => attempt += 1,
+            }
+        }
+    }
+
+    /// Sends AddCanopy transaction for the given chunk of canopy leaf nodes.
+    /// `payer` is the transaction fee payer.
+    async fn add_canopy_chunk(
+        &self,
+        payer: &Keypair,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        start_index: u32,
+        canopy_nodes: &[Node],
+    ) -> std::result::Result<Signature, BatchMintError> {
+        let tx = self
+            .add_canopy_transaction(payer, batch_mint_builder, tree_creator, start_index, canopy_nodes)
+            .await?;
+        self.send_and_confirm_transaction(&tx).await
+    }
+
+    /// Builds signed AddCanopy transaction for the given chunk of canopy leaf nodes.
+    async fn add_canopy_transaction(
+        &self,
+        payer: &Keypair,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        start_index: u32,
+        canopy_nodes: &[Node],
+    ) -> std::result::Result<Transaction, BatchMintError> {
+        let instructions =
+            self.add_canopy_instructions(batch_mint_builder, tree_creator.pubkey(), start_index, canopy_nodes);
+
+        Ok(Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&payer.pubkey()),
+            &[payer, tree_creator],
+            self.client.get_latest_blockhash().await?,
+        ))
+    }
+
+    /// Instructions of AddCanopy transaction: compute budget ones followed by AddCanopy.
+    fn add_canopy_instructions(
+        &self,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: Pubkey,
+        start_index: u32,
+        canopy_nodes: &[Node],
+    ) -> Vec<Instruction> {
+        let tree_config_account = pubkey_util::derive_tree_config_account(&batch_mint_builder.tree_account);
+
+        let mut instructions = self.compute_budget_instructions(self.canopy_compute_unit_limit);
+        let add_canopy_inst = AddCanopyBuilder::new()
+            .tree_config(tree_config_account)
+            .merkle_tree(batch_mint_builder.tree_account)
+            .tree_creator_or_delegate(tree_creator) // Correct?
+            .canopy_nodes(canopy_nodes.to_vec())
+            .start_index(start_index)
+            .log_wrapper(spl_noop::id())
+            .compression_program(spl_account_compression::id())
+            .system_program(system_program::id())
+            .instruction();
+        instructions.push(add_canopy_inst);
+        instructions
+    }
+
+    /// ComputeBudget instructions a transaction starts with: the compute unit limit,
+    /// and the compute unit price if the priority fee is set, see [BatchMintClient::with_priority_fee].
+    fn compute_budget_instructions(&self, compute_unit_limit: u32) -> Vec<Instruction> {
+        let mut instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(compute_unit_limit)];
+        if let Some(micro_lamports) = self.priority_fee {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_price(micro_lamports));
+        }
+        instructions
+    }
+
+    /// Sends the final FinalizeTreeWithRoot (or FinalizeTreeWithRootAndCollection) transaction.
+    /// Canopy (if any) is expected to be already added.
+    async fn send_finalize_tree_transaction(
+        &self,
+        payer: &Keypair,
+        metadata_url: &str,
+        metadata_hash: &str,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        staker: &Keypair,
+        extra_signers: &[&Keypair],
+    ) -> std::result::Result<Signature, BatchMintError> {
+        batch_mint_builder.seal();
+
+        let tx = self
+            .finalize_tree_transaction(
+                payer,
+                metadata_url,
+                metadata_hash,
+                batch_mint_builder,
+                tree_creator,
+                staker,
+                extra_signers,
+            )
+            .await?;
+        self.send_and_confirm_transaction(&tx).await
+    }
+
+    /// Builds signed FinalizeTreeWithRoot (or FinalizeTreeWithRootAndCollection) transaction.
+    async fn finalize_tree_transaction(
+        &self,
+        payer: &Keypair,
+        metadata_url: &str,
+        metadata_hash: &str,
+        batch_mint_builder: &BatchMintBuilder,
+        tree_creator: &Keypair,
+        staker: &Keypair,
+        extra_signers: &[&Keypair],
+    ) -> std::result::Result<Transaction, BatchMintError> {
+        let (instructions, signing_keypairs) = self.finalize_tree_instructions(
+            payer,
+            metadata_url,
+            metadata_hash,
+            batch_mint_builder,
+            tree_creator,
+            staker,
+            extra_signers,
+        )?;
+
+        Ok(Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&payer.pubkey()),
+            signing_keypairs.as_slice(),
+            self.client.get_latest_blockhash().await?,
+        ))
+    }
+
+    /// Instructions of the finalize transaction: compute budget ones followed by FinalizeTreeWithRoot
+    /// (or FinalizeTreeWithRootAndCollection), together with the keypairs the transaction is signed with.
+    fn finalize_tree_instructions<'a>(
+        &self,
+        payer: &'a Keypair,
+        metadata_url: &str,
+        metadata_hash: &str,
+        batch_mint_builder: &'a BatchMintBuilder,
+        tree_creator: &'a Keypair,
+        staker: &'a Keypair,
+        extra_signers: &[&'a Keypair],
+    ) -> std::result::Result<(Vec<Instruction>, Vec<&'a dyn Signer>), BatchMintError> {
+        let tree_config_account = pubkey_util::derive_tree_config_account(&batch_mint_builder.tree_account);
+
+        // We're just using remaining_accounts to send proofs because they are of the same type,
+        // the proof nodes covered by the canopy are filled in by the program
+        let remaining_accounts = batch_mint_builder.remaining_accounts();
+        let mut finalize_instruction = self.finalize_tree_instruction(
+            payer,
+            batch_mint_builder,
+            metadata_url,
+            metadata_hash,
+            remaining_accounts.as_slice(),
+            tree_config_account,
+            staker.pubkey(),
+            tree_creator.pubkey(),
+        )?;
+        let mut signing_keypairs: Vec<&dyn Signer> = vec![payer, tree_creator, staker];
+        if let Some(collection_authority) = collection_authority_signer(batch_mint_builder)? {
+            signing_keypairs.push(collection_authority);
+        }
+        for extra_signer in extra_signers {
+            let extra_signer_key = extra_signer.pubkey();
+            match finalize_instruction
+                .accounts
+                .iter_mut()
+                .find(|account| account.pubkey == extra_signer_key)
+            {
+                Some(account) => account.is_signer = true,
+                None => finalize_instruction
+                    .accounts
+                    .push(AccountMeta::new_readonly(extra_signer_key, true)),
+            }
+            signing_keypairs.push(*extra_signer);
+        }
+
+        let mut instructions = self.compute_budget_instructions(self.compute_unit_limit);
+        instructions.push(finalize_instruction);
+        Ok((instructions, signing_keypairs))
+    }
+
+    /// Checks a finalize transaction, that has been signed externally (e.g. by a hardware wallet),
+    /// before it is broadcasted:
+    /// * signatures of the transaction are valid
+    /// * it contains FinalizeTreeWithRoot (or FinalizeTreeWithRootAndCollection if collection config is set)
+    ///   instruction for the builder tree and its tree config account
+    /// * root, rightmost leaf and rightmost index of the instruction match the builder ones,
+    ///   and metadata URL and hash are the given ones
+    /// * remaining accounts are exactly [BatchMintBuilder::remaining_accounts], the ones finalize is sent with,
+    ///   followed by nothing but the extra signers
+    ///
+    /// [BatchMintError::InvalidFinalizeTransaction] describing the first mismatch is returned otherwise.
+    pub fn verify_signed_finalize(
+        &self,
+        tx: &Transaction,
+        batch_mint_builder: &BatchMintBuilder,
+        metadata_url: &str,
+        metadata_hash: &str,
+    ) -> std::result::Result<(), BatchMintError> {
+        let invalid = |reason: &str| BatchMintError::InvalidFinalizeTransaction(reason.to_string());
+
+        tx.verify()
+            .map_err(|e| invalid(&format!("signature verification failed: {}", e)))?;
+
+        let instruction_name = if batch_mint_builder.collection_config.is_some() {
+            "finalize_tree_with_root_and_collection"
+        } else {
+            "finalize_tree_with_root"
+        };
+        let discriminator = anchor_instruction_discriminator(instruction_name);
+        let message = &tx.message;
+        let finalize_instruction = message
+            .instructions
+            .iter()
+            .find(|instruction| {
+                message.account_keys.get(instruction.program_id_index as usize) == Some(&mpl_bubblegum::ID)
+                    && instruction.data.starts_with(&discriminator)
+            })
+            .ok_or_else(|| invalid(&format!("{} instruction is not found", instruction_name)))?;
+
+        let (root, rightmost_leaf, rightmost_index, tx_metadata_url, tx_metadata_hash) =
+            if batch_mint_builder.collection_config.is_some() {
+                let args = FinalizeTreeWithRootAndCollectionInstructionArgs::try_from_slice(
+                    &finalize_instruction.data[discriminator.len()..],
+                )
+                .map_err(|e| invalid(&format!("cannot parse instruction arguments: {}", e)))?;
+                (
+                    args.root,
+                    args.rightmost_leaf,
+                    args.rightmost_index,
+                    args.metadata_url,
+                    args.metadata_hash,
+                )
+            } else {
+                let args = FinalizeTreeWithRootInstructionArgs::try_from_slice(
+                    &finalize_instruction.data[discriminator.len()..],
+                )
+                .map_err(|e| invalid(&format!("cannot parse instruction arguments: {}", e)))?;
+                (
+                    args.root,
+                    args.rightmost_leaf,
+                    args.rightmost_index,
+                    args.metadata_url,
+                    args.metadata_hash,
+                )
+            };
+
+        batch_mint_builder.build_batch_mint()?;
+        let expected = batch_mint_builder.finalize_args(metadata_url, metadata_hash);
+        if root != expected.root {
+            return Err(invalid(&format!(
+                "root {} does not match {}",
+                node_to_string(&root),
+                node_to_string(&expected.root)
+            )));
+        }
+        if rightmost_leaf != expected.rightmost_leaf {
+            return Err(invalid(&format!(
+                "rightmost leaf {} does not match {}",
+                node_to_string(&rightmost_leaf),
+                node_to_string(&expected.rightmost_leaf)
+            )));
+        }
+        if rightmost_index != expected.rightmost_index {
+            return Err(invalid("rightmost index does not match"));
+        }
+        if tx_metadata_url != expected.metadata_url {
+            return Err(invalid("metadata URL does not match"));
+        }
+        if tx_metadata_hash != expected.metadata_hash {
+            return Err(invalid("metadata hash does not match"));
+        }
+
+        let instruction_accounts = finalize_instruction
+            .accounts
+            .iter()
+            .map(|index| message.account_keys.get(*index as usize).copied())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("instruction refers to a missing account"))?;
+        let tree_config_account = pubkey_util::derive_tree_config_account(&batch_mint_builder.tree_account);
+        if !instruction_accounts.contains(&batch_mint_builder.tree_account) {
+            return Err(invalid("merkle tree account is missing"));
+        }
+        if !instruction_accounts.contains(&tree_config_account) {
+            return Err(invalid("tree config account is missing"));
+        }
+        // the accounts preceding the remaining ones do not depend on the keys the instruction is built with
+        let fixed_accounts_len = self
+            .finalize_tree_instruction_with_pubkeys(
+                Pubkey::default(),
+                batch_mint_builder,
+                metadata_url,
+                metadata_hash,
+                &[],
+                tree_config_account,
+                Pubkey::default(),
+                Pubkey::default(),
+            )?
+            .accounts
+            .len();
+        let remaining_accounts = batch_mint_builder
+            .remaining_accounts()
+            .iter()
+            .map(|account| account.pubkey)
+            .collect::<Vec<_>>();
+        let remaining_accounts_end = fixed_accounts_len + remaining_accounts.len();
+        if instruction_accounts.get(fixed_accounts_len..remaining_accounts_end) != Some(remaining_accounts.as_slice()) {
+            return Err(invalid("rightmost proof does not match"));
+        }
+        if finalize_instruction.accounts[remaining_accounts_end..]
+            .iter()
+            .any(|index| !message.is_signer(*index as usize))
+        {
+            return Err(invalid("unexpected accounts follow the rightmost proof"));
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn finalize_tree_instruction(
+        &self,
+        payer: &Keypair,
+        batch_mint_builder: &BatchMintBuilder,
+        metadata_url: &str,
+        metadata_hash: &str,
+        remaining_accounts: &[AccountMeta],
+        tree_config_account: Pubkey,
+        staker: Pubkey,
+        tree_creator: Pubkey,
+    ) -> std::result::Result<Instruction, BatchMintError> {
+        self.finalize_tree_instruction_with_pubkeys(
+            payer.pubkey(),
+            batch_mint_builder,
+            metadata_url,
+            metadata_hash,
+            remaining_accounts,
+            tree_config_account,
+            staker,
+            tree_creator,
+        )
+    }
+
+    /// Same as [BatchMintClient::finalize_tree_instruction], but requires only the public key of the payer.
+    /// Together with [crate::model::CollectionAuthority::Pubkey] collection authority it allows to build the finalize
+    /// instruction without any private key in memory, for a transaction that is signed externally
+    /// and then checked with [BatchMintClient::verify_signed_finalize].
+    #[allow(clippy::too_many_arguments)]
+    pub fn finalize_tree_instruction_with_pubkeys(
+        &self,
+        payer: Pubkey,
+        batch_mint_builder: &BatchMintBuilder,
+        metadata_url: &str,
+        metadata_hash: &str,
+        remaining_accounts: &[AccountMeta],
+        tree_config_account: Pubkey,
+        staker: Pubkey,
+        tree_creator: Pubkey,
+    ) -> std::result::Result<Instruction, BatchMintError> {
+        check_metadata_fields(metadata_url, metadata_hash)?;
+        let fee_receiver_key = Pubkey::new_from_array(FEE_RECEIVER);
+
+        // makes sure the batch mint is complete, i.e. has all the required signatures
+        let batch_mint = batch_mint_builder.build_batch_mint()?;
+        let FinalizeArgs {
+            root,
+            rightmost_leaf,
+            rightmost_index,
+            metadata_url,
+            metadata_hash,
+        } = batch_mint_builder.finalize_args(metadata_url, metadata_hash);
+        if let Some(ref collection_config) = batch_mint_builder.collection_config {
+            return Ok(FinalizeTreeWithRootAndCollectionBuilder::new()
+                .merkle_tree(batch_mint.tree_id)
+                .tree_config(tree_config_account)
+                .staker(staker)
+                .fee_receiver(fee_receiver_key)
+                .tree_creator_or_delegate(tree_creator) // Correct?
+                .registrar(pubkey_util::get_registrar_key())
+                .voter(pubkey_util::get_voter_key(&pubkey_util::get_registrar_key(), &staker))
+                .root(root)
+                .rightmost_leaf(rightmost_leaf)
+                .rightmost_index(rightmost_index)
+                .metadata_url(metadata_url)
+                .metadata_hash(metadata_hash)
+                .add_remaining_accounts(remaining_accounts)
+                .log_wrapper(spl_noop::id())
+                .compression_program(spl_account_compression::id())
+                .system_program(system_program::id())
+                .collection_authority(collection_config.collection_authority.pubkey())
+                .collection_mint(collection_config.collection_mint)
+                .collection_edition(collection_config.edition_account)
+                .collection_metadata(collection_config.collection_metadata)
+                .collection_authority_record_pda(collection_config.collection_authority_record_pda)
+                .mining(pubkey_util::get_mining_key(&staker))
+                .payer(payer)
+                .instruction());
+        }
+        Ok(FinalizeTreeWithRootBuilder::new()
             .merkle_tree(batch_mint.tree_id)
             .tree_config(tree_config_account)
             .staker(staker)
             .fee_receiver(fee_receiver_key)
             .tree_creator_or_delegate(tree_creator) // Correct?
             .registrar(pubkey_util::get_registrar_key())
-            .voter(pubkey_util::get_voter_key(
-                &pubkey_util::get_registrar_key(),
-                &payer.pubkey(),
-            ))
-            .root(batch_mint.merkle_root)
-            .rightmost_leaf(batch_mint.last_leaf_hash)
-            .rightmost_index((batch_mint.batch_mints.len() as u32).saturating_sub(1))
-            .metadata_url(metadata_url.to_string())
-            .metadata_hash(metadata_hash.to_string())
+            .voter(pubkey_util::get_voter_key(&pubkey_util::get_registrar_key(), &staker))
+            .root(root)
+            .rightmost_leaf(rightmost_leaf)
+            .rightmost_index(rightmost_index)
+            .metadata_url(metadata_url)
+            .metadata_hash(metadata_hash)
             .add_remaining_accounts(remaining_accounts)
             .log_wrapper(spl_noop::id())
             .compression_program(spl_account_compression::id())
             .system_program(system_program::id())
             .mining(pubkey_util::get_mining_key(&staker))
-            .payer(payer.pubkey())
+            .payer(payer)
             .instruction())
     }
 }
 
+#[cfg(feature = "offchain-metadata")]
+async fn check_offchain_metadata(http_client: &reqwest::Client, metadata_args: &MetadataArgs) -> MetadataCheck {
+    let response = match http_client
+        .get(metadata_args.uri.trim_end_matches('\0'))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+    {
+        Ok(response) => response,
+        Err(e) => return MetadataCheck::Unreachable(e.to_string()),
+    };
+    let json = match response.json::<serde_json::Value>().await {
+        Ok(json) => json,
+        Err(e) => return MetadataCheck::InvalidJson(e.to_string()),
+    };
+
+    for (field, expected) in [("name", &metadata_args.name), ("symbol", &metadata_args.symbol)] {
+        let expected = expected.trim_end_matches('\0');
+        let actual = json.get(field).and_then(|value| value.as_str());
+        if actual != Some(expected) {
+            return MetadataCheck::Mismatch {
+                field: field.to_string(),
+                expected: expected.to_string(),
+                actual: actual.map(str::to_string),
+            };
+        }
+    }
+
+    MetadataCheck::Valid
+}
+
+/// Makes sure `metadata_url` and `metadata_hash` are not longer than the finalize instruction accepts,
+/// see [MAX_METADATA_URL_LEN] and [MAX_METADATA_HASH_LEN].
+/// Otherwise [BatchMintError::MetadataFieldTooLong] is returned.
+fn check_metadata_fields(metadata_url: &str, metadata_hash: &str) -> std::result::Result<(), BatchMintError> {
+    for (field, value, max_len) in [
+        ("metadata_url", metadata_url, MAX_METADATA_URL_LEN),
+        ("metadata_hash", metadata_hash, MAX_METADATA_HASH_LEN),
+    ] {
+        if value.len() > max_len {
+            return Err(BatchMintError::MetadataFieldTooLong {
+                field: field.to_string(),
+                len: value.len(),
+                max_len,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Weighted stake of a voter, as calculated by the Bubblegum program to check the stake requirement:
+/// the sum of the amounts of used deposits with a lockup,
+/// each multiplied by the multiplier of its lockup period (see [mplx_staking_states::state::LockupPeriod::multiplier]).
+pub fn weighted_stake(voter: &Voter) -> u64 {
+    voter
+        .deposits
+        .iter()
+        .filter(|deposit| deposit.is_used && !matches!(deposit.lockup.kind, LockupKind::None))
+        .map(|deposit| {
+            deposit
+                .amount_deposited_native
+                .saturating_mul(deposit.lockup.period.multiplier())
+        })
+        .fold(0, u64::saturating_add)
+}
+
+/// Parses a voter account of the staking program, None if it is not a voter account.
+fn parse_voter(account: &Account) -> Option<Voter> {
+    if account.owner != mplx_staking_states::ID {
+        return None;
+    }
+    let data = account.data.strip_prefix(&pubkey_util::VOTER_DISCRIMINATOR)?;
+    bytemuck::try_pod_read_unaligned(data.get(..std::mem::size_of::<Voter>())?).ok()
+}
+
+/// Signer of the collection authority of the builder collection config, if the config is set.
+/// Returns [BatchMintError::CollectionAuthoritySignerMissing] if only the public key of the authority is known.
+fn collection_authority_signer(
+    batch_mint_builder: &BatchMintBuilder,
+) -> std::result::Result<Option<&dyn Signer>, BatchMintError> {
+    let Some(ref collection_config) = batch_mint_builder.collection_config else {
+        return Ok(None);
+    };
+    let collection_authority = &collection_config.collection_authority;
+    collection_authority
+        .signer()
+        .map(Some)
+        .ok_or_else(|| BatchMintError::CollectionAuthoritySignerMissing {
+            collection_authority: collection_authority.pubkey(),
+        })
+}
+
+/// First 8 bytes of an anchor instruction data, that identify the instruction.
+fn anchor_instruction_discriminator(instruction_name: &str) -> [u8; 8] {
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash(format!("global:{}", instruction_name).as_bytes()).to_bytes()[..8]);
+    discriminator
+}
+
+/// Interprets the error of an instruction simulated without accounts and arguments, see
+/// [BatchMintClient::probe_program_features]. The error is returned back if the simulation failed
+/// before the instruction was run, e.g. the fee payer does not exist.
+fn instruction_supported(err: Option<TransactionError>) -> std::result::Result<bool, TransactionError> {
+    match err {
+        Some(TransactionError::InstructionError(0, InstructionError::Custom(code)))
+            if code == anchor_lang::error::ErrorCode::InstructionFallbackNotFound as u32 =>
+        {
+            Ok(false)
+        }
+        Some(TransactionError::InstructionError(0, InstructionError::InvalidInstructionData)) => Ok(false),
+        Some(TransactionError::InstructionError(..)) | None => Ok(true),
+        Some(err) => Err(err),
+    }
+}
+
 /// Fetches max depth, max buffer size and canopy_depth for a tree identified by given account.
 async fn read_prepared_tree_size(
     client: &RpcClient,
     tree_accout: &Pubkey,
-) -> std::result::Result<(u32, u32, u32), BatchMintError> {
-    let account = client.get_account(tree_accout).await?;
+) -> std::result::Result<TreeParams, BatchMintError> {
+    let account = get_tree_account(client, tree_accout).await?;
     parse_tree_size(&account)
 }
 
-fn parse_tree_size(tree_account: &Account) -> std::result::Result<(u32, u32, u32), BatchMintError> {
+/// Fetches the tree data account, returns [BatchMintError::TreeNotPrepared] if the account does not exist.
+async fn get_tree_account(client: &RpcClient, tree_account: &Pubkey) -> std::result::Result<Account, BatchMintError> {
+    client
+        .get_account_with_commitment(tree_account, client.commitment())
+        .await?
+        .value
+        .ok_or(BatchMintError::TreeNotPrepared {
+            tree_account: *tree_account,
+        })
+}
+
+/// Checks the tree params are acceptable for a tree prepared by [BatchMintClient::prepare_tree],
+/// and returns the size of the tree data account for them.
+fn checked_tree_data_account_size(
+    max_depth: u32,
+    max_buf_size: u32,
+    canopy_depth: u32,
+) -> std::result::Result<usize, BatchMintError> {
+    if canopy_depth >= max_depth {
+        return Err(BatchMintError::IllegalArgumets(
+            "Canopy depth should be less than tree maximum depth".to_string(),
+        ));
+    }
+
+    let required_canopy = max_depth.saturating_sub(mpl_bubblegum::MAX_ACC_PROOFS_SIZE);
+    if canopy_depth < required_canopy {
+        return Err(BatchMintError::IllegalArgumets(format!(
+            "Three of depth={max_depth} requires as least canopy={required_canopy}"
+        )));
+    }
+
+    let merkle_tree_size = calc_tree_data_account_size(max_depth, max_buf_size, canopy_depth)
+        .ok_or(BatchMintError::UnexpectedTreeSize(max_depth, max_buf_size))?;
+    if merkle_tree_size as u64 > MAX_PERMITTED_DATA_LENGTH {
+        return Err(BatchMintError::AccountTooLarge {
+            size: merkle_tree_size,
+            max: MAX_PERMITTED_DATA_LENGTH as usize,
+        });
+    }
+    Ok(merkle_tree_size)
+}
+
+/// Reads the checkpoint written by [BatchMintClient::finalize_tree_resumable], if there is one.
+fn read_finalize_checkpoint(path: &Path) -> std::result::Result<Option<FinalizeCheckpoint>, BatchMintError> {
+    match std::fs::File::open(path) {
+        Ok(file) => FinalizeCheckpoint::read_as_json(file)
+            .map(Some)
+            .map_err(|e| BatchMintError::IoError(e.into())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(BatchMintError::IoError(e)),
+    }
+}
+
+/// Writes the checkpoint to a temporary file first and then renames it,
+/// so a crash in the middle of writing does not leave a corrupted checkpoint.
+fn write_finalize_checkpoint(path: &Path, checkpoint: &FinalizeCheckpoint) -> std::result::Result<(), BatchMintError> {
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(std::fs::File::create(&tmp_path).map_err(BatchMintError::IoError)?);
+    checkpoint
+        .write_as_json(&mut writer)
+        .map_err(|e| BatchMintError::IoError(e.into()))?;
+    writer
+        .into_inner()
+        .map_err(|e| BatchMintError::IoError(e.into_error()))?
+        .sync_all()
+        .map_err(BatchMintError::IoError)?;
+    std::fs::rename(&tmp_path, path).map_err(BatchMintError::IoError)
+}
+
+fn parse_tree_size(tree_account: &Account) -> std::result::Result<TreeParams, BatchMintError> {
+    check_header_version(tree_account.data())?;
     let merkle_tree = MerkleTree::from_bytes(tree_account.data())?;
     let (max_depth, max_buffer_size) = match merkle_tree.tree_header {
         ConcurrentMerkleTreeHeaderData::V1 {
@@ -371,7 +2049,73 @@ fn parse_tree_size(tree_account: &Account) -> std::result::Result<(u32, u32, u32
         .ok_or(BatchMintError::UnexpectedTreeSize(max_depth, max_buffer_size))?;
     let canopy_buf_size = merkle_tree.serialized_tree.len() - merkel_tree_size;
     let canopy_depth = restore_canopy_depth_from_buffer(canopy_buf_size as u32);
-    Ok((max_depth, max_buffer_size, canopy_depth))
+    Ok(TreeParams {
+        max_depth,
+        max_buffer_size,
+        canopy_depth,
+    })
+}
+
+/// Reads `sequence_number` and `buffer_size` of a serialized [spl_account_compression::ConcurrentMerkleTree],
+/// they are the first and the third u64 fields of the tree regardless its depth and buffer size.
+fn parse_tree_counters(serialized_tree: &[u8]) -> std::result::Result<(u64, u64), BatchMintError> {
+    let read_u64 = |offset: usize| {
+        serialized_tree
+            .get(offset..offset + 8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| BatchMintError::GenricErr("Tree data account is too small".to_string()))
+    };
+    Ok((read_u64(0)?, read_u64(16)?))
+}
+
+/// Parts of a serialized [spl_account_compression::ConcurrentMerkleTree] checked by [BatchMintClient::verify_finalized].
+pub(crate) struct TreeState {
+    pub sequence_number: u64,
+    pub root: Node,
+    pub rightmost_proof: Vec<Node>,
+    pub rightmost_leaf: Node,
+}
+
+/// Reads [TreeState] from a serialized [spl_account_compression::ConcurrentMerkleTree] of the given size.
+/// The tree consists of `sequence_number`, `active_index` and `buffer_size` u64 fields,
+/// followed by the changelog buffer and the rightmost proof.
+pub(crate) fn parse_tree_state(
+    serialized_tree: &[u8],
+    max_depth: u32,
+    max_buffer_size: u32,
+) -> std::result::Result<TreeState, BatchMintError> {
+    const COUNTERS_SIZE: usize = 3 * size_of::<u64>();
+    let too_small = || BatchMintError::GenricErr("Tree data account is too small".to_string());
+    let read_node = |offset: usize| -> std::result::Result<Node, BatchMintError> {
+        serialized_tree
+            .get(offset..offset + size_of::<Node>())
+            .map(|bytes| bytes.try_into().unwrap())
+            .ok_or_else(too_small)
+    };
+
+    let sequence_number = parse_tree_counters(serialized_tree)?.0;
+    let active_index = serialized_tree
+        .get(8..16)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(too_small)? as usize;
+
+    // root, path of max_depth nodes, index and padding
+    let changelog_size = size_of::<Node>() * (max_depth as usize + 1) + 2 * size_of::<u32>();
+    let root = read_node(COUNTERS_SIZE + active_index * changelog_size)?;
+
+    // rightmost proof is a path of max_depth nodes followed by the leaf
+    let rightmost_proof_offset = COUNTERS_SIZE + max_buffer_size as usize * changelog_size;
+    let rightmost_proof = (0..max_depth as usize)
+        .map(|level| read_node(rightmost_proof_offset + size_of::<Node>() * level))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let rightmost_leaf = read_node(rightmost_proof_offset + size_of::<Node>() * max_depth as usize)?;
+
+    Ok(TreeState {
+        sequence_number,
+        root,
+        rightmost_proof,
+        rightmost_leaf,
+    })
 }
 
 /// Because canopy nodes are added by separate transactions, we may fall into situation when a portion of nodes
@@ -387,6 +2131,10 @@ fn calc_canopy_to_add<'a>(
     let canopy_leaves: &Vec<Node> = &batch_mint_builder.canopy_leaves;
 
     let existing_canopy = tree_data_info.non_empty_canopy_leaves()?;
+    if existing_canopy.len() > canopy_leaves.len() {
+        // the tree contains a canopy of some other batch mint, it has to be overwritten
+        return Ok((canopy_leaves, 0));
+    }
     let (canopy_to_skip, canopy_to_add) = canopy_leaves.split_at(existing_canopy.len());
     for (to_add, existing) in existing_canopy.into_iter().zip(canopy_to_skip) {
         if to_add != existing {
@@ -397,3 +2145,620 @@ fn calc_canopy_to_add<'a>(
 
     Ok((canopy_to_add, canopy_offset))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::CollectionConfig;
+    use mplx_staking_states::state::LockupPeriod;
+    use solana_sdk::hash::Hash;
+
+    #[test]
+    fn test_instruction_supported() {
+        let custom_error = |error_code: anchor_lang::error::ErrorCode| {
+            Some(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(error_code as u32),
+            ))
+        };
+        let instruction_error = |error| Some(TransactionError::InstructionError(0, error));
+
+        // unknown instruction
+        assert_eq!(
+            instruction_supported(custom_error(anchor_lang::error::ErrorCode::InstructionFallbackNotFound)),
+            Ok(false)
+        );
+        assert_eq!(
+            instruction_supported(instruction_error(InstructionError::InvalidInstructionData)),
+            Ok(false)
+        );
+
+        // a known instruction fails on the missing arguments or accounts
+        assert_eq!(
+            instruction_supported(custom_error(
+                anchor_lang::error::ErrorCode::InstructionDidNotDeserialize
+            )),
+            Ok(true)
+        );
+        assert_eq!(
+            instruction_supported(instruction_error(InstructionError::NotEnoughAccountKeys)),
+            Ok(true)
+        );
+
+        // the fee payer cannot pay for the simulation, nothing is known about the instruction
+        assert_eq!(
+            instruction_supported(Some(TransactionError::AccountNotFound)),
+            Err(TransactionError::AccountNotFound)
+        );
+
+        let features = ProgramFeatures {
+            prepare_tree: true,
+            add_canopy: false,
+            finalize_tree_with_root: true,
+            finalize_tree_with_root_and_collection: false,
+        };
+        match features.check_batch_mint_supported() {
+            Err(BatchMintError::BatchMintNotSupported(missing)) => {
+                assert_eq!(missing, "AddCanopy, FinalizeTreeWithRootAndCollection")
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    const METADATA_URL: &str = "http://mymetadata.ololo/";
+    const METADATA_HASH: &str = "mymetadatahash";
+
+    fn make_builder() -> BatchMintBuilder {
+        let mut batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for i in 0u8..3 {
+            let metadata_args = mpl_bubblegum::types::MetadataArgs {
+                name: format!("Test NFT {}", i),
+                symbol: "TNFT".to_string(),
+                uri: format!("https://example.com/nft/{}", i),
+                seller_fee_basis_points: 0,
+                primary_sale_happened: false,
+                is_mutable: false,
+                edition_nonce: None,
+                token_standard: Some(mpl_bubblegum::types::TokenStandard::NonFungible),
+                collection: None,
+                uses: None,
+                token_program_version: mpl_bubblegum::types::TokenProgramVersion::Original,
+                creators: Vec::new(),
+            };
+            batch_mint_builder
+                .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+                .unwrap();
+        }
+        batch_mint_builder
+    }
+
+    fn make_signed_finalize_tx(
+        batch_mint_client: &BatchMintClient,
+        batch_mint_builder: &BatchMintBuilder,
+        metadata_url: &str,
+    ) -> Transaction {
+        make_signed_finalize_tx_with_accounts(
+            batch_mint_client,
+            batch_mint_builder,
+            metadata_url,
+            batch_mint_builder.remaining_accounts(),
+        )
+    }
+
+    fn make_signed_finalize_tx_with_accounts(
+        batch_mint_client: &BatchMintClient,
+        batch_mint_builder: &BatchMintBuilder,
+        metadata_url: &str,
+        remaining_accounts: Vec<AccountMeta>,
+    ) -> Transaction {
+        let payer = Keypair::new();
+        let tree_creator = Keypair::new();
+        let finalize_instruction = batch_mint_client
+            .finalize_tree_instruction(
+                &payer,
+                batch_mint_builder,
+                metadata_url,
+                METADATA_HASH,
+                remaining_accounts.as_slice(),
+                pubkey_util::derive_tree_config_account(&batch_mint_builder.tree_account),
+                payer.pubkey(),
+                tree_creator.pubkey(),
+            )
+            .unwrap();
+        Transaction::new_signed_with_payer(
+            &[finalize_instruction],
+            Some(&payer.pubkey()),
+            &[&payer, &tree_creator],
+            Hash::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_prepare_tree_account_too_large() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+
+        // canopy of depth 20 alone takes 64MB
+        let result = batch_mint_client
+            .prepare_tree(&Keypair::new(), &Keypair::new(), &Keypair::new(), 30, 2048, 20)
+            .await;
+        match result {
+            Err(BatchMintError::AccountTooLarge { size, max }) => {
+                assert_eq!(size, calc_tree_data_account_size(30, 2048, 20).unwrap());
+                assert_eq!(max, 10_485_760);
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_build_prepare_tree_instructions() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        let payer = Pubkey::new_unique();
+        let tree_creator = Pubkey::new_unique();
+        let tree_data_account = Pubkey::new_unique();
+
+        let instructions = batch_mint_client
+            .build_prepare_tree_instructions(payer, tree_creator, tree_data_account, (10, 32, 3), 1_000)
+            .unwrap();
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(instructions[0].program_id, system_program::id());
+        assert_eq!(instructions[1].program_id, mpl_bubblegum::ID);
+
+        let signers = instructions
+            .iter()
+            .flat_map(|instruction| instruction.accounts.iter())
+            .filter(|account| account.is_signer)
+            .map(|account| account.pubkey)
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(signers, [payer, tree_creator, tree_data_account].into_iter().collect());
+
+        assert!(matches!(
+            batch_mint_client.build_prepare_tree_instructions(
+                payer,
+                tree_creator,
+                tree_data_account,
+                (10, 32, 10),
+                1_000
+            ),
+            Err(BatchMintError::IllegalArgumets(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_tree_state() {
+        let mut tree = spl_account_compression::ConcurrentMerkleTree::<5, 8>::new();
+        tree.initialize().unwrap();
+        for i in 1u8..=11 {
+            tree.append([i; 32]).unwrap();
+        }
+
+        let tree_state = parse_tree_state(bytemuck::bytes_of(&tree), 5, 8).unwrap();
+        assert_eq!(tree_state.sequence_number, 11);
+        assert_eq!(tree_state.root, tree.get_root());
+        assert_eq!(tree_state.rightmost_proof, tree.rightmost_proof.proof);
+        assert_eq!(tree_state.rightmost_leaf, [11; 32]);
+
+        assert!(parse_tree_state(&bytemuck::bytes_of(&tree)[..100], 5, 8).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_canopy_upload_failed_after_retries() {
+        // nothing listens on this port, so every attempt fails
+        let batch_mint_client =
+            BatchMintClient::new(Arc::new(RpcClient::new("http://127.0.0.1:1".to_string()))).with_canopy_retries(2);
+        let batch_mint_builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 3)).unwrap();
+
+        let result = batch_mint_client
+            .add_canopy_chunk_with_retries(&Keypair::new(), &batch_mint_builder, &Keypair::new(), 3, 72, &[[1; 32]])
+            .await;
+        match result {
+            Err(BatchMintError::CanopyUploadFailed { chunk_index, source }) => {
+                assert_eq!(chunk_index, 3);
+                assert!(matches!(*source, BatchMintError::SolanaClientErr(_)));
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_canopy_chunk_size() {
+        let make_client = || BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        assert_eq!(make_client().canopy_chunk_size, MAX_CANOPY_NODES_PER_TX);
+
+        for canopy_chunk_size in [1, 10, MAX_CANOPY_NODES_PER_TX] {
+            let batch_mint_client = make_client().with_canopy_chunk_size(canopy_chunk_size).ok().unwrap();
+            assert_eq!(batch_mint_client.canopy_chunk_size, canopy_chunk_size);
+        }
+        for canopy_chunk_size in [0, MAX_CANOPY_NODES_PER_TX + 1] {
+            assert!(matches!(
+                make_client().with_canopy_chunk_size(canopy_chunk_size),
+                Err(BatchMintError::IllegalArgumets(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_canopy_concurrency() {
+        let make_client = || BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        assert_eq!(make_client().canopy_concurrency, DEFAULT_CANOPY_CONCURRENCY);
+        assert_eq!(make_client().with_canopy_concurrency(16).canopy_concurrency, 16);
+        assert_eq!(make_client().with_canopy_concurrency(0).canopy_concurrency, 1);
+    }
+
+    #[test]
+    fn test_metadata_fields_too_long() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        let batch_mint_builder = make_builder();
+        let finalize_instruction = |metadata_url: &str, metadata_hash: &str| {
+            batch_mint_client.finalize_tree_instruction_with_pubkeys(
+                Pubkey::new_unique(),
+                &batch_mint_builder,
+                metadata_url,
+                metadata_hash,
+                &[],
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+            )
+        };
+
+        let longest_url = "u".repeat(MAX_METADATA_URL_LEN);
+        let longest_hash = "h".repeat(MAX_METADATA_HASH_LEN);
+        finalize_instruction(&longest_url, &longest_hash).unwrap();
+
+        match finalize_instruction(&format!("{longest_url}/"), METADATA_HASH) {
+            Err(BatchMintError::MetadataFieldTooLong { field, len, max_len }) => {
+                assert_eq!(field, "metadata_url");
+                assert_eq!(len, MAX_METADATA_URL_LEN + 1);
+                assert_eq!(max_len, MAX_METADATA_URL_LEN);
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(matches!(
+            finalize_instruction(METADATA_URL, &format!("{longest_hash}0")),
+            Err(BatchMintError::MetadataFieldTooLong { field, .. }) if field == "metadata_hash"
+        ));
+    }
+
+    #[test]
+    fn test_compute_budget_instructions() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        assert_eq!(
+            batch_mint_client.compute_budget_instructions(200_000),
+            vec![ComputeBudgetInstruction::set_compute_unit_limit(200_000)]
+        );
+
+        let batch_mint_client = batch_mint_client.with_priority_fee(5_000);
+        assert_eq!(
+            batch_mint_client.compute_budget_instructions(200_000),
+            vec![
+                ComputeBudgetInstruction::set_compute_unit_limit(200_000),
+                ComputeBudgetInstruction::set_compute_unit_price(5_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_finalize_instructions_do_not_seal_builder() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        let mut batch_mint_builder = make_builder();
+        let (payer, tree_creator, staker) = (Keypair::new(), Keypair::new(), Keypair::new());
+
+        // the same instructions simulate_finalize_tree simulates
+        batch_mint_client
+            .finalize_tree_instructions(
+                &payer,
+                METADATA_URL,
+                METADATA_HASH,
+                &batch_mint_builder,
+                &tree_creator,
+                &staker,
+                &[],
+            )
+            .unwrap();
+
+        assert!(!batch_mint_builder.is_sealed());
+        let metadata_args = mpl_bubblegum::types::MetadataArgs {
+            name: "Test NFT 3".to_string(),
+            symbol: "TNFT".to_string(),
+            uri: "https://example.com/nft/3".to_string(),
+            seller_fee_basis_points: 0,
+            primary_sale_happened: false,
+            is_mutable: false,
+            edition_nonce: None,
+            token_standard: Some(mpl_bubblegum::types::TokenStandard::NonFungible),
+            collection: None,
+            uses: None,
+            token_program_version: mpl_bubblegum::types::TokenProgramVersion::Original,
+            creators: Vec::new(),
+        };
+        batch_mint_builder
+            .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_compute_unit_limit() {
+        let batch_mint_builder = make_builder();
+        let (payer, tree_creator, staker) = (Keypair::new(), Keypair::new(), Keypair::new());
+        let finalize_instructions = |batch_mint_client: &BatchMintClient| {
+            batch_mint_client
+                .finalize_tree_instructions(
+                    &payer,
+                    METADATA_URL,
+                    METADATA_HASH,
+                    &batch_mint_builder,
+                    &tree_creator,
+                    &staker,
+                    &[],
+                )
+                .unwrap()
+                .0
+        };
+        let add_canopy_instructions = |batch_mint_client: &BatchMintClient| {
+            batch_mint_client.add_canopy_instructions(&batch_mint_builder, tree_creator.pubkey(), 0, &[[0; 32]])
+        };
+
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        assert_eq!(
+            finalize_instructions(&batch_mint_client)[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(DEFAULT_COMPUTE_UNIT_LIMIT)
+        );
+        assert_eq!(
+            add_canopy_instructions(&batch_mint_client)[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(DEFAULT_CANOPY_COMPUTE_UNIT_LIMIT)
+        );
+
+        let batch_mint_client = batch_mint_client.with_compute_unit_limit(400_000);
+        assert_eq!(
+            finalize_instructions(&batch_mint_client)[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(400_000)
+        );
+        assert_eq!(
+            add_canopy_instructions(&batch_mint_client)[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(400_000)
+        );
+
+        let batch_mint_client = batch_mint_client.with_canopy_compute_unit_limit(100_000);
+        assert_eq!(
+            finalize_instructions(&batch_mint_client)[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(400_000)
+        );
+        assert_eq!(
+            add_canopy_instructions(&batch_mint_client)[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(100_000)
+        );
+    }
+
+    #[test]
+    fn test_weighted_stake() {
+        let mut voter: Voter = bytemuck::Zeroable::zeroed();
+        assert_eq!(weighted_stake(&voter), 0);
+
+        for (deposit, (is_used, kind, amount)) in voter.deposits.iter_mut().zip([
+            (true, LockupKind::Constant, 10),
+            (true, LockupKind::Constant, 7),
+            (false, LockupKind::Constant, 100),
+            (true, LockupKind::None, 1000),
+        ]) {
+            deposit.is_used = is_used;
+            deposit.lockup.kind = kind;
+            deposit.lockup.period = LockupPeriod::OneYear;
+            deposit.amount_deposited_native = amount;
+        }
+        assert_eq!(weighted_stake(&voter), 17 * LockupPeriod::OneYear.multiplier());
+
+        let mut account = Account {
+            owner: mplx_staking_states::ID,
+            data: [pubkey_util::VOTER_DISCRIMINATOR.as_ref(), bytemuck::bytes_of(&voter)].concat(),
+            ..Account::default()
+        };
+        let parsed_voter = parse_voter(&account).unwrap();
+        assert_eq!(weighted_stake(&parsed_voter), weighted_stake(&voter));
+
+        account.owner = Pubkey::new_unique();
+        assert!(parse_voter(&account).is_none());
+        account.owner = mplx_staking_states::ID;
+        account.data[0] ^= 1;
+        assert!(parse_voter(&account).is_none());
+        account.data.truncate(100);
+        assert!(parse_voter(&account).is_none());
+    }
+
+    #[test]
+    fn test_finalize_instruction_with_pubkeys() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        let mut batch_mint_builder = make_builder();
+        let collection_authority = Pubkey::new_unique();
+        batch_mint_builder.setup_collection_config(CollectionConfig {
+            collection_authority: collection_authority.into(),
+            collection_authority_record_pda: None,
+            collection_mint: Pubkey::new_unique(),
+            collection_metadata: Pubkey::new_unique(),
+            edition_account: Pubkey::new_unique(),
+        });
+
+        let payer = Pubkey::new_unique();
+        let finalize_instruction = batch_mint_client
+            .finalize_tree_instruction_with_pubkeys(
+                payer,
+                &batch_mint_builder,
+                METADATA_URL,
+                METADATA_HASH,
+                &[],
+                pubkey_util::derive_tree_config_account(&batch_mint_builder.tree_account),
+                payer,
+                Pubkey::new_unique(),
+            )
+            .unwrap();
+        assert!(finalize_instruction
+            .accounts
+            .iter()
+            .any(|account| account.pubkey == collection_authority && account.is_signer));
+
+        // the transaction cannot be signed by the client
+        match collection_authority_signer(&batch_mint_builder) {
+            Err(BatchMintError::CollectionAuthoritySignerMissing {
+                collection_authority: missing,
+            }) => assert_eq!(missing, collection_authority),
+            r => panic!("Unexpected result: {:?}", r.map(|signer| signer.is_some())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finalize_tree_stream_checks_collection_authority() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        let mut batch_mint_builder = make_builder();
+        let collection_authority = Pubkey::new_unique();
+        batch_mint_builder.setup_collection_config(CollectionConfig {
+            collection_authority: collection_authority.into(),
+            collection_authority_record_pda: None,
+            collection_mint: Pubkey::new_unique(),
+            collection_metadata: Pubkey::new_unique(),
+            edition_account: Pubkey::new_unique(),
+        });
+        let (payer, tree_creator, staker) = (Keypair::new(), Keypair::new(), Keypair::new());
+
+        let mut stream = Box::pin(batch_mint_client.finalize_tree_stream(
+            &payer,
+            METADATA_URL,
+            METADATA_HASH,
+            &batch_mint_builder,
+            &tree_creator,
+            &staker,
+            &[],
+        ));
+        match stream.next().await {
+            Some(Err(BatchMintError::CollectionAuthoritySignerMissing {
+                collection_authority: missing,
+            })) => assert_eq!(missing, collection_authority),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(stream.next().await.is_none());
+        // failed before the canopy upload started
+        assert!(!batch_mint_builder.is_sealed());
+    }
+
+    #[test]
+    fn test_finalize_instruction_staking_accounts() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        let mut batch_mint_builder = make_builder();
+        let (payer, staker) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let registrar = pubkey_util::get_registrar_key();
+
+        for with_collection in [false, true] {
+            if with_collection {
+                batch_mint_builder.setup_collection_config(CollectionConfig {
+                    collection_authority: Pubkey::new_unique().into(),
+                    collection_authority_record_pda: None,
+                    collection_mint: Pubkey::new_unique(),
+                    collection_metadata: Pubkey::new_unique(),
+                    edition_account: Pubkey::new_unique(),
+                });
+            }
+            let finalize_instruction = batch_mint_client
+                .finalize_tree_instruction_with_pubkeys(
+                    payer,
+                    &batch_mint_builder,
+                    METADATA_URL,
+                    METADATA_HASH,
+                    &[],
+                    pubkey_util::derive_tree_config_account(&batch_mint_builder.tree_account),
+                    staker,
+                    Pubkey::new_unique(),
+                )
+                .unwrap();
+            // staking accounts belong to the staker, not to the payer
+            let has_account = |pubkey: Pubkey| {
+                finalize_instruction
+                    .accounts
+                    .iter()
+                    .any(|account| account.pubkey == pubkey)
+            };
+            assert!(has_account(pubkey_util::get_voter_key(&registrar, &staker)));
+            assert!(has_account(pubkey_util::get_mining_key(&staker)));
+            assert!(!has_account(pubkey_util::get_voter_key(&registrar, &payer)));
+        }
+    }
+
+    #[test]
+    fn test_verify_signed_finalize() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        let batch_mint_builder = make_builder();
+
+        let tx = make_signed_finalize_tx(&batch_mint_client, &batch_mint_builder, METADATA_URL);
+        batch_mint_client
+            .verify_signed_finalize(&tx, &batch_mint_builder, METADATA_URL, METADATA_HASH)
+            .unwrap();
+
+        // transaction for another metadata
+        let tx = make_signed_finalize_tx(&batch_mint_client, &batch_mint_builder, "http://other.ololo/");
+        assert!(matches!(
+            batch_mint_client.verify_signed_finalize(&tx, &batch_mint_builder, METADATA_URL, METADATA_HASH),
+            Err(BatchMintError::InvalidFinalizeTransaction(_))
+        ));
+
+        // transaction for another tree
+        let tx = make_signed_finalize_tx(&batch_mint_client, &make_builder(), METADATA_URL);
+        assert!(matches!(
+            batch_mint_client.verify_signed_finalize(&tx, &batch_mint_builder, METADATA_URL, METADATA_HASH),
+            Err(BatchMintError::InvalidFinalizeTransaction(_))
+        ));
+
+        // tampered after signing
+        let mut tx = make_signed_finalize_tx(&batch_mint_client, &batch_mint_builder, METADATA_URL);
+        let data = &mut tx.message.instructions[0].data;
+        data[8] = data[8].wrapping_add(1);
+        assert!(matches!(
+            batch_mint_client.verify_signed_finalize(&tx, &batch_mint_builder, METADATA_URL, METADATA_HASH),
+            Err(BatchMintError::InvalidFinalizeTransaction(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_signed_finalize_proof_accounts() {
+        let batch_mint_client = BatchMintClient::new(Arc::new(RpcClient::new("http://localhost:8899".to_string())));
+        let mut batch_mint_builder = make_builder();
+        let verify = |tx: &Transaction, batch_mint_builder: &BatchMintBuilder| {
+            batch_mint_client.verify_signed_finalize(tx, batch_mint_builder, METADATA_URL, METADATA_HASH)
+        };
+
+        // a proof node replaced
+        let mut remaining_accounts = batch_mint_builder.remaining_accounts();
+        remaining_accounts[0].pubkey = Pubkey::new_unique();
+        let tx = make_signed_finalize_tx_with_accounts(
+            &batch_mint_client,
+            &batch_mint_builder,
+            METADATA_URL,
+            remaining_accounts,
+        );
+        assert!(matches!(
+            verify(&tx, &batch_mint_builder),
+            Err(BatchMintError::InvalidFinalizeTransaction(_))
+        ));
+
+        // an account appended to the proof
+        let mut remaining_accounts = batch_mint_builder.remaining_accounts();
+        remaining_accounts.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+        let tx = make_signed_finalize_tx_with_accounts(
+            &batch_mint_client,
+            &batch_mint_builder,
+            METADATA_URL,
+            remaining_accounts,
+        );
+        assert!(matches!(
+            verify(&tx, &batch_mint_builder),
+            Err(BatchMintError::InvalidFinalizeTransaction(_))
+        ));
+
+        // no proof accounts are expected, while the transaction carries the whole proof
+        let tx = make_signed_finalize_tx(&batch_mint_client, &batch_mint_builder, METADATA_URL);
+        batch_mint_builder.override_remaining_accounts(Some(vec![]));
+        assert!(matches!(
+            verify(&tx, &batch_mint_builder),
+            Err(BatchMintError::InvalidFinalizeTransaction(_))
+        ));
+        let tx = make_signed_finalize_tx(&batch_mint_client, &batch_mint_builder, METADATA_URL);
+        verify(&tx, &batch_mint_builder).unwrap();
+    }
+}
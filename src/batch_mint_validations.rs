@@ -1,18 +1,18 @@
-use crate::batch_mint_builder::{make_changelog_path, verify_signature, MetadataArgsHash};
+use crate::batch_mint_builder::{ensure_leaf_schema_v1, make_changelog_path, verify_signature, MetadataArgsHash};
 use crate::errors::BatchMintError;
-use crate::merkle_tree_wrapper::make_concurrent_merkle_tree;
+use crate::merkle_tree_wrapper::{default_buffer_for, make_concurrent_merkle_tree, min_depth_for, ITree};
 use crate::model::{BatchMint, BatchMintInstruction, ChangeLogEventV1, PathNode};
+use crate::util::node_to_string;
 use anchor_lang::AnchorSerialize;
 use mpl_bubblegum::types::{Collection, LeafSchema, MetadataArgs, TokenProgramVersion, TokenStandard};
 use mpl_bubblegum::utils::get_asset_id;
 use rand::{thread_rng, Rng};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use solana_program::keccak;
-use solana_program::keccak::Hash;
 use solana_program::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
-use spl_concurrent_merkle_tree::concurrent_merkle_tree::ConcurrentMerkleTree;
 use std::collections::HashMap;
-use std::ops::Deref;
 use std::str::FromStr;
 
 #[derive(thiserror::Error, Debug, PartialEq, Eq)]
@@ -25,8 +25,6 @@ pub enum BatchMintValidationError {
     InvalidCreatorsHash(String, String),
     #[error("InvalidRoot: expected: {0}, got: {1}")]
     InvalidRoot(String, String),
-    #[error("NoRelevantRolledMint: index {0}")]
-    NoRelevantRolledMint(u64),
     #[error("WrongAssetPath: id {0}")]
     WrongAssetPath(String),
     #[error("StdIo {0}")]
@@ -49,6 +47,12 @@ pub enum BatchMintValidationError {
     WrongCollectionVerified(String),
     #[error("VerifiedCollectionMismatch: expected :{0}, got :{1}")]
     VerifiedCollectionMismatch(String, String),
+    #[error("TooManyAssets: count {count}, limit {limit}")]
+    TooManyAssets { count: usize, limit: usize },
+    #[error("InvalidPathStructure: leaf index {leaf_index}, level {level}")]
+    InvalidPathStructure { leaf_index: u32, level: usize },
+    #[error("InvalidMetadataHash: expected: {expected}, got: {actual}")]
+    InvalidMetadataHash { expected: String, actual: String },
 }
 
 impl From<std::io::Error> for BatchMintValidationError {
@@ -62,53 +66,78 @@ impl From<BatchMintError> for BatchMintValidationError {
     }
 }
 
-fn validate_change_logs(
-    max_depth: u32,
-    max_buffer_size: u32,
-    leaves: &[[u8; 32]],
-    batch_mint: &BatchMint,
+/// Appends the given leaf to the tree and checks that the resulting changelog
+/// matches the one stored in the batch mint for the asset.
+fn append_and_validate_change_log(
+    tree: &mut dyn ITree,
+    leaf_hash: [u8; 32],
+    mint: &BatchMintInstruction,
+    tree_id: &Pubkey,
 ) -> Result<(), BatchMintValidationError> {
-    let mut tree = make_concurrent_merkle_tree(max_depth, max_buffer_size)?;
-    tree.initialize()?;
-    for (i, leaf_hash) in leaves.iter().enumerate() {
-        tree.append(*leaf_hash)?;
-        let changelog = tree.change_logs(tree.active_index() as usize);
-        let path = make_changelog_path(changelog.deref());
-        match batch_mint.batch_mints.get(i) {
-            Some(mint) => {
-                if mint.tree_update.path != path.into_iter().map(Into::<PathNode>::into).collect::<Vec<_>>() {
-                    return Err(BatchMintValidationError::WrongAssetPath(
-                        mint.leaf_update.id().to_string(),
-                    ));
-                }
-                if mint.tree_update.id != batch_mint.tree_id {
-                    return Err(BatchMintValidationError::WrongTreeIdForChangeLog(
-                        mint.leaf_update.id().to_string(),
-                        batch_mint.tree_id.to_string(),
-                        mint.tree_update.id.to_string(),
-                    ));
-                }
-                if mint.tree_update.index != changelog.index() {
-                    return Err(BatchMintValidationError::WrongChangeLogIndex(
-                        mint.leaf_update.id().to_string(),
-                        changelog.index(),
-                        mint.tree_update.index,
-                    ));
-                }
-            }
-            None => return Err(BatchMintValidationError::NoRelevantRolledMint(i as u64)),
+    tree.append(leaf_hash)?;
+    let changelog = tree.change_log(tree.active_index() as usize);
+    let path = make_changelog_path(changelog);
+    if mint.tree_update.path != path.into_iter().map(Into::<PathNode>::into).collect::<Vec<_>>() {
+        return Err(BatchMintValidationError::WrongAssetPath(
+            mint.leaf_update.id().to_string(),
+        ));
+    }
+    if mint.tree_update.id != *tree_id {
+        return Err(BatchMintValidationError::WrongTreeIdForChangeLog(
+            mint.leaf_update.id().to_string(),
+            tree_id.to_string(),
+            mint.tree_update.id.to_string(),
+        ));
+    }
+    if mint.tree_update.index != changelog.index() {
+        return Err(BatchMintValidationError::WrongChangeLogIndex(
+            mint.leaf_update.id().to_string(),
+            changelog.index(),
+            mint.tree_update.index,
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the changelog path of the leaf with the given index is structurally correct,
+/// independently of the path node values:
+/// it consists of `tree_depth` nodes, where the node of level `lvl` has index
+/// `(1 << (tree_depth - lvl)) + (leaf_index >> lvl)`, followed by the root node with index 1.
+pub fn validate_path_structure(
+    path: &[PathNode],
+    leaf_index: u32,
+    tree_depth: u32,
+) -> Result<(), BatchMintValidationError> {
+    let expected_indexes = (0..tree_depth)
+        .map(|lvl| (1u32 << (tree_depth - lvl)) + (leaf_index >> lvl))
+        .chain(std::iter::once(1));
+    for (level, expected_index) in expected_indexes.enumerate() {
+        if path.get(level).map(|node| node.index) != Some(expected_index) {
+            return Err(BatchMintValidationError::InvalidPathStructure { leaf_index, level });
         }
     }
+    if path.len() > tree_depth as usize + 1 {
+        return Err(BatchMintValidationError::InvalidPathStructure {
+            leaf_index,
+            level: tree_depth as usize + 1,
+        });
+    }
+    Ok(())
+}
+
+fn validate_root(tree: &dyn ITree, batch_mint: &BatchMint) -> Result<(), BatchMintValidationError> {
     if tree.get_root() != batch_mint.merkle_root {
         return Err(BatchMintValidationError::InvalidRoot(
-            Hash::new(tree.get_root().as_slice()).to_string(),
-            Hash::new(batch_mint.merkle_root.as_slice()).to_string(),
+            node_to_string(&tree.get_root()),
+            node_to_string(&batch_mint.merkle_root),
         ));
     }
     Ok(())
 }
 
 fn get_leaf_hash(asset: &BatchMintInstruction, tree_id: &Pubkey) -> Result<[u8; 32], BatchMintValidationError> {
+    ensure_leaf_schema_v1(&asset.leaf_update)?;
+
     let asset_id = get_asset_id(tree_id, asset.leaf_update.nonce());
     if asset_id != asset.leaf_update.id() {
         return Err(BatchMintValidationError::PDACheckFail(
@@ -127,12 +156,14 @@ fn get_leaf_hash(asset: &BatchMintInstruction, tree_id: &Pubkey) -> Result<[u8;
     if asset.leaf_update.data_hash() != data_hash.to_bytes() {
         return Err(BatchMintValidationError::InvalidDataHash(
             data_hash.to_string(),
-            Hash::new(asset.leaf_update.data_hash().as_slice()).to_string(),
+            node_to_string(&asset.leaf_update.data_hash()),
         ));
     }
 
     // Use the metadata auth to check whether we can allow `verified` to be set to true in the
     // creator Vec.
+    // Note, creators are hashed in the order they are listed, so the order is significant:
+    // the same creators listed in a different order do not match the creator hash of the leaf.
     let creator_data = asset
         .mint_args
         .creators
@@ -151,7 +182,7 @@ fn get_leaf_hash(asset: &BatchMintInstruction, tree_id: &Pubkey) -> Result<[u8;
     if asset.leaf_update.creator_hash() != creator_hash.to_bytes() {
         return Err(BatchMintValidationError::InvalidCreatorsHash(
             creator_hash.to_string(),
-            Hash::new(asset.leaf_update.creator_hash().as_slice()).to_string(),
+            node_to_string(&asset.leaf_update.creator_hash()),
         ));
     }
 
@@ -184,60 +215,228 @@ fn verify_creators_signatures(
     Ok(())
 }
 
+/// Verifies creator signatures of all the assets of the batch mint in a separate pass,
+/// and returns the index of the first asset (in the batch mint order) that fails the verification,
+/// along with the error for the first failing creator of the asset.
+///
+/// With the `rayon` feature enabled the assets are verified in parallel,
+/// the result is the same as of the serial verification.
+/// The message signed by the creators is computed once per asset and shared by all its creators.
+pub fn find_failed_creator_signature(batch_mint: &BatchMint) -> Option<(usize, BatchMintValidationError)> {
+    let verify = |(index, asset): (usize, &BatchMintInstruction)| {
+        verify_creators_signatures(
+            &batch_mint.tree_id,
+            asset,
+            asset.creator_signature.clone().unwrap_or_default(),
+        )
+        .err()
+        .map(|err| (index, err))
+    };
+
+    #[cfg(feature = "rayon")]
+    let failed = batch_mint.batch_mints.par_iter().enumerate().find_map_first(verify);
+    #[cfg(not(feature = "rayon"))]
+    let failed = batch_mint.batch_mints.iter().enumerate().find_map(verify);
+
+    failed
+}
+
+/// Validates the given batch mint.
+///
+/// Leaf hashing and changelog replay are done in a single pass:
+/// each leaf hash is appended to the replayed merkle tree right after it is computed.
+///
+/// ## Arguments
+/// * `batch_mint` - batch mint to validate
+/// * `collection_mint` - the only collection assets are allowed to have as verified,
+///   `None` means that no asset may have a verified collection
 pub async fn validate_batch_mint(
     batch_mint: &BatchMint,
     collection_mint: Option<Pubkey>,
 ) -> Result<(), BatchMintValidationError> {
-    let mut leaf_hashes = Vec::new();
-    for asset in batch_mint.batch_mints.iter() {
-        let leaf_hash = match get_leaf_hash(asset, &batch_mint.tree_id) {
-            Ok(leaf_hash) => leaf_hash,
-            Err(e) => {
-                return Err(e);
-            }
-        };
-        leaf_hashes.push(leaf_hash);
+    let allowed_collection_mints = match collection_mint {
+        Some(ref collection_mint) => std::slice::from_ref(collection_mint),
+        None => &[],
+    };
+    validate_batch_mint_with_collections(batch_mint, allowed_collection_mints).await
+}
+
+/// Same as [validate_batch_mint], but allows assets of the batch mint
+/// to have any of the given collections as verified.
+///
+/// ## Arguments
+/// * `batch_mint` - batch mint to validate
+/// * `allowed_collection_mints` - collections assets are allowed to have as verified,
+///   an empty slice means that no asset may have a verified collection
+pub async fn validate_batch_mint_with_collections(
+    batch_mint: &BatchMint,
+    allowed_collection_mints: &[Pubkey],
+) -> Result<(), BatchMintValidationError> {
+    validate(batch_mint, allowed_collection_mints, None, None).await
+}
+
+/// Same as [validate_batch_mint_with_collections], but rejects batch mints
+/// that contain more than `max_assets` assets before doing any heavy work.
+/// Protects validators from resource-exhausting batch mints.
+pub async fn validate_batch_mint_with_max_assets(
+    batch_mint: &BatchMint,
+    allowed_collection_mints: &[Pubkey],
+    max_assets: usize,
+) -> Result<(), BatchMintValidationError> {
+    validate(batch_mint, allowed_collection_mints, Some(max_assets), None).await
+}
+
+/// Same as [validate_batch_mint_with_collections], but reports the progress of a long validation:
+/// `progress(validated, total)` is called every `progress_interval` validated assets, and after the last one.
+///
+/// An asset is counted as validated once its leaf hash is checked and appended to the replayed tree,
+/// both are done in the same pass.
+pub async fn validate_batch_mint_with_progress(
+    batch_mint: &BatchMint,
+    allowed_collection_mints: &[Pubkey],
+    progress_interval: usize,
+    mut progress: impl FnMut(usize, usize) + Send,
+) -> Result<(), BatchMintValidationError> {
+    validate(
+        batch_mint,
+        allowed_collection_mints,
+        None,
+        Some((progress_interval.max(1), &mut progress)),
+    )
+    .await
+}
+
+/// If the batch mint contains its metadata hash, makes sure it is the canonical hash of the document,
+/// see [BatchMint::canonical_metadata_hash].
+fn validate_metadata_hash(batch_mint: &BatchMint) -> Result<(), BatchMintValidationError> {
+    let Some(ref actual) = batch_mint.metadata_hash else {
+        return Ok(());
+    };
+    let expected = batch_mint
+        .canonical_metadata_hash()
+        .map_err(|e| BatchMintValidationError::StdIo(e.to_string()))?;
+    if *actual != expected {
+        return Err(BatchMintValidationError::InvalidMetadataHash {
+            expected,
+            actual: actual.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Makes sure the batch mint does not contain more assets than allowed
+/// by the given limit and by the capacity of the tree.
+fn validate_assets_count(batch_mint: &BatchMint, max_assets: Option<usize>) -> Result<(), BatchMintValidationError> {
+    let count = batch_mint.batch_mints.len();
+    if let Some(limit) = max_assets {
+        if count > limit {
+            return Err(BatchMintValidationError::TooManyAssets { count, limit });
+        }
+    }
+    // for an unsupported depth the error is returned on the tree creation
+    if let Some(capacity) = 1usize.checked_shl(batch_mint.max_depth) {
+        if count > capacity {
+            return Err(BatchMintValidationError::TooManyAssets { count, limit: capacity });
+        }
+    }
+    Ok(())
+}
+
+async fn validate(
+    batch_mint: &BatchMint,
+    allowed_collection_mints: &[Pubkey],
+    max_assets: Option<usize>,
+    mut progress: Option<(usize, &mut (dyn FnMut(usize, usize) + Send))>,
+) -> Result<(), BatchMintValidationError> {
+    validate_assets_count(batch_mint, max_assets)?;
+    validate_metadata_hash(batch_mint)?;
+
+    let mut tree = make_concurrent_merkle_tree(batch_mint.max_depth, batch_mint.max_buffer_size)?;
+    tree.initialize()?;
+
+    // signatures are verified in a separate parallel pass, but the failure is reported
+    // at the same point of the main loop as the serial verification would do
+    #[cfg(feature = "rayon")]
+    let mut failed_signature = find_failed_creator_signature(batch_mint);
+
+    let total = batch_mint.batch_mints.len();
+    for (index, asset) in batch_mint.batch_mints.iter().enumerate() {
+        let leaf_hash = get_leaf_hash(asset, &batch_mint.tree_id)?;
 
         if let Some(ref collection) = asset.mint_args.collection {
-            match collection_mint {
-                None => {
-                    if collection.verified {
-                        return Err(BatchMintValidationError::WrongCollectionVerified(
-                            collection.key.to_string(),
-                        ));
-                    }
+            if collection.verified {
+                if allowed_collection_mints.is_empty() {
+                    return Err(BatchMintValidationError::WrongCollectionVerified(
+                        collection.key.to_string(),
+                    ));
                 }
-                Some(collection_mint) => {
-                    if collection.verified && collection_mint != collection.key {
-                        return Err(BatchMintValidationError::VerifiedCollectionMismatch(
-                            collection_mint.to_string(),
-                            collection.key.to_string(),
-                        ));
-                    }
+                if !allowed_collection_mints.contains(&collection.key) {
+                    return Err(BatchMintValidationError::VerifiedCollectionMismatch(
+                        allowed_collection_mints
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        collection.key.to_string(),
+                    ));
                 }
             }
         }
 
+        #[cfg(feature = "rayon")]
+        if failed_signature
+            .as_ref()
+            .is_some_and(|(failed_index, _)| *failed_index == index)
+        {
+            return Err(failed_signature.take().unwrap().1);
+        }
+        #[cfg(not(feature = "rayon"))]
         verify_creators_signatures(
             &batch_mint.tree_id,
             asset,
             asset.creator_signature.clone().unwrap_or_default(),
         )?;
+
+        append_and_validate_change_log(tree.as_mut(), leaf_hash, asset, &batch_mint.tree_id)?;
+        // defense in depth: the path matches the replayed one, but both could be structurally wrong
+        validate_path_structure(&asset.tree_update.path, asset.tree_update.index, batch_mint.max_depth)?;
+
+        if let Some((progress_interval, ref mut progress)) = progress {
+            let validated = index + 1;
+            if validated % progress_interval == 0 || validated == total {
+                progress(validated, total);
+            }
+        }
     }
 
-    validate_change_logs(
-        batch_mint.max_depth,
-        batch_mint.max_buffer_size,
-        &leaf_hashes,
-        batch_mint,
-    )
+    validate_root(tree.as_ref(), batch_mint)
 }
 
 pub fn generate_batch_mint(size: usize) -> BatchMint {
+    let mut mints = Vec::with_capacity(size);
+    let mut batch_mint = generate_batch_mint_streaming(size, |mint| mints.push(mint));
+    batch_mint.batch_mints = mints;
+    batch_mint
+}
+
+/// Same as [generate_batch_mint], but instead of collecting mints into the batch mint
+/// hands each of them to the `sink` as soon as it is generated, so huge batch mints
+/// can be benchmarked without holding all the mints in memory.
+///
+/// Returns the batch mint header, i.e. the batch mint with empty `batch_mints`,
+/// but with the merkle root and the last leaf hash of the full tree.
+/// Sizes up to 1024 produce a tree of depth 10 with buffer size 32,
+/// bigger sizes use the smallest supported tree that fits all the mints.
+pub fn generate_batch_mint_streaming(size: usize, mut sink: impl FnMut(BatchMintInstruction)) -> BatchMint {
     let authority = Pubkey::from_str("3VvLDXqJbw3heyRwFxv8MmurPznmDVUJS9gPMX2BDqfM").unwrap();
     let tree = Pubkey::from_str("HxhCw9g3kZvrdg9zZvctmh6qpSDg1FfsBXfFvRkbCHB7").unwrap();
-    let mut mints = Vec::new();
-    let mut merkle = ConcurrentMerkleTree::<10, 32>::new();
+    let (max_depth, max_buffer_size) = if size <= 1 << 10 {
+        (10, 32)
+    } else {
+        let max_depth = min_depth_for(size as u64).expect("batch mint size exceeds the biggest supported tree");
+        (max_depth, default_buffer_for(max_depth).unwrap())
+    };
+    let mut merkle = make_concurrent_merkle_tree(max_depth, max_buffer_size).unwrap();
     merkle.initialize().unwrap();
 
     let mut last_leaf_hash = [0u8; 32];
@@ -327,27 +526,15 @@ pub fn generate_batch_mint(size: usize) -> BatchMint {
         .to_bytes();
         merkle.append(hashed_leaf).unwrap();
         last_leaf_hash = hashed_leaf;
-        let changelog = merkle.change_logs[merkle.active_index as usize];
-        let path_len = changelog.path.len() as u32;
-        let mut path: Vec<spl_account_compression::state::PathNode> = changelog
-            .path
-            .iter()
-            .enumerate()
-            .map(|(lvl, n)| {
-                spl_account_compression::state::PathNode::new(
-                    *n,
-                    (1 << (path_len - lvl as u32)) + (changelog.index >> lvl),
-                )
-            })
-            .collect();
-        path.push(spl_account_compression::state::PathNode::new(changelog.root, 1));
+        let changelog = merkle.change_logs(merkle.active_index() as usize);
+        let path = make_changelog_path(changelog.as_ref());
 
         let rolled_mint = BatchMintInstruction {
             tree_update: ChangeLogEventV1 {
                 id: tree,
                 path: path.into_iter().map(Into::into).collect::<Vec<_>>(),
-                seq: merkle.sequence_number,
-                index: changelog.index,
+                seq: merkle.sequence_number(),
+                index: changelog.index(),
             },
             leaf_update: LeafSchema::V1 {
                 id,
@@ -361,27 +548,247 @@ pub fn generate_batch_mint(size: usize) -> BatchMint {
             authority,
             creator_signature: None,
         };
-        mints.push(rolled_mint);
+        sink(rolled_mint);
     }
 
     BatchMint {
         tree_id: tree,
         raw_metadata_map: HashMap::new(),
-        max_depth: 10,
-        batch_mints: mints,
+        asset_metadata_map: HashMap::new(),
+        metadata_hash: None,
+        max_depth,
+        batch_mints: Vec::new(),
         merkle_root: merkle.get_root(),
         last_leaf_hash,
-        max_buffer_size: 32,
+        max_buffer_size,
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::batch_mint_validations::{generate_batch_mint, validate_batch_mint, BatchMintValidationError};
+    use crate::batch_mint_builder::BatchMintBuilder;
+    use crate::batch_mint_builder::MetadataArgsHash;
+    use crate::batch_mint_validations::{
+        find_failed_creator_signature, generate_batch_mint, generate_batch_mint_streaming, validate_batch_mint,
+        validate_batch_mint_with_collections, validate_batch_mint_with_max_assets, validate_batch_mint_with_progress,
+        validate_path_structure, verify_creators_signatures, BatchMintValidationError,
+    };
     use crate::errors::BatchMintError;
-    use crate::model::PathNode;
-    use mpl_bubblegum::types::LeafSchema;
+    use crate::model::{BatchMint, PathNode};
+    use anchor_lang::AnchorSerialize;
+    use mpl_bubblegum::types::{Collection, LeafSchema, MetadataArgs, TokenProgramVersion, TokenStandard};
+    use solana_program::keccak;
     use solana_program::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signature};
+    use solana_sdk::signer::Signer;
+    use std::collections::HashMap;
+
+    fn test_metadata_args(i: u8, collection: Option<Collection>) -> MetadataArgs {
+        MetadataArgs {
+            name: format!("{i}"),
+            symbol: format!("symbol-{i}"),
+            uri: format!("https://immutable-storage/asset/{i}"),
+            seller_fee_basis_points: 0,
+            primary_sale_happened: false,
+            is_mutable: false,
+            edition_nonce: None,
+            token_standard: Some(TokenStandard::NonFungible),
+            collection,
+            uses: None,
+            token_program_version: TokenProgramVersion::Original,
+            creators: Vec::new(),
+        }
+    }
+
+    /// Makes a batch mint with an asset per each given verified collection.
+    /// Batch mint is assembled manually, because builder allows only one verified collection.
+    fn make_batch_mint_with_collections(collections: &[Pubkey]) -> BatchMint {
+        let owner = Pubkey::new_unique();
+        let mut builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for (i, collection) in collections.iter().enumerate() {
+            let collection = Collection {
+                verified: true,
+                key: *collection,
+            };
+            builder
+                .add_asset(&owner, &owner, &test_metadata_args(i as u8, Some(collection)))
+                .unwrap();
+        }
+        BatchMint {
+            tree_id: builder.tree_account,
+            batch_mints: builder.mints.values().cloned().collect(),
+            raw_metadata_map: HashMap::new(),
+            asset_metadata_map: HashMap::new(),
+            metadata_hash: None,
+            max_depth: builder.max_depth,
+            max_buffer_size: builder.max_buffer_size,
+            merkle_root: builder.merkle.get_root(),
+            last_leaf_hash: builder.last_leaf_hash,
+        }
+    }
+
+    #[tokio::test]
+    async fn multiple_collections_validation_test() {
+        let collection_1 = Pubkey::new_unique();
+        let collection_2 = Pubkey::new_unique();
+        let collection_3 = Pubkey::new_unique();
+
+        let batch_mint = make_batch_mint_with_collections(&[collection_1, collection_2, collection_1]);
+
+        assert_eq!(
+            validate_batch_mint_with_collections(&batch_mint, &[collection_1, collection_2]).await,
+            Ok(())
+        );
+        assert_eq!(
+            validate_batch_mint_with_collections(&batch_mint, &[collection_2, collection_3, collection_1]).await,
+            Ok(())
+        );
+
+        assert_eq!(
+            validate_batch_mint_with_collections(&batch_mint, &[collection_1, collection_3]).await,
+            Err(BatchMintValidationError::VerifiedCollectionMismatch(
+                format!("{}, {}", collection_1, collection_3),
+                collection_2.to_string()
+            ))
+        );
+        assert_eq!(
+            validate_batch_mint_with_collections(&batch_mint, &[]).await,
+            Err(BatchMintValidationError::WrongCollectionVerified(
+                collection_1.to_string()
+            ))
+        );
+
+        // single collection behavior is kept
+        let batch_mint = make_batch_mint_with_collections(&[collection_1, collection_1]);
+        assert_eq!(validate_batch_mint(&batch_mint, Some(collection_1)).await, Ok(()));
+        assert_eq!(
+            validate_batch_mint(&batch_mint, Some(collection_2)).await,
+            Err(BatchMintValidationError::VerifiedCollectionMismatch(
+                collection_2.to_string(),
+                collection_1.to_string()
+            ))
+        );
+    }
+
+    // Depth 30 trees are the biggest supported ones (up to 2MB), and this is exactly
+    // the case the make_concurrent_merkle_tree_X_Y indirection functions exist for:
+    // without them a debug build fails with stack overflow.
+    #[tokio::test]
+    async fn depth_30_batch_mint_test() {
+        const DEPTH: u32 = 30;
+        const BUFFER: u32 = 512;
+        const CANOPY: u32 = 13; // minimal canopy for depth 30: 30 - MAX_ACC_PROOFS_SIZE
+
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let mut builder = BatchMintBuilder::new(Pubkey::new_unique(), (DEPTH, BUFFER, CANOPY)).unwrap();
+
+        for i in 0..10u8 {
+            let metadata_args = test_metadata_args(i, None);
+            builder.add_asset(&owner, &delegate, &metadata_args).unwrap();
+        }
+
+        // all 10 assets are under the first canopy leaf node (each one covers 2^17 leaves)
+        assert_eq!(builder.canopy_leaves.len(), 1);
+
+        let batch_mint = builder.build_batch_mint().unwrap();
+        let last_path = &batch_mint.batch_mints.last().unwrap().tree_update.path;
+        assert_eq!(builder.canopy_leaves[0], last_path[(DEPTH - CANOPY) as usize].node);
+
+        assert_eq!(validate_batch_mint(&batch_mint, None).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn validation_progress_test() {
+        let batch_mint = generate_batch_mint(10);
+
+        let mut reports = Vec::new();
+        validate_batch_mint_with_progress(&batch_mint, &[], 3, |validated, total| reports.push((validated, total)))
+            .await
+            .unwrap();
+        assert_eq!(reports, vec![(3, 10), (6, 10), (9, 10), (10, 10)]);
+    }
+
+    #[tokio::test]
+    async fn too_many_assets_validation_test() {
+        let mut batch_mint = generate_batch_mint(100);
+
+        assert_eq!(validate_batch_mint_with_max_assets(&batch_mint, &[], 100).await, Ok(()));
+        assert_eq!(
+            validate_batch_mint_with_max_assets(&batch_mint, &[], 99).await,
+            Err(BatchMintValidationError::TooManyAssets { count: 100, limit: 99 })
+        );
+
+        // more assets than the tree of depth 5 may contain
+        batch_mint.max_depth = 5;
+        assert_eq!(
+            validate_batch_mint(&batch_mint, None).await,
+            Err(BatchMintValidationError::TooManyAssets { count: 100, limit: 32 })
+        );
+    }
+
+    #[tokio::test]
+    async fn seller_fee_basis_points_validation_test() {
+        let mut builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for (i, seller_fee_basis_points) in [0u16, 9999, 10000].into_iter().enumerate() {
+            let mut metadata_args = test_metadata_args(i as u8, None);
+            metadata_args.seller_fee_basis_points = seller_fee_basis_points;
+            builder
+                .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+                .unwrap();
+        }
+        let mut batch_mint = builder.build_batch_mint().unwrap();
+        assert_eq!(validate_batch_mint(&batch_mint, None).await, Ok(()));
+
+        // data hash covers seller_fee_basis_points
+        batch_mint.batch_mints[2].mint_args.seller_fee_basis_points = 9999;
+        assert!(matches!(
+            validate_batch_mint(&batch_mint, None).await,
+            Err(BatchMintValidationError::InvalidDataHash(_, _))
+        ));
+    }
+
+    #[test]
+    fn path_structure_validation_test() {
+        let batch_mint = generate_batch_mint(10);
+        for asset in &batch_mint.batch_mints {
+            assert_eq!(
+                validate_path_structure(&asset.tree_update.path, asset.tree_update.index, batch_mint.max_depth),
+                Ok(())
+            );
+        }
+
+        let asset = &batch_mint.batch_mints[5];
+        let mut path = asset.tree_update.path.clone();
+        path[3].index += 1;
+        assert_eq!(
+            validate_path_structure(&path, asset.tree_update.index, batch_mint.max_depth),
+            Err(BatchMintValidationError::InvalidPathStructure {
+                leaf_index: 5,
+                level: 3
+            })
+        );
+
+        let mut path = asset.tree_update.path.clone();
+        path.pop();
+        assert_eq!(
+            validate_path_structure(&path, asset.tree_update.index, batch_mint.max_depth),
+            Err(BatchMintValidationError::InvalidPathStructure {
+                leaf_index: 5,
+                level: batch_mint.max_depth as usize
+            })
+        );
+
+        let mut path = asset.tree_update.path.clone();
+        path.push(path[0].clone());
+        assert_eq!(
+            validate_path_structure(&path, asset.tree_update.index, batch_mint.max_depth),
+            Err(BatchMintValidationError::InvalidPathStructure {
+                leaf_index: 5,
+                level: batch_mint.max_depth as usize + 1
+            })
+        );
+    }
 
     #[tokio::test]
     async fn batch_mint_validation_test() {
@@ -523,4 +930,162 @@ pub mod tests {
             ))
         );
     }
+
+    #[tokio::test]
+    async fn generate_batch_mint_streaming_test() {
+        let mut streamed = 0;
+        let header = generate_batch_mint_streaming(1100, |_| streamed += 1);
+        assert_eq!(streamed, 1100);
+        assert!(header.batch_mints.is_empty());
+        // 1100 mints do not fit into the default depth 10 tree
+        assert_eq!((header.max_depth, header.max_buffer_size), (11, 32));
+
+        let mut mints = Vec::new();
+        let mut batch_mint = generate_batch_mint_streaming(1100, |mint| mints.push(mint));
+        batch_mint.batch_mints = mints;
+        assert_eq!(validate_batch_mint(&batch_mint, None).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn creators_order_validation_test() {
+        let mut builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        let mut metadata_args = test_metadata_args(1, None);
+        metadata_args.creators = (0..3)
+            .map(|share| mpl_bubblegum::types::Creator {
+                address: Pubkey::new_unique(),
+                verified: false,
+                share: 30 + share,
+            })
+            .collect();
+        builder
+            .add_asset(&Pubkey::new_unique(), &Pubkey::new_unique(), &metadata_args)
+            .unwrap();
+        let mut batch_mint = builder.build_batch_mint().unwrap();
+        assert_eq!(validate_batch_mint(&batch_mint, None).await, Ok(()));
+
+        // creators are a part of metadata args, so the data hash mismatch is detected first
+        batch_mint.batch_mints[0].mint_args.creators.reverse();
+        assert!(matches!(
+            validate_batch_mint(&batch_mint, None).await,
+            Err(BatchMintValidationError::InvalidDataHash(_, _))
+        ));
+
+        // even if the data hash is forged to match the reordered creators, the creator hash is not
+        let mint = &mut batch_mint.batch_mints[0];
+        let metadata_args_hash = keccak::hashv(&[mint.mint_args.try_to_vec().unwrap().as_slice()]);
+        let forged_data_hash = keccak::hashv(&[
+            &metadata_args_hash.to_bytes(),
+            &mint.mint_args.seller_fee_basis_points.to_le_bytes(),
+        ]);
+        match &mut mint.leaf_update {
+            LeafSchema::V1 { data_hash, .. } => *data_hash = forged_data_hash.to_bytes(),
+        }
+        assert!(matches!(
+            validate_batch_mint(&batch_mint, None).await,
+            Err(BatchMintValidationError::InvalidCreatorsHash(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn creator_signatures_validation_test() {
+        let creators = [Keypair::new(), Keypair::new()];
+        let mut metadata_args = test_metadata_args(1, None);
+        metadata_args.creators = creators
+            .iter()
+            .map(|creator| mpl_bubblegum::types::Creator {
+                address: creator.pubkey(),
+                verified: true,
+                share: 50,
+            })
+            .collect();
+
+        let owner = Pubkey::new_unique();
+        let mut builder = BatchMintBuilder::new(Pubkey::new_unique(), (5, 8, 0)).unwrap();
+        for i in 0u8..8 {
+            metadata_args.name = format!("{i}");
+            builder.add_asset(&owner, &owner, &metadata_args).unwrap();
+        }
+        let mut batch_mint = BatchMint {
+            tree_id: builder.tree_account,
+            batch_mints: builder.mints.values().cloned().collect(),
+            raw_metadata_map: HashMap::new(),
+            asset_metadata_map: HashMap::new(),
+            metadata_hash: None,
+            max_depth: builder.max_depth,
+            max_buffer_size: builder.max_buffer_size,
+            merkle_root: builder.merkle.get_root(),
+            last_leaf_hash: builder.last_leaf_hash,
+        };
+        for asset in batch_mint.batch_mints.iter_mut() {
+            let message =
+                MetadataArgsHash::new(&asset.leaf_update, &batch_mint.tree_id, &asset.mint_args).get_message();
+            asset.creator_signature = Some(
+                creators
+                    .iter()
+                    .map(|creator| (creator.pubkey(), creator.sign_message(&message)))
+                    .collect(),
+            );
+        }
+        assert_eq!(find_failed_creator_signature(&batch_mint), None);
+        assert_eq!(validate_batch_mint(&batch_mint, None).await, Ok(()));
+
+        // the first failing asset is reported, and for it the first failing creator
+        let forged_signature = Signature::new_unique();
+        for (asset_index, creator_index) in [(6, 0), (3, 1), (5, 0)] {
+            batch_mint.batch_mints[asset_index]
+                .creator_signature
+                .as_mut()
+                .unwrap()
+                .insert(creators[creator_index].pubkey(), forged_signature);
+        }
+        batch_mint.batch_mints[3]
+            .creator_signature
+            .as_mut()
+            .unwrap()
+            .remove(&creators[0].pubkey());
+
+        let serial_result = batch_mint.batch_mints.iter().enumerate().find_map(|(index, asset)| {
+            verify_creators_signatures(
+                &batch_mint.tree_id,
+                asset,
+                asset.creator_signature.clone().unwrap_or_default(),
+            )
+            .err()
+            .map(|err| (index, err))
+        });
+        let expected_error = || BatchMintValidationError::MissingCreatorSignature(creators[0].pubkey().to_string());
+        assert_eq!(serial_result, Some((3, expected_error())));
+        assert_eq!(find_failed_creator_signature(&batch_mint), serial_result);
+        assert_eq!(validate_batch_mint(&batch_mint, None).await, Err(expected_error()));
+
+        // a data hash mismatch of an earlier asset is still reported before the failed signature
+        batch_mint.batch_mints[1].mint_args.name = "forged".to_string();
+        assert!(matches!(
+            validate_batch_mint(&batch_mint, None).await,
+            Err(BatchMintValidationError::InvalidDataHash(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn metadata_hash_validation_test() {
+        let mut batch_mint = generate_batch_mint(10);
+        // the hash is optional
+        assert_eq!(validate_batch_mint(&batch_mint, None).await, Ok(()));
+
+        batch_mint.metadata_hash = Some(batch_mint.canonical_metadata_hash().unwrap());
+        assert_eq!(validate_batch_mint(&batch_mint, None).await, Ok(()));
+
+        // survives JSON round trip
+        let json = serde_json::to_string(&batch_mint).unwrap();
+        let restored = BatchMint::read_as_json(json.as_bytes()).unwrap();
+        assert_eq!(restored.metadata_hash, batch_mint.metadata_hash);
+        assert_eq!(validate_batch_mint(&restored, None).await, Ok(()));
+
+        // any change of the document invalidates the hash
+        batch_mint.batch_mints[3].mint_args.uri = "https://arweave.net/other".to_string();
+        assert!(matches!(
+            validate_batch_mint(&batch_mint, None).await,
+            Err(BatchMintValidationError::InvalidMetadataHash { .. })
+        ));
+    }
 }
@@ -1,5 +1,9 @@
 use anchor_lang::error;
-use solana_sdk::pubkey::ParsePubkeyError;
+use solana_rpc_client_api::client_error::ErrorKind;
+use solana_rpc_client_api::request::{RpcError, RpcResponseErrorData};
+use solana_sdk::pubkey::{ParsePubkeyError, Pubkey};
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::TransactionError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -36,4 +40,182 @@ pub enum BatchMintError {
     CanopyCoercionErr,
     #[error("Missing collection signature: {0}")]
     MissingCollectionSignature(String),
+    #[error("Canopy has an empty leaf node at index {index} followed by non-empty ones")]
+    CanopyHole { index: usize },
+    #[error("Tree account {tree_account} does not exist, make sure prepare_tree has been called for it")]
+    TreeNotPrepared { tree_account: Pubkey },
+    #[error("Stored leaf hash of asset with nonce={nonce} does not match the one calculated from its metadata")]
+    CorruptBatchMint { nonce: u64 },
+    #[error("Transaction {signature} is not confirmed in time, check its state later")]
+    ConfirmationTimeout { signature: Signature },
+    #[error("Canopy is not fully uploaded: {uploaded} of {expected} leaf nodes match the batch mint")]
+    CanopyIncomplete { uploaded: usize, expected: usize },
+    #[error("Asset has verified collection {actual}, but the batch mint is set up for collection {expected}")]
+    CollectionKeyMismatch { expected: Pubkey, actual: Pubkey },
+    #[error("Invalid finalize transaction: {0}")]
+    InvalidFinalizeTransaction(String),
+    #[error("Tree data account size {size} exceeds the maximum account size {max}, try to reduce canopy depth")]
+    AccountTooLarge { size: usize, max: usize },
+    #[error("Tree {tree_account} is already initialized (sequence number {sequence_number}), a batch mint cannot be added to it")]
+    TreeAlreadyInitialized { tree_account: Pubkey, sequence_number: u64 },
+    #[error("Batch mint builder does not match the checkpoint: {field} differs")]
+    CheckpointMismatch { field: String },
+    #[error("Batch mint is built for tree max_depth={max_depth} max_buffer_size={max_buffer_size}, but the tree account has max_depth={onchain_max_depth} max_buffer_size={onchain_max_buffer_size}")]
+    GeometryMismatch {
+        max_depth: u32,
+        max_buffer_size: u32,
+        onchain_max_depth: u32,
+        onchain_max_buffer_size: u32,
+    },
+    #[error("Program {program_id} is not deployed")]
+    ProgramNotDeployed { program_id: Pubkey },
+    #[error("Deployed program does not support batch mint, missing instructions: {0}")]
+    BatchMintNotSupported(String),
+    #[error("Batch mint builder has {actual} canopy leaf nodes, but {expected} are expected for its assets")]
+    CanopyLeavesCountMismatch { expected: usize, actual: usize },
+    #[error("Canopy leaf node at index {index} is empty")]
+    EmptyCanopyLeaf { index: usize },
+    #[error("Unsupported leaf schema {0}, only V1 leaf schema is supported")]
+    UnsupportedLeafSchema(String),
+    #[error("Batch mint builder for tree {tree_account} is sealed, since its finalization has started, no more assets can be added")]
+    BuilderSealed { tree_account: Pubkey },
+    #[error("AddCanopy transaction for canopy chunk {chunk_index} failed: {source}")]
+    CanopyUploadFailed {
+        chunk_index: usize,
+        #[source]
+        source: Box<BatchMintError>,
+    },
+    #[error("Collection authority {collection_authority} has no signer, only its public key is known")]
+    CollectionAuthoritySignerMissing { collection_authority: Pubkey },
+    #[error("Tree data account has header version {version}, only V1 (0) is supported")]
+    UnsupportedHeaderVersion { version: u8 },
+    #[error("An asset with nonce {nonce} has already been added")]
+    NonceAlreadyUsed { nonce: u64 },
+    #[error("No asset added at nonce {nonce}, while there are assets with greater nonces")]
+    NonceGap { nonce: u64 },
+    #[error("Finalize checkpoint is written for tree {actual}, while the batch mint is for tree {expected}")]
+    FinalizeCheckpointTreeMismatch { expected: Pubkey, actual: Pubkey },
+    #[error("Finalize checkpoint says {checkpoint_uploaded} canopy leaf nodes are uploaded, but only {onchain_uploaded} are found in the tree")]
+    FinalizeCheckpointMismatch {
+        checkpoint_uploaded: usize,
+        onchain_uploaded: usize,
+    },
+    #[error(
+        "Mining account {mining_account} of staker {staker} is not initialized, set up rewards for the staker first"
+    )]
+    MiningAccountNotInitialized { mining_account: Pubkey, staker: Pubkey },
+    #[error("Upload to storage failed: {0}")]
+    StorageUploadFailed(String),
+    #[error("{field} is {len} bytes long, while at most {max_len} bytes are allowed")]
+    MetadataFieldTooLong { field: String, len: usize, max_len: usize },
+    #[error("Account {account} is not a valid {kind} account of the staking program")]
+    InvalidStakingAccount { account: Pubkey, kind: String },
+    #[error("Tree data account is {actual} bytes long, while {expected} bytes are expected for its geometry")]
+    MalformedTreeAccount { expected: usize, actual: usize },
+    #[error("Support of {instruction} instruction cannot be probed, simulation failed: {err}")]
+    ProgramProbeFailed { instruction: String, err: TransactionError },
+}
+
+/// User-facing reason of a failed finalize transaction, see [BatchMintError::finalize_failure_reason].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinalizeFailureReason {
+    /// The staker doesn't have enough MPLX staked to create a batch mint
+    NotEnoughStake,
+    /// The canopy uploaded to the tree account doesn't match the batch mint tree
+    CanopyRootMismatch,
+    /// The tree already contains assets, i.e. the batch mint has already been finalized
+    TreeAlreadyFinalized,
+    /// The fee payer cannot pay for the transaction
+    InsufficientFunds,
+    /// The transaction failed for some other reason, contains the transaction error
+    Other(String),
+}
+
+/// Substrings of program logs that identify a finalize failure reason.
+const FINALIZE_FAILURE_LOG_MARKERS: &[(&str, FinalizeFailureReason)] = &[
+    ("NotEnoughStakeForOperation", FinalizeFailureReason::NotEnoughStake),
+    ("CanopyRootMismatch", FinalizeFailureReason::CanopyRootMismatch),
+    ("Tree already initialized", FinalizeFailureReason::TreeAlreadyFinalized),
+];
+
+impl BatchMintError {
+    /// Classifies the error returned by `finalize_tree` into a [FinalizeFailureReason],
+    /// by inspecting the transaction error and the program logs of the failed transaction simulation.
+    ///
+    /// Returns None if the error is not caused by a failed transaction, e.g. it is a connection error.
+    pub fn finalize_failure_reason(&self) -> Option<FinalizeFailureReason> {
+        match self {
+            BatchMintError::TreeAlreadyInitialized { .. } => Some(FinalizeFailureReason::TreeAlreadyFinalized),
+            BatchMintError::SolanaClientErr(e) => match &e.kind {
+                ErrorKind::RpcError(RpcError::RpcResponseError {
+                    data: RpcResponseErrorData::SendTransactionPreflightFailure(simulation),
+                    ..
+                }) => {
+                    classify_finalize_failure(simulation.logs.as_deref().unwrap_or_default(), simulation.err.as_ref())
+                }
+                ErrorKind::TransactionError(tx_error) => classify_finalize_failure(&[], Some(tx_error)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn classify_finalize_failure(
+    logs: &[String],
+    tx_error: Option<&TransactionError>,
+) -> Option<FinalizeFailureReason> {
+    let by_logs = FINALIZE_FAILURE_LOG_MARKERS
+        .iter()
+        .find(|(marker, _)| logs.iter().any(|log| log.contains(marker)))
+        .map(|(_, reason)| reason.clone());
+    by_logs.or_else(|| match tx_error? {
+        TransactionError::InsufficientFundsForFee | TransactionError::InsufficientFundsForRent { .. } => {
+            Some(FinalizeFailureReason::InsufficientFunds)
+        }
+        tx_error => Some(FinalizeFailureReason::Other(tx_error.to_string())),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_sdk::instruction::InstructionError;
+
+    #[test]
+    fn test_classify_finalize_failure() {
+        let custom_error = TransactionError::InstructionError(1, InstructionError::Custom(6042));
+        let logs = vec![
+            "Program BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY invoke [1]".to_string(),
+            "Program log: AnchorError occurred. Error Code: NotEnoughStakeForOperation. Error Number: 6042."
+                .to_string(),
+        ];
+        assert_eq!(
+            classify_finalize_failure(&logs, Some(&custom_error)),
+            Some(FinalizeFailureReason::NotEnoughStake)
+        );
+        assert_eq!(
+            classify_finalize_failure(&["Program log: Error Code: CanopyRootMismatch".to_string()], None),
+            Some(FinalizeFailureReason::CanopyRootMismatch)
+        );
+        assert_eq!(
+            classify_finalize_failure(&[], Some(&TransactionError::InsufficientFundsForFee)),
+            Some(FinalizeFailureReason::InsufficientFunds)
+        );
+        assert_eq!(
+            classify_finalize_failure(&[], Some(&custom_error)),
+            Some(FinalizeFailureReason::Other(custom_error.to_string()))
+        );
+        assert_eq!(classify_finalize_failure(&[], None), None);
+
+        assert_eq!(
+            BatchMintError::TreeAlreadyInitialized {
+                tree_account: Pubkey::new_unique(),
+                sequence_number: 1
+            }
+            .finalize_failure_reason(),
+            Some(FinalizeFailureReason::TreeAlreadyFinalized)
+        );
+        assert_eq!(BatchMintError::MissingBatchMint(1).finalize_failure_reason(), None);
+    }
 }
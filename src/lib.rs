@@ -5,4 +5,9 @@ pub mod errors;
 pub mod merkle_tree_wrapper;
 pub mod model;
 pub mod pubkey_util;
+pub mod storage;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tree_config_acc;
 pub mod tree_data_acc;
+pub mod util;
@@ -3,8 +3,10 @@ use std::{mem::size_of, slice::Iter};
 use spl_account_compression::{ConcurrentMerkleTree, ConcurrentMerkleTreeError, Node};
 
 use spl_concurrent_merkle_tree::changelog::ChangeLog;
+use spl_concurrent_merkle_tree::node::empty_node;
 
 use crate::errors::BatchMintError;
+use crate::util::keccak_hashv;
 
 /// Interface that abstracts over [ConcurrentMerkleTree]<DEPTH, BUF_SIZE>
 /// regardless const generic parameters.
@@ -13,6 +15,9 @@ pub trait ITree {
     fn append(&mut self, node: Node) -> Result<Node, ConcurrentMerkleTreeError>;
     fn active_index(&self) -> u64;
     fn change_logs(&self, ind: usize) -> Box<dyn IChangeLog>;
+    /// Same as [ITree::change_logs], but borrows the changelog instead of copying it to the heap,
+    /// which matters when it is called for every appended leaf.
+    fn change_log(&self, ind: usize) -> &dyn IChangeLog;
     fn sequence_number(&self) -> u64;
     fn get_root(&self) -> [u8; 32];
     fn get_rightmost_proof(&self) -> &[[u8; 32]];
@@ -36,6 +41,9 @@ macro_rules! make_tree_impls {
             fn change_logs(&self, ind: usize) -> Box<dyn IChangeLog> {
                 Box::new(self.change_logs[ind])
             }
+            fn change_log(&self, ind: usize) -> &dyn IChangeLog {
+                &self.change_logs[ind]
+            }
             fn sequence_number(&self) -> u64 {
                 self.sequence_number
             }
@@ -50,44 +58,54 @@ macro_rules! make_tree_impls {
   }
 }
 
+/// Invokes the given macro with all the (max_depth, max_buffer_size) pairs the SDK supports,
+/// sorted by depth and then by buffer size.
+/// It is the only place the supported tree sizes are listed,
+/// [ITree] impls, tree constructors, [SUPPORTED_TREE_SIZES] etc. are generated from it.
+macro_rules! with_supported_tree_sizes {
+    ($m:ident) => {
+        $m!(
+            (3, 8),
+            (5, 8),
+            (6, 16),
+            (7, 16),
+            (8, 16),
+            (9, 16),
+            (10, 32),
+            (11, 32),
+            (12, 32),
+            (13, 32),
+            (14, 64),
+            (14, 256),
+            (14, 1024),
+            (14, 2048),
+            (15, 64),
+            (16, 64),
+            (17, 64),
+            (18, 64),
+            (19, 64),
+            (20, 64),
+            (20, 256),
+            (20, 1024),
+            (20, 2048),
+            (24, 64),
+            (24, 256),
+            (24, 512),
+            (24, 1024),
+            (24, 2048),
+            (26, 512),
+            (26, 1024),
+            (26, 2048),
+            (30, 512),
+            (30, 1024),
+            (30, 2048)
+        );
+    };
+}
+
 // Building implementations of ITree
 // for all possible instances of ConcurrentMerkleTreeError.
-make_tree_impls!(
-    (3, 8),
-    (5, 8),
-    (6, 16),
-    (7, 16),
-    (8, 16),
-    (9, 16),
-    (10, 32),
-    (11, 32),
-    (12, 32),
-    (13, 32),
-    (14, 64),
-    (14, 256),
-    (14, 1024),
-    (14, 2048),
-    (15, 64),
-    (16, 64),
-    (17, 64),
-    (18, 64),
-    (19, 64),
-    (20, 64),
-    (20, 256),
-    (20, 1024),
-    (20, 2048),
-    (24, 64),
-    (24, 256),
-    (24, 512),
-    (24, 1024),
-    (24, 2048),
-    (26, 512),
-    (26, 1024),
-    (26, 2048),
-    (30, 512),
-    (30, 1024),
-    (30, 2048)
-);
+with_supported_tree_sizes!(make_tree_impls);
 
 /// An abstraction for [ChangeLog]
 /// that abstracts over const generic parameter.
@@ -140,45 +158,82 @@ macro_rules! make_tree_creator_funcs {
             }
         }
     )*
+
+    /// Creates a tree of the given size, or returns None if the size is not supported.
+    fn make_concurrent_merkle_tree_of_size(max_depth: u32, max_buf_size: u32) -> Option<Box<dyn ITree>> {
+        match (max_depth, max_buf_size) {
+            $(
+                ($x, $y) => Some(paste::paste! { [< make_concurrent_merkle_tree_ $x _ $y >]() }),
+            )*
+            _ => None,
+        }
+    }
+  }
+}
+
+/// Generates a function that returns the size of a [ConcurrentMerkleTree] of the given size, without canopy.
+macro_rules! make_tree_size_func {
+  ( $( ($x:literal, $y:literal) ),* ) => {
+    fn concurrent_merkle_tree_size(max_depth: u32, max_buffer_size: u32) -> Option<usize> {
+        match (max_depth, max_buffer_size) {
+            $(
+                ($x, $y) => Some(size_of::<ConcurrentMerkleTree<$x, $y>>()),
+            )*
+            _ => None,
+        }
+    }
+  }
+}
+
+/// Generates [SUPPORTED_TREE_SIZES].
+macro_rules! make_supported_tree_sizes {
+  ( $( ($x:literal, $y:literal) ),* ) => {
+    /// All the (max_depth, max_buffer_size) pairs the batch mint can be created for,
+    /// sorted by depth and then by buffer size.
+    pub const SUPPORTED_TREE_SIZES: &[(u32, u32)] = &[ $( ($x, $y) ),* ];
   }
 }
 
-make_tree_creator_funcs!(
-    (3, 8),
-    (5, 8),
-    (6, 16),
-    (7, 16),
-    (8, 16),
-    (9, 16),
-    (10, 32),
-    (11, 32),
-    (12, 32),
-    (13, 32),
-    (14, 64),
-    (14, 256),
-    (14, 1024),
-    (14, 2048),
-    (15, 64),
-    (16, 64),
-    (17, 64),
-    (18, 64),
-    (19, 64),
-    (20, 64),
-    (20, 256),
-    (20, 1024),
-    (20, 2048),
-    (24, 64),
-    (24, 256),
-    (24, 512),
-    (24, 1024),
-    (24, 2048),
-    (26, 512),
-    (26, 1024),
-    (26, 2048),
-    (30, 512),
-    (30, 1024),
-    (30, 2048)
-);
+with_supported_tree_sizes!(make_tree_creator_funcs);
+with_supported_tree_sizes!(make_tree_size_func);
+with_supported_tree_sizes!(make_supported_tree_sizes);
+
+/// Checks the tree of the given size is supported, i.e. it is one of [SUPPORTED_TREE_SIZES].
+/// Allows to validate user input before [crate::batch_mint_client::BatchMintClient::prepare_tree] is called.
+pub fn is_supported_tree_size(max_depth: u32, max_buffer_size: u32) -> bool {
+    SUPPORTED_TREE_SIZES.contains(&(max_depth, max_buffer_size))
+}
+
+/// Returns the smallest supported tree depth that can hold the given number of assets,
+/// or None if there are too many assets even for the deepest supported tree.
+pub fn min_depth_for(asset_count: u64) -> Option<u32> {
+    SUPPORTED_TREE_SIZES
+        .iter()
+        .map(|(max_depth, _)| *max_depth)
+        .find(|max_depth| (1u64 << max_depth) >= asset_count)
+}
+
+/// Returns the conventional max buffer size for the given tree depth,
+/// that is the smallest buffer size supported for this depth.
+/// Returns None if the depth is not supported.
+pub fn default_buffer_for(max_depth: u32) -> Option<u32> {
+    SUPPORTED_TREE_SIZES
+        .iter()
+        .find(|(depth, _)| *depth == max_depth)
+        .map(|(_, max_buffer_size)| *max_buffer_size)
+}
+
+/// Suggests a supported tree size for the requested depth: the smallest supported buffer size for it,
+/// e.g. `(14, 64)` for depth 14. If the depth is not supported, the size of the next supported (deeper) tree
+/// is suggested, e.g. `(24, 64)` for depth 21. Returns None if the depth exceeds the deepest supported tree.
+///
+/// Useful to give a hint when [BatchMintError::UnexpectedTreeSize] is returned for a user provided tree size.
+pub fn suggest_tree_size(max_depth: u32) -> Option<(u32, u32)> {
+    SUPPORTED_TREE_SIZES
+        .iter()
+        .find(|(depth, _)| *depth >= max_depth)
+        .copied()
+}
 
 pub fn make_concurrent_merkle_tree(max_dapth: u32, max_buf_size: u32) -> Result<Box<dyn ITree>, BatchMintError> {
     // Note: We do not create ConcurrentMerkleTree<A,B> object right inside of match statement
@@ -193,43 +248,8 @@ pub fn make_concurrent_merkle_tree(max_dapth: u32, max_buf_size: u32) -> Result<
     // Though, we need the debug to not fail with the stack overflow,
     // that's why we had to move creation of an exact ConcurrentMerkleTree<A,B> objects
     // into separate function that return trait objects.
-    match (max_dapth, max_buf_size) {
-        (3, 8) => Ok(make_concurrent_merkle_tree_3_8()),
-        (5, 8) => Ok(make_concurrent_merkle_tree_5_8()),
-        (6, 16) => Ok(make_concurrent_merkle_tree_6_16()),
-        (7, 16) => Ok(make_concurrent_merkle_tree_7_16()),
-        (8, 16) => Ok(make_concurrent_merkle_tree_8_16()),
-        (9, 16) => Ok(make_concurrent_merkle_tree_9_16()),
-        (10, 32) => Ok(make_concurrent_merkle_tree_10_32()),
-        (11, 32) => Ok(make_concurrent_merkle_tree_11_32()),
-        (12, 32) => Ok(make_concurrent_merkle_tree_12_32()),
-        (13, 32) => Ok(make_concurrent_merkle_tree_13_32()),
-        (14, 64) => Ok(make_concurrent_merkle_tree_14_64()),
-        (14, 256) => Ok(make_concurrent_merkle_tree_14_256()),
-        (14, 1024) => Ok(make_concurrent_merkle_tree_14_1024()),
-        (14, 2048) => Ok(make_concurrent_merkle_tree_14_2048()),
-        (15, 64) => Ok(make_concurrent_merkle_tree_15_64()),
-        (16, 64) => Ok(make_concurrent_merkle_tree_16_64()),
-        (17, 64) => Ok(make_concurrent_merkle_tree_17_64()),
-        (18, 64) => Ok(make_concurrent_merkle_tree_18_64()),
-        (19, 64) => Ok(make_concurrent_merkle_tree_19_64()),
-        (20, 64) => Ok(make_concurrent_merkle_tree_20_64()),
-        (20, 256) => Ok(make_concurrent_merkle_tree_20_256()),
-        (20, 1024) => Ok(make_concurrent_merkle_tree_20_1024()),
-        (20, 2048) => Ok(make_concurrent_merkle_tree_20_2048()),
-        (24, 64) => Ok(make_concurrent_merkle_tree_24_64()),
-        (24, 256) => Ok(make_concurrent_merkle_tree_24_256()),
-        (24, 512) => Ok(make_concurrent_merkle_tree_24_512()),
-        (24, 1024) => Ok(make_concurrent_merkle_tree_24_1024()),
-        (24, 2048) => Ok(make_concurrent_merkle_tree_24_2048()),
-        (26, 512) => Ok(make_concurrent_merkle_tree_26_512()),
-        (26, 1024) => Ok(make_concurrent_merkle_tree_26_1024()),
-        (26, 2048) => Ok(make_concurrent_merkle_tree_26_2048()),
-        (30, 512) => Ok(make_concurrent_merkle_tree_30_512()),
-        (30, 1024) => Ok(make_concurrent_merkle_tree_30_1024()),
-        (30, 2048) => Ok(make_concurrent_merkle_tree_30_2048()),
-        (d, s) => Err(BatchMintError::UnexpectedTreeSize(d, s)),
-    }
+    make_concurrent_merkle_tree_of_size(max_dapth, max_buf_size)
+        .ok_or(BatchMintError::UnexpectedTreeSize(max_dapth, max_buf_size))
 }
 
 make_changelog_impls!(3, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 24, 26, 30);
@@ -240,46 +260,33 @@ make_changelog_impls!(3, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19,
 
 pub fn calc_merkle_tree_size(max_depth: u32, max_buffer_size: u32, canopy_depth: u32) -> Option<usize> {
     // Note: max_buffer_size MUST be a power of 2
-    let tree_size = match (max_depth, max_buffer_size) {
-        (3, 8) => Some(size_of::<ConcurrentMerkleTree<3, 8>>()),
-        (5, 8) => Some(size_of::<ConcurrentMerkleTree<5, 8>>()),
-        (6, 16) => Some(size_of::<ConcurrentMerkleTree<6, 16>>()),
-        (7, 16) => Some(size_of::<ConcurrentMerkleTree<7, 16>>()),
-        (8, 16) => Some(size_of::<ConcurrentMerkleTree<8, 16>>()),
-        (9, 16) => Some(size_of::<ConcurrentMerkleTree<9, 16>>()),
-        (10, 32) => Some(size_of::<ConcurrentMerkleTree<10, 32>>()),
-        (11, 32) => Some(size_of::<ConcurrentMerkleTree<11, 32>>()),
-        (12, 32) => Some(size_of::<ConcurrentMerkleTree<12, 32>>()),
-        (13, 32) => Some(size_of::<ConcurrentMerkleTree<13, 32>>()),
-        (14, 64) => Some(size_of::<ConcurrentMerkleTree<14, 64>>()),
-        (14, 256) => Some(size_of::<ConcurrentMerkleTree<14, 256>>()),
-        (14, 1024) => Some(size_of::<ConcurrentMerkleTree<14, 1024>>()),
-        (14, 2048) => Some(size_of::<ConcurrentMerkleTree<14, 2048>>()),
-        (15, 64) => Some(size_of::<ConcurrentMerkleTree<15, 64>>()),
-        (16, 64) => Some(size_of::<ConcurrentMerkleTree<16, 64>>()),
-        (17, 64) => Some(size_of::<ConcurrentMerkleTree<17, 64>>()),
-        (18, 64) => Some(size_of::<ConcurrentMerkleTree<18, 64>>()),
-        (19, 64) => Some(size_of::<ConcurrentMerkleTree<19, 64>>()),
-        (20, 64) => Some(size_of::<ConcurrentMerkleTree<20, 64>>()),
-        (20, 256) => Some(size_of::<ConcurrentMerkleTree<20, 256>>()),
-        (20, 1024) => Some(size_of::<ConcurrentMerkleTree<20, 1024>>()),
-        (20, 2048) => Some(size_of::<ConcurrentMerkleTree<20, 2048>>()),
-        (24, 64) => Some(size_of::<ConcurrentMerkleTree<24, 64>>()),
-        (24, 256) => Some(size_of::<ConcurrentMerkleTree<24, 256>>()),
-        (24, 512) => Some(size_of::<ConcurrentMerkleTree<24, 512>>()),
-        (24, 1024) => Some(size_of::<ConcurrentMerkleTree<24, 1024>>()),
-        (24, 2048) => Some(size_of::<ConcurrentMerkleTree<24, 2048>>()),
-        (26, 512) => Some(size_of::<ConcurrentMerkleTree<26, 512>>()),
-        (26, 1024) => Some(size_of::<ConcurrentMerkleTree<26, 1024>>()),
-        (26, 2048) => Some(size_of::<ConcurrentMerkleTree<26, 2048>>()),
-        (30, 512) => Some(size_of::<ConcurrentMerkleTree<30, 512>>()),
-        (30, 1024) => Some(size_of::<ConcurrentMerkleTree<30, 1024>>()),
-        (30, 2048) => Some(size_of::<ConcurrentMerkleTree<30, 2048>>()),
-        _ => None,
-    };
+    let tree_size = concurrent_merkle_tree_size(max_depth, max_buffer_size);
     tree_size.map(|s| s + calc_canopy_size(canopy_depth))
 }
 
+/// Calculates nodes of the given `level` of a merkle tree (level 0 consists of leaves, so level `max_depth` is the root)
+/// from the leaves appended to the tree, missing nodes are treated as empty ones.
+/// Only nodes that cover at least one of the given leaves are returned.
+pub fn calc_level_nodes(leaves: Vec<Node>, level: u32) -> Vec<Node> {
+    calc_level_nodes_from(leaves, 0, level)
+}
+
+/// Same as [calc_level_nodes], but starts from nodes of the given `from_level` instead of leaves,
+/// e.g. from canopy leaf nodes, which are at level `max_depth - canopy_depth`.
+pub fn calc_level_nodes_from(nodes: Vec<Node>, from_level: u32, to_level: u32) -> Vec<Node> {
+    let mut nodes = nodes;
+    for current_level in from_level..to_level {
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).copied().unwrap_or_else(|| empty_node(current_level));
+                keccak_hashv(&[&pair[0], &right])
+            })
+            .collect();
+    }
+    nodes
+}
+
 /// Calculates the amount of bytes required to store acanopy of given size.
 pub fn calc_canopy_size(canopy_depth: u32) -> usize {
     if canopy_depth == 0 {
@@ -336,4 +343,102 @@ mod test {
         assert_eq!(restore_canopy_depth_from_buffer(448), 3);
         assert_eq!(restore_canopy_depth_from_buffer(960), 4);
     }
+
+    #[test]
+    fn test_calc_level_nodes() {
+        let leaves = (1u8..=5).map(|i| [i; 32]).collect::<Vec<_>>();
+        let mut tree = make_concurrent_merkle_tree(3, 8).unwrap();
+        tree.initialize().unwrap();
+        for leaf in &leaves {
+            tree.append(*leaf).unwrap();
+        }
+
+        assert_eq!(calc_level_nodes(leaves.clone(), 0), leaves);
+        assert_eq!(calc_level_nodes(leaves.clone(), 2).len(), 2);
+        assert_eq!(calc_level_nodes(leaves, 3), vec![tree.get_root()]);
+    }
+
+    #[test]
+    fn test_change_log() {
+        let mut tree = make_concurrent_merkle_tree(3, 8).unwrap();
+        tree.initialize().unwrap();
+        for i in 1u8..=5 {
+            tree.append([i; 32]).unwrap();
+        }
+
+        let ind = tree.active_index() as usize;
+        let (borrowed, boxed) = (tree.change_log(ind), tree.change_logs(ind));
+        assert_eq!(borrowed.index(), 4);
+        assert_eq!(borrowed.index(), boxed.index());
+        assert_eq!(borrowed.root(), tree.get_root());
+        assert_eq!(borrowed.path_slice(), boxed.path_slice());
+    }
+
+    #[test]
+    fn test_supported_tree_sizes() {
+        for (max_depth, max_buffer_size) in SUPPORTED_TREE_SIZES {
+            assert!(calc_merkle_tree_size(*max_depth, *max_buffer_size, 0).is_some());
+            assert!(is_supported_tree_size(*max_depth, *max_buffer_size));
+        }
+        assert!(!is_supported_tree_size(4, 8));
+        assert!(!is_supported_tree_size(10, 64));
+        assert!(calc_merkle_tree_size(10, 64, 0).is_none());
+        assert!(matches!(
+            make_concurrent_merkle_tree(10, 64),
+            Err(BatchMintError::UnexpectedTreeSize(10, 64))
+        ));
+    }
+
+    #[test]
+    fn test_make_concurrent_merkle_tree_of_supported_sizes() {
+        // the biggest trees take a few MB, which is more than the default stack of a test thread in debug build
+        std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(|| {
+                for (max_depth, max_buffer_size) in SUPPORTED_TREE_SIZES {
+                    let mut tree = make_concurrent_merkle_tree(*max_depth, *max_buffer_size).unwrap();
+                    tree.initialize().unwrap();
+                    assert_eq!(tree.get_root(), empty_node(*max_depth));
+                    assert!(calc_merkle_tree_size(*max_depth, *max_buffer_size, 0).is_some());
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        // not supported by spl-account-compression, PrepareTree would fail for them
+        assert!(!is_supported_tree_size(14, 128));
+        assert!(!is_supported_tree_size(16, 256));
+        assert!(!is_supported_tree_size(18, 256));
+    }
+
+    #[test]
+    fn test_suggest_tree_size() {
+        for (max_depth, _) in SUPPORTED_TREE_SIZES {
+            let (depth, max_buffer_size) = suggest_tree_size(*max_depth).unwrap();
+            assert_eq!(depth, *max_depth);
+            assert_eq!(Some(max_buffer_size), default_buffer_for(*max_depth));
+        }
+        assert_eq!(suggest_tree_size(14), Some((14, 64)));
+        assert_eq!(suggest_tree_size(21), Some((24, 64)));
+        assert_eq!(suggest_tree_size(31), None);
+    }
+
+    #[test]
+    fn test_min_depth_for() {
+        assert_eq!(min_depth_for(0), Some(3));
+        assert_eq!(min_depth_for(8), Some(3));
+        assert_eq!(min_depth_for(9), Some(5));
+        assert_eq!(min_depth_for(50_000), Some(16));
+        assert_eq!(min_depth_for(1 << 21), Some(24));
+        assert_eq!(min_depth_for(1 << 30), Some(30));
+        assert_eq!(min_depth_for((1 << 30) + 1), None);
+    }
+
+    #[test]
+    fn test_default_buffer_for() {
+        assert_eq!(default_buffer_for(5), Some(8));
+        assert_eq!(default_buffer_for(14), Some(64));
+        assert_eq!(default_buffer_for(30), Some(512));
+        assert_eq!(default_buffer_for(4), None);
+    }
 }
@@ -1,34 +1,71 @@
 use blockbuster::programs::bubblegum::{BubblegumInstruction, Payload};
 use mpl_bubblegum::{InstructionName, LeafSchemaEvent};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     io::{Read, Write},
 };
 
-use mpl_bubblegum::types::{LeafSchema, MetadataArgs, Version};
+use mpl_bubblegum::types::{
+    Collection, Creator, LeafSchema, MetadataArgs, TokenProgramVersion, TokenStandard, Uses, Version,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::value::RawValue;
 use serde_with::DisplayFromStr;
 use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
 use solana_sdk::{pubkey::Pubkey, signature::Signature};
 
+use crate::merkle_tree_wrapper::calc_level_nodes;
+use crate::util::{keccak_hashv, metadata_hash};
+
 /// Represents an off-chain compressed NFT merkle tree, that can be uploaded to
 /// an immutable storage, and picked up by DAS validatiors, that verify the correctness
 /// of a batch mint.
 /// This type is used only for providing the batch mint data to DAS validators,
 /// all the off-chain batch mint changes should be done via BatchMintBuilder.
+///
+/// camelCase names of the fields of the batch mint and its instructions, as the TypeScript SDK names them,
+/// are accepted as aliases.
+/// The nested Bubblegum types (leaf schema, metadata args) are expected in the format of this SDK,
+/// since DAS parses them with the same types. The JSON is always written with snake_case fields.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BatchMint {
-    #[serde(with = "serde_with::As::<serde_with::DisplayFromStr>")]
+    #[serde(alias = "treeId", with = "serde_with::As::<serde_with::DisplayFromStr>")]
     pub tree_id: Pubkey,
+    #[serde(alias = "batchMints")]
     pub batch_mints: Vec<BatchMintInstruction>,
+    /// URL of metadata -> JSON text.
+    /// This is the map DAS uses to get metadata JSONs of batch mint assets.
+    #[serde(alias = "rawMetadataMap")]
     pub raw_metadata_map: HashMap<String, Box<RawValue>>, // URL of metadata -> JSON text
+    /// Asset ID -> metadata JSON text.
+    /// Unlike [BatchMint::raw_metadata_map] it allows different JSONs for assets sharing the same URI
+    /// (e.g. an edition series), and is intended for indexers that key metadata by asset ID.
+    /// DAS does not read it.
+    #[serde(
+        default,
+        alias = "assetMetadataMap",
+        skip_serializing_if = "HashMap::is_empty",
+        with = "serde_with::As::<HashMap<serde_with::DisplayFromStr, serde_with::Same>>"
+    )]
+    pub asset_metadata_map: HashMap<Pubkey, Box<RawValue>>,
+    #[serde(alias = "maxDepth")]
     pub max_depth: u32,
+    #[serde(alias = "maxBufferSize")]
     pub max_buffer_size: u32,
 
     // derived data
-    pub merkle_root: [u8; 32],    // validate
+    #[serde(alias = "merkleRoot")]
+    pub merkle_root: [u8; 32], // validate
+    #[serde(alias = "lastLeafHash")]
     pub last_leaf_hash: [u8; 32], // validate
+
+    /// Canonical hash of the document, see [BatchMint::canonical_metadata_hash].
+    /// If present, `validate_batch_mint` checks it, that makes the batch mint JSON self-verifiable.
+    /// It is not the `metadata_hash` passed to finalize, which is the hash of the uploaded file,
+    /// see [crate::batch_mint_builder::BatchMintBuilder::build_batch_mint_with_hash].
+    #[serde(default, alias = "metadataHash", skip_serializing_if = "Option::is_none")]
+    pub metadata_hash: Option<String>,
 }
 
 impl BatchMint {
@@ -41,6 +78,324 @@ impl BatchMint {
         let batch_mint = serde_json::from_reader(reader)?;
         Ok(batch_mint)
     }
+
+    /// Calculates the canonical hash of the batch mint document, that is stored in [BatchMint::metadata_hash].
+    ///
+    /// It is [metadata_hash] of the batch mint JSON with object keys sorted,
+    /// the `metadata_hash` field itself is excluded.
+    /// Since it doesn't cover the document as uploaded, it is not the hash to pass to finalize.
+    pub fn canonical_metadata_hash(&self) -> serde_json::error::Result<String> {
+        let mut document = serde_json::to_value(self)?;
+        if let serde_json::Value::Object(fields) = &mut document {
+            fields.remove("metadata_hash");
+        }
+        Ok(metadata_hash(&serde_json::to_vec(&sort_json_keys(document))?))
+    }
+
+    /// Verifies a proof of an asset, provided by a third party (e.g. DAS `getAssetProof`),
+    /// against the root of this batch mint, without rebuilding the tree.
+    ///
+    /// ## Arguments
+    /// * `asset_id` - ID of the asset, it must be the one of the asset with nonce `index` in this tree
+    /// * `leaf` - leaf hash of the asset
+    /// * `proof` - full proof of the asset, i.e. `max_depth` sibling nodes starting from the leaf level
+    /// * `index` - index of the leaf in the tree
+    pub fn verify_external_proof(&self, asset_id: Pubkey, leaf: [u8; 32], proof: &[[u8; 32]], index: u32) -> bool {
+        if asset_id != mpl_bubblegum::utils::get_asset_id(&self.tree_id, index as u64)
+            || proof.len() != self.max_depth as usize
+        {
+            return false;
+        }
+
+        let root = proof.iter().enumerate().fold(leaf, |node, (level, sibling)| {
+            if (index >> level) & 1 == 0 {
+                keccak_hashv(&[&node, sibling])
+            } else {
+                keccak_hashv(&[sibling, &node])
+            }
+        });
+        root == self.merkle_root
+    }
+
+    /// Reshapes the batch mint into the form DAS indexers ingest it, see [DasIngestionPayload].
+    pub fn to_das_ingestion(&self) -> DasIngestionPayload {
+        DasIngestionPayload {
+            schema_version: DAS_INGESTION_SCHEMA_VERSION,
+            tree_id: self.tree_id,
+            max_depth: self.max_depth,
+            max_buffer_size: self.max_buffer_size,
+            merkle_root: self.merkle_root,
+            last_leaf_hash: self.last_leaf_hash,
+            instructions: self.batch_mints.iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Recomputes canopy leaf nodes of the tree from leaf hashes of the batch mint assets,
+    /// the same way the batch mint builder collects them while assets are added.
+    /// This allows to serve proofs trimmed by the canopy after the on-chain canopy is cleared by finalize.
+    ///
+    /// Only canopy leaf nodes that cover at least one asset are returned.
+    pub fn rebuild_canopy(&self, canopy_depth: u32) -> Vec<[u8; 32]> {
+        if canopy_depth == 0 || canopy_depth > self.max_depth {
+            return Vec::new();
+        }
+
+        let leaves = self
+            .batch_mints
+            .iter()
+            .map(|batch_mint| batch_mint.leaf_update.hash())
+            .collect::<Vec<_>>();
+        calc_level_nodes(leaves, self.max_depth - canopy_depth)
+    }
+
+    /// Compares this batch mint with `other` one, e.g. a regenerated version of it,
+    /// and returns nonces of assets that were added, removed or changed in `other`, see [BatchMintDiff].
+    ///
+    /// An asset is considered changed if its metadata args, owner or delegate differ.
+    pub fn diff(&self, other: &BatchMint) -> BatchMintDiff {
+        let assets_by_nonce = |batch_mint: &BatchMint| {
+            batch_mint
+                .batch_mints
+                .iter()
+                .map(|mint| (mint.leaf_update.nonce(), mint))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let old_assets = assets_by_nonce(self);
+        let new_assets = assets_by_nonce(other);
+
+        let mut diff = BatchMintDiff::default();
+        for (nonce, new_asset) in &new_assets {
+            match old_assets.get(nonce) {
+                None => diff.added.push(*nonce),
+                Some(old_asset)
+                    if old_asset.mint_args != new_asset.mint_args
+                        || old_asset.leaf_update.owner() != new_asset.leaf_update.owner()
+                        || old_asset.leaf_update.delegate() != new_asset.leaf_update.delegate() =>
+                {
+                    diff.changed.push(*nonce)
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = old_assets
+            .keys()
+            .filter(|nonce| !new_assets.contains_key(nonce))
+            .copied()
+            .collect();
+        diff
+    }
+}
+
+/// Rebuilds JSON objects inserting their keys in sorted order, so the serialized document is the same
+/// regardless of whether the `preserve_order` feature of serde_json is enabled by some crate.
+fn sort_json_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(fields) => {
+            let mut fields = fields.into_iter().collect::<Vec<_>>();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(fields.into_iter().map(|(k, v)| (k, sort_json_keys(v))).collect())
+        }
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.into_iter().map(sort_json_keys).collect()),
+        value => value,
+    }
+}
+
+/// Difference between two versions of a batch mint, returned by [BatchMint::diff].
+/// All the nonces are sorted in ascending order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchMintDiff {
+    /// nonces of assets present only in the new version
+    pub added: Vec<u64>,
+    /// nonces of assets present only in the old version
+    pub removed: Vec<u64>,
+    /// nonces of assets present in both versions, but with different metadata args, owner or delegate
+    pub changed: Vec<u64>,
+}
+
+impl BatchMintDiff {
+    /// Returns true if the versions contain the same assets,
+    /// i.e. there is no need to finalize the new version.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Builder of [MetadataArgs] with defaults of a standard NFT:
+/// `TokenStandard::NonFungible`, `TokenProgramVersion::Original`, no creators, no collection,
+/// not mutable, zero seller fee.
+/// Any field can be overridden with the corresponding `with_*` method.
+#[derive(Debug, Clone)]
+pub struct MetadataArgsBuilder {
+    metadata_args: MetadataArgs,
+}
+
+impl MetadataArgsBuilder {
+    pub fn new(name: impl Into<String>, symbol: impl Into<String>, uri: impl Into<String>) -> Self {
+        MetadataArgsBuilder {
+            metadata_args: MetadataArgs {
+                name: name.into(),
+                symbol: symbol.into(),
+                uri: uri.into(),
+                seller_fee_basis_points: 0,
+                primary_sale_happened: false,
+                is_mutable: false,
+                edition_nonce: None,
+                token_standard: Some(TokenStandard::NonFungible),
+                collection: None,
+                uses: None,
+                token_program_version: TokenProgramVersion::Original,
+                creators: Vec::new(),
+            },
+        }
+    }
+
+    pub fn with_seller_fee_basis_points(mut self, seller_fee_basis_points: u16) -> Self {
+        self.metadata_args.seller_fee_basis_points = seller_fee_basis_points;
+        self
+    }
+
+    pub fn with_primary_sale_happened(mut self, primary_sale_happened: bool) -> Self {
+        self.metadata_args.primary_sale_happened = primary_sale_happened;
+        self
+    }
+
+    pub fn with_is_mutable(mut self, is_mutable: bool) -> Self {
+        self.metadata_args.is_mutable = is_mutable;
+        self
+    }
+
+    pub fn with_edition_nonce(mut self, edition_nonce: Option<u8>) -> Self {
+        self.metadata_args.edition_nonce = edition_nonce;
+        self
+    }
+
+    pub fn with_token_standard(mut self, token_standard: Option<TokenStandard>) -> Self {
+        self.metadata_args.token_standard = token_standard;
+        self
+    }
+
+    pub fn with_collection(mut self, collection: Collection) -> Self {
+        self.metadata_args.collection = Some(collection);
+        self
+    }
+
+    pub fn with_uses(mut self, uses: Uses) -> Self {
+        self.metadata_args.uses = Some(uses);
+        self
+    }
+
+    pub fn with_token_program_version(mut self, token_program_version: TokenProgramVersion) -> Self {
+        self.metadata_args.token_program_version = token_program_version;
+        self
+    }
+
+    pub fn with_creators(mut self, creators: Vec<Creator>) -> Self {
+        self.metadata_args.creators = creators;
+        self
+    }
+
+    pub fn build(self) -> MetadataArgs {
+        self.metadata_args
+    }
+}
+
+/// Size parameters of a merkle tree account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeParams {
+    /// depth of merkle tree
+    pub max_depth: u32,
+    /// size of changelogs buffer
+    pub max_buffer_size: u32,
+    /// level of merkle tree (not counting root) that contains canopy leaf nodes, 0 if no canopy
+    pub canopy_depth: u32,
+}
+
+/// Converts `(max_depth, max_buffer_size, canopy_depth)` tuple into [TreeParams].
+impl From<(u32, u32, u32)> for TreeParams {
+    fn from((max_depth, max_buffer_size, canopy_depth): (u32, u32, u32)) -> Self {
+        TreeParams {
+            max_depth,
+            max_buffer_size,
+            canopy_depth,
+        }
+    }
+}
+
+/// Compact snapshot of a batch mint builder progress, written by [crate::batch_mint_builder::BatchMintBuilder::checkpoint].
+/// Unlike the whole [BatchMint] it does not contain assets, so it can be cheaply saved while
+/// a huge batch mint is being built, and used to verify a resumed build is on track.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BatchMintCheckpoint {
+    /// number of assets added to the builder
+    pub count: u64,
+    pub root: [u8; 32],
+    pub last_leaf_hash: [u8; 32],
+    pub canopy_leaves: Vec<[u8; 32]>,
+}
+
+/// Progress of a canopy upload, written by [crate::batch_mint_client::BatchMintClient::finalize_tree_resumable]
+/// after each confirmed AddCanopy transaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FinalizeCheckpoint {
+    #[serde(with = "serde_with::As::<serde_with::DisplayFromStr>")]
+    pub tree_id: Pubkey,
+    /// index of the first canopy leaf node that has not been uploaded yet
+    pub next_canopy_index: u32,
+}
+
+impl FinalizeCheckpoint {
+    pub fn write_as_json(&self, writer: &mut dyn Write) -> serde_json::error::Result<()> {
+        serde_json::to_writer(writer, self)
+    }
+
+    pub fn read_as_json(reader: impl Read) -> serde_json::error::Result<FinalizeCheckpoint> {
+        serde_json::from_reader(reader)
+    }
+}
+
+/// Full state of a batch mint builder, including partially collected creator signatures,
+/// written by [crate::batch_mint_builder::BatchMintBuilder::save_session].
+/// Allows to pause a long signing campaign and resume it later
+/// with [crate::batch_mint_client::BatchMintClient::load_session].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchMintSession {
+    pub canopy_depth: u32,
+    pub canopy_leaves: Vec<[u8; 32]>,
+    /// assets with the signatures collected so far,
+    /// unlike the one returned by `build_batch_mint` it may lack some required signatures
+    pub batch_mint: BatchMint,
+}
+
+/// Values FinalizeTreeWithRoot (or FinalizeTreeWithRootAndCollection) instruction commits to the tree,
+/// see [crate::batch_mint_builder::BatchMintBuilder::finalize_args].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalizeArgs {
+    pub root: [u8; 32],
+    pub rightmost_leaf: [u8; 32],
+    pub rightmost_index: u32,
+    pub metadata_url: String,
+    pub metadata_hash: String,
+}
+
+/// Version of [DasIngestionPayload] layout, it is incremented on every incompatible change of the payload.
+///
+/// Version 1 targets DAS indexers built with blockbuster rev `59e983a`,
+/// i.e. every asset is represented as [BubblegumInstruction] of that version.
+pub const DAS_INGESTION_SCHEMA_VERSION: u32 = 1;
+
+/// Batch mint in the form DAS indexers ingest it:
+/// each asset is represented as a MintV1 [BubblegumInstruction], the same one DAS gets
+/// by parsing a regular MintV1 transaction, including the changelog with the asset proof path.
+pub struct DasIngestionPayload {
+    /// see [DAS_INGESTION_SCHEMA_VERSION]
+    pub schema_version: u32,
+    pub tree_id: Pubkey,
+    pub max_depth: u32,
+    pub max_buffer_size: u32,
+    pub merkle_root: [u8; 32],
+    pub last_leaf_hash: [u8; 32],
+    /// MintV1 instructions ordered by asset nonce
+    pub instructions: Vec<BubblegumInstruction>,
 }
 
 impl PartialEq for BatchMint {
@@ -56,12 +411,20 @@ impl PartialEq for BatchMint {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct BatchMintInstruction {
+    #[serde(alias = "treeUpdate")]
     pub tree_update: ChangeLogEventV1, // validate // derive from nonce
-    pub leaf_update: LeafSchema,       // validate
+    #[serde(alias = "leafUpdate")]
+    pub leaf_update: LeafSchema, // validate
+    #[serde(alias = "mintArgs")]
     pub mint_args: MetadataArgs,
     #[serde(with = "serde_with::As::<serde_with::DisplayFromStr>")]
     pub authority: Pubkey,
-    #[serde(with = "serde_with::As::<Option<HashMap<DisplayFromStr, DisplayFromStr>>>")]
+    // missing if there are no signatures, since JSON.stringify drops undefined fields
+    #[serde(
+        default,
+        alias = "creatorSignature",
+        with = "serde_with::As::<Option<HashMap<DisplayFromStr, DisplayFromStr>>>"
+    )]
     pub creator_signature: Option<HashMap<Pubkey, Signature>>, // signatures of the asset with the creator pubkey to ensure verified creator
 }
 
@@ -89,8 +452,53 @@ impl From<spl_account_compression::state::PathNode> for PathNode {
     }
 }
 
+/// Authority of the collection that signs the finalize transaction.
+pub enum CollectionAuthority {
+    /// Collection authority keypair held in the process memory.
+    Keypair(Keypair),
+    /// External signer of the collection authority (e.g. a hardware wallet),
+    /// so the private key never gets into the process memory.
+    External(Box<dyn Signer + Send + Sync>),
+    /// Only the public key of the collection authority is known.
+    /// Allows to build the finalize instruction with
+    /// [crate::batch_mint_client::BatchMintClient::finalize_tree_instruction_with_pubkeys]
+    /// for a transaction that is signed elsewhere, but not to send the finalize transaction.
+    Pubkey(Pubkey),
+}
+
+impl CollectionAuthority {
+    pub fn pubkey(&self) -> Pubkey {
+        match self {
+            CollectionAuthority::Keypair(keypair) => keypair.pubkey(),
+            CollectionAuthority::External(signer) => signer.pubkey(),
+            CollectionAuthority::Pubkey(pubkey) => *pubkey,
+        }
+    }
+
+    /// Returns the signer of the collection authority, or None if only its public key is known.
+    pub fn signer(&self) -> Option<&dyn Signer> {
+        match self {
+            CollectionAuthority::Keypair(keypair) => Some(keypair),
+            CollectionAuthority::External(signer) => Some(signer.as_ref()),
+            CollectionAuthority::Pubkey(_) => None,
+        }
+    }
+}
+
+impl From<Pubkey> for CollectionAuthority {
+    fn from(pubkey: Pubkey) -> Self {
+        CollectionAuthority::Pubkey(pubkey)
+    }
+}
+
+impl From<Keypair> for CollectionAuthority {
+    fn from(keypair: Keypair) -> Self {
+        CollectionAuthority::Keypair(keypair)
+    }
+}
+
 pub struct CollectionConfig {
-    pub collection_authority: Keypair,
+    pub collection_authority: CollectionAuthority,
     pub collection_authority_record_pda: Option<Pubkey>,
     pub collection_mint: Pubkey,
     pub collection_metadata: Pubkey,
@@ -142,3 +550,52 @@ impl From<&BatchMintInstruction> for BubblegumInstruction {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufWriter;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_read_camel_case_batch_mint() {
+        // the fixture is written by hand with camelCase field names, it is not an output of the TypeScript SDK,
+        // so it only shows the aliases are accepted
+        let batch_mint =
+            BatchMint::read_as_json(include_str!("../tests/fixtures/ts_batch_mint.json").as_bytes()).unwrap();
+
+        let tree_id = Pubkey::from_str("9vPfj1E7HrNZ7LL1nvwz2oEpV8boeUeesrijLqLqAuko").unwrap();
+        assert_eq!(batch_mint.tree_id, tree_id);
+        assert_eq!((batch_mint.max_depth, batch_mint.max_buffer_size), (10, 32));
+        assert_eq!(batch_mint.raw_metadata_map.len(), 2);
+        assert!(batch_mint.asset_metadata_map.is_empty());
+        assert_eq!(batch_mint.metadata_hash, None);
+
+        assert_eq!(batch_mint.batch_mints.len(), 2);
+        for (nonce, mint) in batch_mint.batch_mints.iter().enumerate() {
+            assert_eq!(mint.tree_update.id, tree_id);
+            assert_eq!(mint.tree_update.index, nonce as u32);
+            assert_eq!(mint.tree_update.path.len(), 11);
+            assert_eq!(mint.leaf_update.nonce(), nonce as u64);
+            assert_eq!(mint.mint_args.name, format!("TS NFT {nonce}"));
+        }
+        // signatures of an asset without verified creators may be omitted
+        assert_eq!(batch_mint.batch_mints[0].creator_signature, None);
+        let creator = Pubkey::from_str("EPubkr4wBvYcZbVXXbh8cMBmG3fk6fK3YptGAyjZibxu").unwrap();
+        assert!(batch_mint.batch_mints[1]
+            .creator_signature
+            .as_ref()
+            .is_some_and(|signatures| signatures.contains_key(&creator)));
+        assert_eq!(
+            batch_mint.batch_mints[1].leaf_update.owner(),
+            Pubkey::from_str("FMFZzxyWesc3dB4cQE9RYmzF7FDnTFSgXFZ4B3kJEmQU").unwrap()
+        );
+
+        // written back in the format of this SDK
+        let mut buffer = BufWriter::new(Vec::new());
+        batch_mint.write_as_json(&mut buffer).unwrap();
+        let json = String::from_utf8_lossy(buffer.buffer());
+        assert!(json.contains("\"batch_mints\"") && !json.contains("\"batchMints\""));
+        assert_eq!(BatchMint::read_as_json(buffer.buffer()).unwrap(), batch_mint);
+    }
+}
@@ -14,13 +14,16 @@ pub fn get_registrar_key() -> Pubkey {
     registrar_key
 }
 
+/// Anchor discriminator of a voter account of the staking program.
+pub const VOTER_DISCRIMINATOR: [u8; 8] = [241, 93, 35, 191, 254, 147, 17, 202];
+
 /// ## Arguments
 /// `registrar_account` - registrar
-/// `voter_authority` - payer
+/// `voter_authority` - staker
 pub fn get_voter_key(registrar_account: &Pubkey, voter_authority: &Pubkey) -> Pubkey {
     let (voter_key, _voter_bump) = Pubkey::find_program_address(
         &[
-            registrar_account.to_bytes().as_ref(), // staker or tree_creator?
+            registrar_account.to_bytes().as_ref(),
             b"voter".as_ref(),
             voter_authority.to_bytes().as_ref(),
         ],
@@ -29,6 +32,9 @@ pub fn get_voter_key(registrar_account: &Pubkey, voter_authority: &Pubkey) -> Pu
     voter_key
 }
 
+/// Offset of the owner (the staker) pubkey in the data of a mining account, it follows the account type and the reward pool.
+pub const MINING_OWNER_OFFSET: usize = 32;
+
 pub fn get_mining_key(staker: &Pubkey) -> Pubkey {
     find_mining_program_address(&mplx_rewards::id(), staker, &REWARD_POOL_ADDRESS).0
 }
@@ -36,10 +42,15 @@ pub fn get_mining_key(staker: &Pubkey) -> Pubkey {
 /// Account that hold additional merkle tree config,
 /// aka tree_authority PDA account previously initialized by `prepare_tree`.
 pub fn derive_tree_config_account(tree_data_account: &Pubkey) -> Pubkey {
-    let (tree_authority, _bump) = Pubkey::find_program_address(&[tree_data_account.as_ref()], &mpl_bubblegum::ID);
+    let (tree_authority, _bump) = derive_tree_config_account_with_bump(tree_data_account);
     tree_authority
 }
 
+/// Same as [derive_tree_config_account], but also returns the canonical bump of the PDA.
+pub fn derive_tree_config_account_with_bump(tree_data_account: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[tree_data_account.as_ref()], &mpl_bubblegum::ID)
+}
+
 pub fn discriminator(namespace: &str, name: &str) -> [u8; 8] {
     let preimage = format!("{}:{}", namespace, name);
 
@@ -47,3 +58,20 @@ pub fn discriminator(namespace: &str, name: &str) -> [u8; 8] {
     sighash.copy_from_slice(&anchor_lang::solana_program::hash::hash(preimage.as_bytes()).to_bytes()[..8]);
     sighash
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tree_config_account_bump() {
+        let tree_data_account = Pubkey::new_unique();
+        let (tree_config_account, bump) = derive_tree_config_account_with_bump(&tree_data_account);
+
+        assert_eq!(tree_config_account, derive_tree_config_account(&tree_data_account));
+        assert_eq!(
+            Pubkey::create_program_address(&[tree_data_account.as_ref(), &[bump]], &mpl_bubblegum::ID).unwrap(),
+            tree_config_account
+        );
+    }
+}
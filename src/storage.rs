@@ -0,0 +1,82 @@
+//! Upload of batch mint JSON documents to an immutable storage,
+//! the step between building a batch mint and finalizing it.
+
+use async_trait::async_trait;
+
+use crate::errors::BatchMintError;
+#[cfg(feature = "arweave")]
+use crate::util::metadata_hash;
+
+/// Immutable storage the batch mint JSON is uploaded to before the tree is finalized,
+/// see [crate::batch_mint_client::BatchMintClient::upload_and_finalize].
+#[async_trait]
+pub trait BatchMintStorage: Send + Sync {
+    /// Uploads the given document and returns its URL and hash,
+    /// that are passed to the finalize instruction as `metadata_url` and `metadata_hash`.
+    /// The hash is expected to be [crate::util::metadata_hash] of the document, the one DAS recomputes.
+    async fn upload(&self, bytes: &[u8]) -> Result<(String, String), BatchMintError>;
+}
+
+/// Default gateway the uploaded documents are served from.
+#[cfg(feature = "arweave")]
+pub const ARWEAVE_GATEWAY_URL: &str = "https://arweave.net";
+
+/// [BatchMintStorage] that stores documents on Arweave.
+///
+/// The document is sent to an upload service (e.g. a bundler node, or a proxy holding the Arweave wallet),
+/// which signs and pays for the Arweave transaction. The service is expected to accept the raw document
+/// as a POST body and to respond with a JSON object containing the `id` of the transaction.
+///
+/// The returned URL is `<gateway_url>/<id>`, and the hash is [crate::util::metadata_hash] of the uploaded document.
+#[cfg(feature = "arweave")]
+pub struct ArweaveStorage {
+    http_client: reqwest::Client,
+    upload_url: String,
+    gateway_url: String,
+}
+
+#[cfg(feature = "arweave")]
+impl ArweaveStorage {
+    /// Creates a storage that uploads documents to the given upload service,
+    /// and serves them from [ARWEAVE_GATEWAY_URL].
+    pub fn new(http_client: reqwest::Client, upload_url: impl Into<String>) -> ArweaveStorage {
+        ArweaveStorage {
+            http_client,
+            upload_url: upload_url.into(),
+            gateway_url: ARWEAVE_GATEWAY_URL.to_string(),
+        }
+    }
+
+    /// Sets the gateway the returned URLs point to, [ARWEAVE_GATEWAY_URL] by default.
+    pub fn with_gateway_url(mut self, gateway_url: impl Into<String>) -> Self {
+        self.gateway_url = gateway_url.into();
+        self
+    }
+}
+
+#[cfg(feature = "arweave")]
+#[async_trait]
+impl BatchMintStorage for ArweaveStorage {
+    async fn upload(&self, bytes: &[u8]) -> Result<(String, String), BatchMintError> {
+        #[derive(serde::Deserialize)]
+        struct UploadResponse {
+            id: String,
+        }
+
+        let response = self
+            .http_client
+            .post(&self.upload_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(bytes.to_vec())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| BatchMintError::StorageUploadFailed(e.to_string()))?
+            .json::<UploadResponse>()
+            .await
+            .map_err(|e| BatchMintError::StorageUploadFailed(e.to_string()))?;
+
+        let url = format!("{}/{}", self.gateway_url.trim_end_matches('/'), response.id);
+        Ok((url, metadata_hash(bytes)))
+    }
+}
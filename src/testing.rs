@@ -0,0 +1,201 @@
+//! Helpers for testing batch mint flows against a local solana-test-validator.
+//! Available with the `testing` feature.
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use mpl_bubblegum::accounts::MerkleTree;
+use mpl_common_constants::constants::{DAO_GOVERNING_MINT, DAO_PUBKEY};
+use mplx_staking_states::state::{
+    DepositEntry, Lockup, LockupKind, LockupPeriod, Registrar, Voter, VotingMintConfig, REGISTRAR_DISCRIMINATOR,
+};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::batch_mint_builder::BatchMintBuilder;
+use crate::batch_mint_client::parse_tree_state;
+use crate::merkle_tree_wrapper::ITree;
+use crate::pubkey_util::{get_mining_key, get_registrar_key, MINING_OWNER_OFFSET, REWARD_POOL_ADDRESS};
+use crate::tree_data_acc::TreeDataInfo;
+
+pub use crate::pubkey_util::VOTER_DISCRIMINATOR;
+
+/// An account that is preloaded into solana-test-validator.
+#[derive(Clone, Debug)]
+pub struct AccountInit {
+    /// name of the JSON file the account is saved to for the validator
+    pub name: String,
+    pub pubkey: Pubkey,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+}
+
+/// Staking accounts required by FinalizeTreeWithRoot instruction.
+#[derive(Clone, Debug)]
+pub struct StakingAccounts {
+    pub registrar: AccountInit,
+    pub voter: AccountInit,
+    pub mining: AccountInit,
+}
+
+/// FinalizeTreeWithRoot instruction, which is the final step for creating a batch mint
+/// requires registrar, voter and mining accounts that are not easy to create.
+/// That's why for the testing purposes we manually create these accounts,
+/// by pushing them directly to solana-test-validator.
+///
+/// The code of accounts initialization is taken from bubblegum program tests.
+///
+/// ## Arguments
+/// * `staker` - voter authority, i.e. the staker passed to `finalize_tree`
+/// * `stake_amount` - amount of deposited tokens, the weighted stake is `stake_amount * lockup.multiplier()`
+/// * `lockup` - lockup period of the deposit
+pub fn make_staking_accounts(staker: &Pubkey, stake_amount: u64, lockup: LockupPeriod) -> StakingAccounts {
+    let governance_program_id = Pubkey::from_str("CuyWCRdHT8pZLG793UR5R9z31AC49d47ZW9ggN6P7qZ4").unwrap();
+    let realm_authority = Pubkey::from_str("Euec5oQGN3Y9kqVrz6PQRfTpYSn6jK3k1JonDiMTzAtA").unwrap();
+    let voter_authority = *staker;
+
+    let mplx_mint_key = Pubkey::new_unique();
+    let grant_authority = Pubkey::new_unique();
+    let mining_key = get_mining_key(staker);
+    let registrar_key = get_registrar_key();
+
+    let (voter_key, voter_bump) = Pubkey::find_program_address(
+        &[
+            registrar_key.to_bytes().as_ref(),
+            b"voter".as_ref(),
+            voter_authority.to_bytes().as_ref(),
+        ],
+        &mplx_staking_states::ID,
+    );
+
+    // init structs for Registrar and Voter and fill it in with data
+    let voting_mint_config = VotingMintConfig {
+        mint: mplx_mint_key,
+        grant_authority,
+    };
+
+    let registrar = Registrar {
+        governance_program_id,
+        realm: Pubkey::new_from_array(DAO_PUBKEY),
+        realm_governing_token_mint: Pubkey::new_from_array(DAO_GOVERNING_MINT),
+        realm_authority,
+        voting_mints: [voting_mint_config, voting_mint_config],
+        padding: [0, 0, 0, 0, 0, 0, 0],
+        bump: 0,
+        reward_pool: REWARD_POOL_ADDRESS,
+    };
+
+    let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+    let lockup = Lockup {
+        start_ts: 0,
+        end_ts: current_time + Duration::from_secs(1000).as_millis() as u64,
+        cooldown_ends_at: 0,
+        cooldown_requested: false,
+        kind: LockupKind::Constant,
+        period: lockup,
+        _reserved0: [0; 16],
+        _reserved1: [0; 5],
+    };
+
+    let deposit_entry = DepositEntry {
+        lockup: lockup.clone(),
+        delegate: Pubkey::new_unique(),
+        amount_deposited_native: 0,
+        voting_mint_config_idx: 0,
+        is_used: true,
+        _reserved0: [0; 32],
+        _reserved1: [0; 6],
+        delegate_last_update_ts: 0,
+    };
+
+    let mut deposit_entries = [deposit_entry; 32];
+    deposit_entries[0] = DepositEntry {
+        lockup: lockup.clone(),
+        delegate: Pubkey::new_unique(),
+        amount_deposited_native: stake_amount,
+        voting_mint_config_idx: 0,
+        is_used: true,
+        _reserved0: [0; 32],
+        _reserved1: [0; 6],
+        delegate_last_update_ts: 0,
+    };
+
+    let voter = Voter {
+        deposits: deposit_entries,
+        voter_authority,
+        registrar: registrar_key,
+        voter_bump,
+        voter_weight_record_bump: 0,
+        _reserved1: [0; 14],
+    };
+
+    let registrar_acc_data = [REGISTRAR_DISCRIMINATOR.as_ref(), bytemuck::bytes_of(&registrar)].concat();
+    let voter_acc_data = [VOTER_DISCRIMINATOR.as_ref(), bytemuck::bytes_of(&voter)].concat();
+
+    let mut mining_acc_data = [0; mplx_rewards::state::WrappedMining::LEN];
+    mining_acc_data[MINING_OWNER_OFFSET..MINING_OWNER_OFFSET + 32].copy_from_slice(&voter_authority.to_bytes());
+
+    StakingAccounts {
+        registrar: AccountInit {
+            name: "registrar.json".to_string(),
+            pubkey: registrar_key,
+            data: registrar_acc_data,
+            owner: mplx_staking_states::ID,
+        },
+        voter: AccountInit {
+            name: "voter.json".to_string(),
+            pubkey: voter_key,
+            data: voter_acc_data,
+            owner: mplx_staking_states::ID,
+        },
+        mining: AccountInit {
+            name: "mining.json".to_string(),
+            pubkey: mining_key,
+            data: mining_acc_data.as_ref().to_vec(),
+            owner: mplx_rewards::ID,
+        },
+    }
+}
+
+/// Asserts that the tree data account of a finalized batch mint reflects the offline merkle tree of the builder:
+/// the roots, the rightmost proofs and the rightmost leaves are the same,
+/// and the sequence number of the on-chain tree is [BatchMintBuilder::parent_sequence_delta].
+///
+/// The account is parsed the same way as by [crate::batch_mint_client::BatchMintClient::verify_finalized],
+/// so tests do not need to reinterpret the account bytes as a [spl_account_compression::ConcurrentMerkleTree].
+///
+/// ## Arguments
+/// * `account_bytes` - raw bytes of the tree data account, e.g. `solana_client.get_account_data(&tree).await?`
+/// * `builder` - the builder the batch mint was finalized from
+pub fn assert_tree_matches(account_bytes: &[u8], builder: &BatchMintBuilder) {
+    let tree_data_info = TreeDataInfo::from_bytes(account_bytes).expect("Tree data account cannot be parsed");
+    assert_eq!(
+        (tree_data_info.max_depth, tree_data_info.max_buffer_size),
+        (builder.max_depth, builder.max_buffer_size),
+        "Tree geometry mismatch"
+    );
+
+    let merkle_tree = MerkleTree::from_bytes(account_bytes).expect("Tree data account cannot be parsed");
+    let tree_state = parse_tree_state(
+        &merkle_tree.serialized_tree,
+        tree_data_info.max_depth,
+        tree_data_info.max_buffer_size,
+    )
+    .expect("Tree data account cannot be parsed");
+
+    assert_eq!(
+        tree_state.sequence_number,
+        builder.parent_sequence_delta(),
+        "Sequence number mismatch"
+    );
+    assert_eq!(tree_state.root, builder.merkle.get_root(), "Root mismatch");
+    assert_eq!(
+        tree_state.rightmost_proof,
+        builder.merkle.get_rightmost_proof(),
+        "Rightmost proof mismatch"
+    );
+    assert_eq!(
+        tree_state.rightmost_leaf, builder.last_leaf_hash,
+        "Rightmost leaf mismatch"
+    );
+}
@@ -0,0 +1,34 @@
+//! This module contains util functionality for parsing out the information
+//! from a bubblegum tree config account (PDA derived from the merkle tree account)
+
+use mpl_bubblegum::{accounts::TreeConfig, types::DecompressibleState};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::errors::BatchMintError;
+
+/// Information about a merkle tree stored in its bubblegum tree config account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeConfigInfo {
+    pub tree_creator: Pubkey,
+    pub tree_delegate: Pubkey,
+    pub total_mint_capacity: u64,
+    /// for a finalized batch mint tree, equals to the number of assets in the batch mint
+    pub num_minted: u64,
+    pub is_public: bool,
+    pub is_decompressible: bool,
+}
+
+impl TreeConfigInfo {
+    /// Parses raw bytes taken from the Solana tree config account.
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<TreeConfigInfo, BatchMintError> {
+        let tree_config = TreeConfig::from_bytes(bytes)?;
+        Ok(TreeConfigInfo {
+            tree_creator: tree_config.tree_creator,
+            tree_delegate: tree_config.tree_delegate,
+            total_mint_capacity: tree_config.total_mint_capacity,
+            num_minted: tree_config.num_minted,
+            is_public: tree_config.is_public,
+            is_decompressible: tree_config.is_decompressible == DecompressibleState::Enabled,
+        })
+    }
+}
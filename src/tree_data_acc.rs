@@ -5,12 +5,56 @@ use std::mem::size_of;
 
 use crate::{
     errors::BatchMintError,
-    merkle_tree_wrapper::{calc_merkle_tree_size, restore_canopy_depth_from_buffer},
+    merkle_tree_wrapper::{calc_merkle_tree_size, calc_tree_data_account_size, restore_canopy_depth_from_buffer},
 };
 use mpl_bubblegum::{accounts::MerkleTree, types::ConcurrentMerkleTreeHeaderData};
 use spl_account_compression::state::CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1;
 use spl_merkle_tree_reference::{Node, EMPTY};
 
+/// Position of the header version byte in a tree data account: it follows the account type byte.
+const HEADER_VERSION_OFFSET: usize = 1;
+
+/// Makes sure the tree data account has the V1 header, the only one the SDK knows how to parse.
+/// Returns [BatchMintError::UnsupportedHeaderVersion] with the detected version otherwise,
+/// e.g. for a tree created by a newer version of the account compression program.
+pub fn check_header_version(bytes: &[u8]) -> std::result::Result<(), BatchMintError> {
+    match bytes.get(HEADER_VERSION_OFFSET) {
+        Some(&version) if version != 0 => Err(BatchMintError::UnsupportedHeaderVersion { version }),
+        _ => Ok(()),
+    }
+}
+
+/// Makes sure the size of the tree data account is exactly the one expected for the geometry in its header,
+/// with the canopy depth restored from the size of the account.
+/// Returns [BatchMintError::MalformedTreeAccount] otherwise, e.g. for a truncated account,
+/// or an account with extra data that does not form a canopy.
+pub fn check_tree_account_size(bytes: &[u8]) -> std::result::Result<(), BatchMintError> {
+    check_header_version(bytes)?;
+    let merkle_tree = MerkleTree::from_bytes(bytes)?;
+    let (max_depth, max_buffer_size) = match merkle_tree.tree_header {
+        ConcurrentMerkleTreeHeaderData::V1 {
+            max_buffer_size,
+            max_depth,
+            authority: _,
+            creation_slot: _,
+            is_batch_initialized: _,
+            padding: _,
+        } => (max_depth, max_buffer_size),
+    };
+    let expected_size = |canopy_depth| {
+        calc_tree_data_account_size(max_depth, max_buffer_size, canopy_depth)
+            .ok_or(BatchMintError::UnexpectedTreeSize(max_depth, max_buffer_size))
+    };
+
+    let actual = bytes.len();
+    let canopy_depth = restore_canopy_depth_from_buffer(actual.saturating_sub(expected_size(0)?) as u32);
+    let expected = expected_size(canopy_depth)?;
+    if expected != actual {
+        return Err(BatchMintError::MalformedTreeAccount { expected, actual });
+    }
+    Ok(())
+}
+
 /// Information about merkle tree stored in a solana account
 pub struct TreeDataInfo<'a> {
     pub max_depth: u32,
@@ -27,6 +71,7 @@ impl<'a> TreeDataInfo<'a> {
     /// ## Arguments:
     /// * `bytes` - raw bytes received as `solana_client.get_account(pubkey).unwrap().data()`
     pub fn from_bytes(bytes: &'a [u8]) -> std::result::Result<TreeDataInfo, BatchMintError> {
+        check_header_version(bytes)?;
         let merkle_tree = MerkleTree::from_bytes(bytes)?;
         let (max_depth, max_buffer_size) = match merkle_tree.tree_header {
             ConcurrentMerkleTreeHeaderData::V1 {
@@ -62,8 +107,7 @@ impl<'a> TreeDataInfo<'a> {
     /// using `add_canopy` bubblegum instruction.
     pub fn non_empty_canopy_leaves(&self) -> std::result::Result<Vec<&'a Node>, BatchMintError> {
         let node_size = size_of::<Node>();
-        let leaves_start_position = self.canopy_buffer.len() - (1 << self.canopy_depth) * node_size;
-        let leaves_buffer = &self.canopy_buffer[leaves_start_position..];
+        let leaves_buffer = self.canopy_leaves_buffer();
 
         let mut canopy_leaves: Vec<&'a Node> = Vec::with_capacity(self.canopy_leaves_count);
         for i in 0..self.canopy_leaves_count {
@@ -79,4 +123,171 @@ impl<'a> TreeDataInfo<'a> {
         }
         Ok(canopy_leaves)
     }
+
+    /// Scans the whole canopy leaves buffer and makes sure there are no "holes" in it,
+    /// i.e. there is no non-empty leaf node after an empty one, which would mean the canopy is corrupted.
+    /// Returns the number of non-empty canopy leaf nodes.
+    ///
+    /// Unlike [TreeDataInfo::non_empty_canopy_leaves], which stops at the first empty node,
+    /// this method returns [BatchMintError::CanopyHole] with the index of the first empty node
+    /// if there is a non-empty node after it.
+    pub fn validate_canopy_contiguity(&self) -> std::result::Result<usize, BatchMintError> {
+        let node_size = size_of::<Node>();
+        let leaves_buffer = self.canopy_leaves_buffer();
+
+        let mut non_empty_count = 0;
+        let mut first_empty = None;
+        for (i, canopy_leaf) in leaves_buffer
+            .chunks_exact(node_size)
+            .take(self.canopy_leaves_count)
+            .enumerate()
+        {
+            if canopy_leaf == EMPTY.as_slice() {
+                first_empty.get_or_insert(i);
+            } else if let Some(index) = first_empty {
+                return Err(BatchMintError::CanopyHole { index });
+            } else {
+                non_empty_count += 1;
+            }
+        }
+        Ok(non_empty_count)
+    }
+
+    /// Returns `true` if the whole canopy buffer (all the canopy levels, not only canopy leaf nodes) is zeroed,
+    /// which is expected for a tree right after FinalizeTreeWithRoot.
+    pub fn is_canopy_cleared(&self) -> bool {
+        self.canopy_buffer.iter().all(|b| *b == 0)
+    }
+
+    /// The part of canopy buffer that contains canopy leaf nodes (the lowest level of the canopy).
+    fn canopy_leaves_buffer(&self) -> &'a [u8] {
+        if self.canopy_depth == 0 {
+            return &[];
+        }
+        let leaves_start_position = self.canopy_buffer.len() - (1 << self.canopy_depth) * size_of::<Node>();
+        &self.canopy_buffer[leaves_start_position..]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::merkle_tree_wrapper::calc_canopy_size;
+
+    fn make_tree_data_info(canopy_depth: u32, canopy_buffer: &[u8]) -> TreeDataInfo {
+        TreeDataInfo {
+            max_depth: 5,
+            max_buffer_size: 8,
+            canopy_depth,
+            canopy_leaves_count: 1 << canopy_depth,
+            canopy_buffer,
+        }
+    }
+
+    fn set_canopy_leaf(canopy_buffer: &mut [u8], canopy_depth: u32, index: usize) {
+        let leaves_start_position = canopy_buffer.len() - (1 << canopy_depth) * size_of::<Node>();
+        let offset = leaves_start_position + index * size_of::<Node>();
+        canopy_buffer[offset..offset + size_of::<Node>()].copy_from_slice(&[1; 32]);
+    }
+
+    #[test]
+    fn test_validate_canopy_contiguity() {
+        const CANOPY: u32 = 3;
+        let mut canopy_buffer = vec![0u8; calc_canopy_size(CANOPY)];
+
+        assert_eq!(
+            make_tree_data_info(CANOPY, &canopy_buffer)
+                .validate_canopy_contiguity()
+                .unwrap(),
+            0
+        );
+
+        set_canopy_leaf(&mut canopy_buffer, CANOPY, 0);
+        set_canopy_leaf(&mut canopy_buffer, CANOPY, 1);
+        assert_eq!(
+            make_tree_data_info(CANOPY, &canopy_buffer)
+                .validate_canopy_contiguity()
+                .unwrap(),
+            2
+        );
+
+        // hole at index 2
+        set_canopy_leaf(&mut canopy_buffer, CANOPY, 4);
+        match make_tree_data_info(CANOPY, &canopy_buffer).validate_canopy_contiguity() {
+            Err(BatchMintError::CanopyHole { index }) => assert_eq!(index, 2),
+            _ => panic!("Canopy hole is not detected"),
+        }
+        assert_eq!(
+            make_tree_data_info(CANOPY, &canopy_buffer)
+                .non_empty_canopy_leaves()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_is_canopy_cleared() {
+        const CANOPY: u32 = 3;
+        let mut canopy_buffer = vec![0u8; calc_canopy_size(CANOPY)];
+        assert!(make_tree_data_info(CANOPY, &canopy_buffer).is_canopy_cleared());
+
+        // a node of an upper canopy level is not cleared
+        canopy_buffer[0] = 1;
+        assert!(!make_tree_data_info(CANOPY, &canopy_buffer).is_canopy_cleared());
+
+        assert!(make_tree_data_info(0, &[]).is_canopy_cleared());
+    }
+
+    fn make_tree_account_bytes(max_depth: u32, max_buffer_size: u32, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        // account type ConcurrentMerkleTree, header V1
+        bytes[0] = 1;
+        bytes[2..6].copy_from_slice(&max_buffer_size.to_le_bytes());
+        bytes[6..10].copy_from_slice(&max_depth.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_check_tree_account_size() {
+        for canopy_depth in [0, 3] {
+            let size = calc_tree_data_account_size(10, 32, canopy_depth).unwrap();
+            check_tree_account_size(&make_tree_account_bytes(10, 32, size)).unwrap();
+        }
+
+        let assert_malformed =
+            |len: usize, expected_size: usize| match check_tree_account_size(&make_tree_account_bytes(10, 32, len)) {
+                Err(BatchMintError::MalformedTreeAccount { expected, actual }) => {
+                    assert_eq!((expected, actual), (expected_size, len))
+                }
+                r => panic!("Unexpected result: {:?}", r),
+            };
+        // extra bytes after the canopy
+        let size = calc_tree_data_account_size(10, 32, 3).unwrap();
+        assert_malformed(size + 1, size);
+        // truncated
+        let size = calc_tree_data_account_size(10, 32, 0).unwrap();
+        assert_malformed(size - 32, size);
+        assert_malformed(100, size);
+
+        // the geometry in the header is not a supported one
+        assert!(matches!(
+            check_tree_account_size(&make_tree_account_bytes(10, 33, size)),
+            Err(BatchMintError::UnexpectedTreeSize(10, 33))
+        ));
+    }
+
+    #[test]
+    fn test_check_header_version() {
+        // account type ConcurrentMerkleTree, header V1
+        assert!(check_header_version(&[1, 0, 8, 0, 0, 0]).is_ok());
+        match check_header_version(&[1, 1, 8, 0, 0, 0]) {
+            Err(BatchMintError::UnsupportedHeaderVersion { version }) => assert_eq!(version, 1),
+            _ => panic!("Unsupported header version is not detected"),
+        }
+        match TreeDataInfo::from_bytes(&[1, 2, 8, 0, 0, 0]) {
+            Err(BatchMintError::UnsupportedHeaderVersion { version }) => assert_eq!(version, 2),
+            _ => panic!("Unsupported header version is not detected"),
+        }
+    }
 }
@@ -0,0 +1,94 @@
+//! Helpers for human-readable representation of merkle tree nodes (roots, leaf hashes, canopy nodes),
+//! keccak hashing used to build the tree, and hashing of uploaded batch mint documents.
+
+use std::str::FromStr;
+
+use solana_sdk::hash::Hash;
+
+use crate::errors::BatchMintError;
+
+/// Encodes a merkle tree node (root, leaf hash, etc.) as base58 string,
+/// the same way solana hashes and pubkeys are displayed.
+pub fn node_to_string(node: &[u8; 32]) -> String {
+    Hash::new_from_array(*node).to_string()
+}
+
+/// Decodes a merkle tree node from base58 string produced by [node_to_string].
+pub fn node_from_string(s: &str) -> std::result::Result<[u8; 32], BatchMintError> {
+    Hash::from_str(s)
+        .map(|hash| hash.to_bytes())
+        .map_err(|e| BatchMintError::IllegalArgumets(format!("Invalid merkle tree node {s}: {e}")))
+}
+
+/// Hash of an uploaded batch mint document, that is passed to finalize as `metadata_hash`:
+/// XXH64 (seed 0) of the document bytes, hex encoded the same way `xxhsum` prints it.
+/// DAS recomputes it from the downloaded document, so it must be calculated over the exact uploaded bytes.
+pub fn metadata_hash(document: &[u8]) -> String {
+    format!("{:016x}", xxhash_rust::xxh64::xxh64(document, 0))
+}
+
+/// Keccak256 hash of the concatenation of given values, the same as [solana_sdk::keccak::hashv].
+///
+/// With the `native-keccak` feature it is computed with the tiny-keccak crate instead of `solana_sdk`.
+#[inline]
+pub fn keccak_hashv(vals: &[&[u8]]) -> [u8; 32] {
+    #[cfg(not(feature = "native-keccak"))]
+    {
+        solana_sdk::keccak::hashv(vals).to_bytes()
+    }
+    #[cfg(feature = "native-keccak")]
+    {
+        use tiny_keccak::{Hasher, Keccak};
+
+        let mut hasher = Keccak::v256();
+        for val in vals {
+            hasher.update(val);
+        }
+        let mut hash = [0u8; 32];
+        hasher.finalize(&mut hash);
+        hash
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_node_string_roundtrip() {
+        for node in [[0u8; 32], [1u8; 32], [255u8; 32]] {
+            let encoded = node_to_string(&node);
+            assert_eq!(node_from_string(&encoded).unwrap(), node);
+        }
+        assert_eq!(node_to_string(&[0u8; 32]), "11111111111111111111111111111111");
+
+        assert!(matches!(
+            node_from_string("not a base58 string"),
+            Err(BatchMintError::IllegalArgumets(_))
+        ));
+        // valid base58, but too short
+        assert!(node_from_string("1111").is_err());
+    }
+
+    #[test]
+    fn test_metadata_hash() {
+        // XXH64 test vector of an empty input
+        assert_eq!(metadata_hash(b""), "ef46db3751d8e999");
+        assert_eq!(metadata_hash(b"{}").len(), 16);
+        assert_ne!(metadata_hash(b"{}"), metadata_hash(b"{ }"));
+    }
+
+    #[test]
+    fn test_keccak_hashv_matches_solana_keccak() {
+        let long_value = vec![7u8; 1000];
+        let inputs: [&[&[u8]]; 4] = [
+            &[],
+            &[b""],
+            &[b"batch", b"mint"],
+            &[&[1u8; 32], &[2u8; 32], &long_value],
+        ];
+        for vals in inputs {
+            assert_eq!(keccak_hashv(vals), solana_sdk::keccak::hashv(vals).to_bytes());
+        }
+    }
+}
@@ -1,28 +1,27 @@
 mod utils;
 
-use bubblegum_batch_sdk::batch_mint_client::BatchMintClient;
-use bubblegum_batch_sdk::errors::BatchMintError;
-use bubblegum_batch_sdk::merkle_tree_wrapper::{calc_canopy_size, calc_merkle_tree_size};
+use bubblegum_batch_sdk::batch_mint_client::{
+    BatchMintClient, FinalizeEvent, FinalizeJob, DEFAULT_CANOPY_COMPUTE_UNIT_LIMIT, MINIMUM_WEIGHTED_STAKE,
+};
+use bubblegum_batch_sdk::errors::{BatchMintError, FinalizeFailureReason};
+use bubblegum_batch_sdk::model::{BatchMint, FinalizeCheckpoint, MetadataArgsBuilder};
 use bubblegum_batch_sdk::pubkey_util;
-use bubblegum_batch_sdk::pubkey_util::{get_mining_key, REWARD_POOL_ADDRESS};
+use bubblegum_batch_sdk::storage::BatchMintStorage;
+use bubblegum_batch_sdk::testing::{assert_tree_matches, make_staking_accounts};
+use bubblegum_batch_sdk::util::metadata_hash;
+use futures::StreamExt;
 use mpl_bubblegum::types::MetadataArgs;
-use mpl_common_constants::constants::{DAO_GOVERNING_MINT, DAO_PUBKEY};
-use mplx_staking_states::state::{
-    DepositEntry, Lockup, LockupKind, LockupPeriod, Registrar, Voter, VotingMintConfig, REGISTRAR_DISCRIMINATOR,
-};
+use mplx_staking_states::state::LockupPeriod;
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
 use solana_program::instruction::{AccountMeta, InstructionError};
 use solana_rpc_client_api::client_error::ErrorKind;
 use solana_rpc_client_api::request::{RpcError, RpcResponseErrorData};
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::compute_budget::ComputeBudgetInstruction;
 use solana_sdk::transaction::{Transaction, TransactionError};
-use solana_sdk::{account::AccountSharedData, pubkey::Pubkey, signature::Keypair, signer::Signer};
-use spl_account_compression::ConcurrentMerkleTree;
-use std::{
-    str::FromStr,
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 use tokio::time::sleep;
 use utils::test_validator_runner::{AccountInit, ChildProcess, ContractToDeploy, TestValidatorRunner};
 
@@ -45,9 +44,6 @@ const TEST_PAYER: &[u8] = &[
     161, 99, 89, 97, 163, 63, 51, 106, 80, 233, 168, 246, 140, 97, 17,
 ];
 
-pub const VOTER_DISCRIMINATOR: [u8; 8] = [241, 93, 35, 191, 254, 147, 17, 202];
-const MINIMUM_WEIGHTED_STAKE: u64 = 30_000_000_000_000; // 30 weighted MPLX
-
 #[tokio::test]
 #[cfg(not(any(skip_integration_tests)))]
 #[serial_test::serial]
@@ -59,10 +55,31 @@ async fn test_complete_batch_mint_flow() {
     // Starting testing
     let batch_mint_client = BatchMintClient::new(solana_client.clone());
 
+    // deployed program supports batch mint instructions
+    batch_mint_client
+        .probe_program_features(&payer.pubkey())
+        .await
+        .unwrap()
+        .check_batch_mint_supported()
+        .unwrap();
+
+    // the staker has exactly the required stake
+    let stake_status = batch_mint_client
+        .check_staker_eligibility(&payer.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(stake_status.weighted_stake, MINIMUM_WEIGHTED_STAKE);
+    assert!(stake_status.is_eligible());
+
     const DEPTH: usize = 10;
     const BUFFER: usize = 32;
     const CANOPY: u32 = 3;
 
+    assert!(!batch_mint_client
+        .is_tree_ready(&tree_data_account.pubkey())
+        .await
+        .unwrap());
+
     let _sig_1 = batch_mint_client
         .prepare_tree(
             &payer,
@@ -74,6 +91,14 @@ async fn test_complete_batch_mint_flow() {
         )
         .await
         .unwrap();
+    assert!(batch_mint_client
+        .is_tree_ready(&tree_data_account.pubkey())
+        .await
+        .unwrap());
+    batch_mint_client
+        .verify_tree_account_size(&tree_data_account.pubkey())
+        .await
+        .unwrap();
 
     let mut batch_mint_builder = batch_mint_client
         .create_batch_mint_builder(&tree_data_account.pubkey())
@@ -96,10 +121,32 @@ async fn test_complete_batch_mint_flow() {
             &batch_mint_builder,
             &tree_creator,
             &payer,
+            &[],
         )
         .await
         .unwrap();
 
+    let tree_config = batch_mint_client
+        .read_tree_config(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(tree_config.num_minted, 1);
+    assert_eq!(tree_config.tree_creator, tree_creator.pubkey());
+
+    // the builder cannot be changed after the finalization
+    assert!(matches!(
+        batch_mint_builder.add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(2u8)),
+        Err(BatchMintError::BuilderSealed { .. })
+    ));
+
+    // no more batch mints can be added to the finalized tree
+    assert!(matches!(
+        batch_mint_client
+            .builder_from_onchain_state(&tree_data_account.pubkey())
+            .await,
+        Err(BatchMintError::TreeAlreadyInitialized { .. })
+    ));
+
     // Verification:
     // After FinilizeTreeWithRoot is executed, the offline ConcurrentMerkleTree
     // which is encapsulated by the batch mint, should be reflected in solana tree data account.
@@ -110,31 +157,14 @@ async fn test_complete_batch_mint_flow() {
         .await
         .unwrap();
 
-    let header_size = spl_account_compression::state::CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1;
-    let tree_size = calc_merkle_tree_size(DEPTH as u32, BUFFER as u32, CANOPY).unwrap();
-    let canopy_size = calc_canopy_size(CANOPY);
-
     // Comparing offchain merkle tree with the one created by finilize_tree
-    unsafe {
-        let (orig_tree_ptr, _vtable_ptr): (*const u8, *const u8) =
-            std::mem::transmute(Box::into_raw(batch_mint_builder.merkle));
-        let original: *const ConcurrentMerkleTree<DEPTH, BUFFER> = std::mem::transmute(orig_tree_ptr);
-
-        let acc_tree_ptr = account_raw_bytes.as_ptr().add(header_size);
-        let created: *const ConcurrentMerkleTree<DEPTH, BUFFER> = std::mem::transmute(acc_tree_ptr);
-
-        assert_eq!((*original).sequence_number, (*created).sequence_number);
-        assert_eq!((*original).rightmost_proof, (*created).rightmost_proof);
-    }
+    assert_tree_matches(&account_raw_bytes, &batch_mint_builder);
 
     // Canopy is cleared after finilize_tree
-    unsafe {
-        let canopy_segment_offset = account_raw_bytes.as_ptr().add(header_size + tree_size);
-        let canopy_ptr = canopy_segment_offset as *const [u8; 32];
-        for canopy_ind in 0..canopy_size / 32 {
-            assert_eq!(*canopy_ptr.add(canopy_ind), [0u8; 32]);
-        }
-    }
+    assert!(batch_mint_client
+        .verify_canopy_cleared(&tree_data_account.pubkey())
+        .await
+        .unwrap());
 }
 
 #[tokio::test]
@@ -176,6 +206,27 @@ async fn finalize_tree_without_enough_stake_fails() {
     // Starting testing
     let batch_mint_client = BatchMintClient::new(solana_client.clone());
 
+    // the lack of stake is detected before anything is sent
+    let stake_status = batch_mint_client
+        .check_staker_eligibility(&payer.pubkey())
+        .await
+        .unwrap();
+    assert!(!stake_status.is_eligible());
+    assert_eq!(
+        stake_status.weighted_stake,
+        (MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier() - 1) * LockupPeriod::OneYear.multiplier()
+    );
+    assert_eq!(
+        stake_status.shortfall(),
+        MINIMUM_WEIGHTED_STAKE - stake_status.weighted_stake
+    );
+    // a staker without a voter account has no stake
+    let stake_status = batch_mint_client
+        .check_staker_eligibility(&Keypair::new().pubkey())
+        .await
+        .unwrap();
+    assert_eq!(stake_status.weighted_stake, 0);
+
     const DEPTH: usize = 10;
     const BUFFER: usize = 32;
     const CANOPY: u32 = 3;
@@ -205,6 +256,34 @@ async fn finalize_tree_without_enough_stake_fails() {
         .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(1u8))
         .unwrap();
 
+    // the failure is detected by the simulation, without spending anything on the final transaction
+    batch_mint_client
+        .upload_canopy(&payer, &batch_mint_builder, &tree_creator)
+        .await
+        .unwrap();
+    let simulation = batch_mint_client
+        .simulate_finalize_tree(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+        )
+        .await
+        .unwrap();
+    assert!(simulation.canopy.is_empty());
+    assert!(!simulation.is_success());
+    assert_eq!(
+        simulation.finalize.err,
+        Some(TransactionError::InstructionError(1, InstructionError::Custom(6042)))
+    );
+    assert_eq!(
+        simulation.finalize_failure_reason(),
+        Some(FinalizeFailureReason::NotEnoughStake)
+    );
+
     let err = batch_mint_client
         .finalize_tree(
             &payer,
@@ -213,11 +292,16 @@ async fn finalize_tree_without_enough_stake_fails() {
             &batch_mint_builder,
             &tree_creator,
             &payer,
+            &[],
         )
         .await
         .err()
         .unwrap();
 
+    assert_eq!(
+        err.finalize_failure_reason(),
+        Some(FinalizeFailureReason::NotEnoughStake)
+    );
     match err {
         BatchMintError::SolanaClientErr(e) => match e.kind {
             ErrorKind::RpcError(rpc_error) => match rpc_error {
@@ -254,7 +338,411 @@ async fn finalize_tree_without_enough_stake_fails() {
 #[tokio::test]
 #[cfg(not(any(skip_integration_tests)))]
 #[serial_test::serial]
-async fn test_half_filled_assets() {
+async fn test_finalize_with_staker_other_than_payer() {
+    // Prepare env, the tree creator is the staker
+    let tree_creator = Keypair::from_bytes(TREE_CREATOR.as_ref()).unwrap();
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) = prepare_bubblegum_test_env_with_staker(
+        8929,
+        MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier(),
+        &tree_creator.pubkey(),
+    )
+    .await;
+    let staker = &tree_creator;
+
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+    assert!(batch_mint_client
+        .check_staker_eligibility(&staker.pubkey())
+        .await
+        .unwrap()
+        .is_eligible());
+    assert!(!batch_mint_client
+        .check_staker_eligibility(&payer.pubkey())
+        .await
+        .unwrap()
+        .is_eligible());
+
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 10, 32, 3)
+        .await
+        .unwrap();
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    for i in 1u8..=10 {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
+    }
+
+    batch_mint_client
+        .finalize_tree(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            staker,
+            &[],
+        )
+        .await
+        .unwrap();
+    assert!(batch_mint_client
+        .verify_finalized(&batch_mint_builder)
+        .await
+        .unwrap()
+        .is_valid());
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_prepare_tree_paid_by_payer() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8909, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+    assert_ne!(payer.pubkey(), tree_creator.pubkey());
+
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    let tree_creator_balance = solana_client.get_balance(&tree_creator.pubkey()).await.unwrap();
+    let payer_balance = solana_client.get_balance(&payer.pubkey()).await.unwrap();
+
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 10, 32, 4)
+        .await
+        .unwrap();
+
+    // the payer covers the rent of both tree data and tree config accounts, and the fee
+    assert_eq!(
+        tree_creator_balance,
+        solana_client.get_balance(&tree_creator.pubkey()).await.unwrap()
+    );
+    assert!(solana_client.get_balance(&payer.pubkey()).await.unwrap() < payer_balance);
+
+    let tree_config = solana_client
+        .get_account(&pubkey_util::derive_tree_config_account(&tree_data_account.pubkey()))
+        .await
+        .unwrap();
+    assert_eq!(tree_config.owner, mpl_bubblegum::ID);
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_half_filled_assets() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8909, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    // Starting testing
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    const DEPTH: usize = 5;
+    const BUFFER: usize = 8;
+    const CANOPY: u32 = 3;
+
+    let _sig_1 = batch_mint_client
+        .prepare_tree(
+            &payer,
+            &tree_creator,
+            &tree_data_account,
+            DEPTH as u32,
+            BUFFER as u32,
+            CANOPY,
+        )
+        .await
+        .unwrap();
+
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+
+    for i in 1u8..(((1 << DEPTH) / 2) + 2) {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
+    }
+
+    let _sig_2 = batch_mint_client
+        .finalize_tree(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // Verification:
+    let account_raw_bytes = solana_client
+        .get_account_data(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+
+    let verification = batch_mint_client.verify_finalized(&batch_mint_builder).await.unwrap();
+    assert!(verification.is_valid(), "{verification:?}");
+
+    // Thought the batch mint contains multiple assets, from the perspective of bubblegum merkle tree,
+    // it is only one node added
+    assert_eq!(1, batch_mint_builder.parent_sequence_delta());
+    assert_tree_matches(&account_raw_bytes, &batch_mint_builder);
+
+    assert!(batch_mint_client
+        .verify_canopy_cleared(&tree_data_account.pubkey())
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_finalize_tree_stream() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8909, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    // Starting testing
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    const DEPTH: usize = 5;
+    const BUFFER: usize = 8;
+    const CANOPY: u32 = 3;
+
+    let _sig_1 = batch_mint_client
+        .prepare_tree(
+            &payer,
+            &tree_creator,
+            &tree_data_account,
+            DEPTH as u32,
+            BUFFER as u32,
+            CANOPY,
+        )
+        .await
+        .unwrap();
+
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+
+    for i in 1u8..(((1 << DEPTH) / 2) + 2) {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
+    }
+
+    let events = batch_mint_client
+        .finalize_tree_stream(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+        )
+        .collect::<Vec<_>>()
+        .await;
+
+    // 5 canopy leaf nodes fit into a single AddCanopy transaction
+    assert_eq!(events.len(), 2);
+    match &events[0] {
+        Ok(FinalizeEvent::CanopyChunkConfirmed { index, signature: _ }) => assert_eq!(*index, 0),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+    match &events[1] {
+        Ok(FinalizeEvent::Finalized { signature: _ }) => {}
+        e => panic!("Unexpected event: {:?}", e),
+    }
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_finalize_many() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8929, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    // Starting testing
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    const DEPTH: usize = 5;
+    const BUFFER: usize = 8;
+    const CANOPY: u32 = 3;
+
+    let tree_data_accounts = [tree_data_account, Keypair::new()];
+
+    let mut batch_mint_builders = Vec::new();
+    for tree_data_account in &tree_data_accounts {
+        batch_mint_client
+            .prepare_tree(
+                &payer,
+                &tree_creator,
+                tree_data_account,
+                DEPTH as u32,
+                BUFFER as u32,
+                CANOPY,
+            )
+            .await
+            .unwrap();
+
+        let mut batch_mint_builder = batch_mint_client
+            .create_batch_mint_builder(&tree_data_account.pubkey())
+            .await
+            .unwrap();
+        for i in 1u8..=10 {
+            batch_mint_builder
+                .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+                .unwrap();
+        }
+        batch_mint_builders.push(batch_mint_builder);
+    }
+
+    let jobs = batch_mint_builders
+        .iter()
+        .map(|batch_mint_builder| FinalizeJob {
+            metadata_url: "http://mymetadata.ololo/",
+            metadata_hash: "mymetadatahash",
+            batch_mint_builder,
+            tree_creator: &tree_creator,
+            extra_signers: &[],
+        })
+        .collect::<Vec<_>>();
+
+    let results = batch_mint_client.finalize_many(&payer, &payer, jobs, 2).await;
+
+    assert_eq!(results.len(), 2);
+    for (result, tree_data_account) in results.into_iter().zip(&tree_data_accounts) {
+        result.unwrap();
+        assert!(batch_mint_client
+            .verify_canopy_cleared(&tree_data_account.pubkey())
+            .await
+            .unwrap());
+    }
+}
+
+// The deepest canopy allowed by prepare_tree is max_depth - 1,
+// i.e. canopy leaf nodes are only one level above the tree leaves.
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_full_canopy_chunk_fits_default_compute_budget() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8909, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    // AddCanopy transactions request DEFAULT_CANOPY_COMPUTE_UNIT_LIMIT compute units
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 6, 16, 5)
+        .await
+        .unwrap();
+
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    for i in 1u8..=64 {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
+    }
+    // 32 canopy leaf nodes: a full chunk of 24 nodes and the rest
+    assert_eq!(batch_mint_builder.canopy_leaves.len(), 32);
+
+    let canopy_signatures = batch_mint_client
+        .upload_canopy(&payer, &batch_mint_builder, &tree_creator)
+        .await
+        .unwrap();
+    assert_eq!(canopy_signatures.len(), 2);
+
+    batch_mint_client
+        .send_finalize(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_concurrent_canopy_upload() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8919, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    // small chunks to get more AddCanopy transactions than sent at once, all of them with a priority fee
+    let batch_mint_client = BatchMintClient::new(solana_client.clone())
+        .with_canopy_chunk_size(4)
+        .unwrap()
+        .with_canopy_concurrency(3)
+        .with_priority_fee(1_000);
+
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 6, 16, 5)
+        .await
+        .unwrap();
+
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    for i in 1u8..=64 {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
+    }
+
+    let report = batch_mint_client
+        .finalize_tree_with_report(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+        )
+        .await
+        .unwrap();
+    // each chunk is reported with the canopy leaf index it starts from
+    assert_eq!(
+        report
+            .canopy_signatures
+            .iter()
+            .map(|(start_index, _)| *start_index)
+            .collect::<Vec<_>>(),
+        (0..32).step_by(4).collect::<Vec<u32>>()
+    );
+    let signatures = report.canopy_signatures.iter().map(|(_, signature)| signature);
+    for signature in signatures.chain([&report.finalize_signature]) {
+        assert!(solana_client.confirm_transaction(signature).await.unwrap());
+    }
+    assert!(batch_mint_client
+        .verify_finalized(&batch_mint_builder)
+        .await
+        .unwrap()
+        .is_valid());
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_max_canopy_depth() {
     // Prepare env
     let (_validator, solana_client, payer, tree_creator, tree_data_account) =
         prepare_bubblegum_test_env(8909, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
@@ -264,7 +752,7 @@ async fn test_half_filled_assets() {
 
     const DEPTH: usize = 5;
     const BUFFER: usize = 8;
-    const CANOPY: u32 = 3;
+    const CANOPY: u32 = DEPTH as u32 - 1;
 
     let _sig_1 = batch_mint_client
         .prepare_tree(
@@ -283,12 +771,16 @@ async fn test_half_filled_assets() {
         .await
         .unwrap();
 
-    for i in 1u8..(((1 << DEPTH) / 2) + 2) {
+    // filling the tree completely
+    for i in 1u8..=(1 << DEPTH) {
         batch_mint_builder
             .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
             .unwrap();
     }
 
+    // each canopy leaf node covers exactly two tree leaves
+    assert_eq!(batch_mint_builder.canopy_leaves.len(), 1 << CANOPY);
+
     let _sig_2 = batch_mint_client
         .finalize_tree(
             &payer,
@@ -297,6 +789,7 @@ async fn test_half_filled_assets() {
             &batch_mint_builder,
             &tree_creator,
             &payer,
+            &[],
         )
         .await
         .unwrap();
@@ -307,31 +800,12 @@ async fn test_half_filled_assets() {
         .await
         .unwrap();
 
-    let header_size = spl_account_compression::state::CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1;
-    let tree_size = calc_merkle_tree_size(DEPTH as u32, BUFFER as u32, CANOPY).unwrap();
-    let canopy_size = calc_canopy_size(CANOPY);
-
-    unsafe {
-        let (orig_tree_ptr, _vtable_ptr): (*const u8, *const u8) =
-            std::mem::transmute(Box::into_raw(batch_mint_builder.merkle));
-        let original: *const ConcurrentMerkleTree<DEPTH, BUFFER> = std::mem::transmute(orig_tree_ptr);
-
-        let acc_tree_ptr = account_raw_bytes.as_ptr().add(header_size);
-        let created: *const ConcurrentMerkleTree<DEPTH, BUFFER> = std::mem::transmute(acc_tree_ptr);
+    assert_tree_matches(&account_raw_bytes, &batch_mint_builder);
 
-        // Thought the batch mint contains multiple assets, from the perspective of bubblegum merkle tree,
-        // it is only one node added
-        assert_eq!(1, (*created).sequence_number);
-        assert_eq!((*original).rightmost_proof, (*created).rightmost_proof);
-    }
-
-    unsafe {
-        let canopy_segment_offset = account_raw_bytes.as_ptr().add(header_size + tree_size);
-        let canopy_ptr = canopy_segment_offset as *const [u8; 32];
-        for canopy_ind in 0..canopy_size / 32 {
-            assert_eq!(*canopy_ptr.add(canopy_ind), [0u8; 32]);
-        }
-    }
+    assert!(batch_mint_client
+        .verify_canopy_cleared(&tree_data_account.pubkey())
+        .await
+        .unwrap());
 }
 
 // Canopy leaf nodes are added in portions of maximum 24 nodes.
@@ -419,6 +893,7 @@ async fn test_canopy_resume() {
             &batch_mint_builder,
             &tree_creator,
             &payer,
+            &[],
         )
         .await
         .unwrap();
@@ -429,31 +904,388 @@ async fn test_canopy_resume() {
         .await
         .unwrap();
 
-    let header_size = spl_account_compression::state::CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1;
-    let tree_size = calc_merkle_tree_size(DEPTH as u32, BUFFER as u32, CANOPY).unwrap();
-    let canopy_size = calc_canopy_size(CANOPY);
+    // Thought the batch mint contains multiple assets, from the perspective of bubblegum merkle tree,
+    // it is only one node added
+    assert_tree_matches(&account_raw_bytes, &batch_mint_builder);
+
+    assert!(batch_mint_client
+        .verify_canopy_cleared(&tree_data_account.pubkey())
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_finalize_after_complete_canopy_upload() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8919, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 5, 8, 3)
+        .await
+        .unwrap();
+
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    for i in 1u8..=20 {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
+    }
+    let batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+
+    // the whole canopy is uploaded, and then the process "crashes" before the final transaction
+    let canopy_signatures = batch_mint_client
+        .upload_canopy(&payer, &batch_mint_builder, &tree_creator)
+        .await
+        .unwrap();
+    assert!(!canopy_signatures.is_empty());
+    drop(batch_mint_builder);
+
+    // a fresh process has only the batch mint JSON
+    let batch_mint_json = serde_json::to_string(&batch_mint).unwrap();
+    let restored_batch_mint = serde_json::from_str(&batch_mint_json).unwrap();
+    let restored_builder = batch_mint_client
+        .restore_batch_mint_builder(&restored_batch_mint)
+        .await
+        .unwrap();
+
+    // nothing is left to upload
+    assert!(batch_mint_client
+        .upload_canopy(&payer, &restored_builder, &tree_creator)
+        .await
+        .unwrap()
+        .is_empty());
+
+    batch_mint_client
+        .finalize_tree(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &restored_builder,
+            &tree_creator,
+            &payer,
+            &[],
+        )
+        .await
+        .unwrap();
+
+    assert!(batch_mint_client
+        .verify_canopy_cleared(&tree_data_account.pubkey())
+        .await
+        .unwrap());
+}
+
+/// Storage that keeps uploaded documents in memory.
+#[derive(Default)]
+struct InMemoryStorage {
+    documents: std::sync::Mutex<Vec<Vec<u8>>>,
+}
+
+#[async_trait::async_trait]
+impl BatchMintStorage for InMemoryStorage {
+    async fn upload(&self, bytes: &[u8]) -> Result<(String, String), BatchMintError> {
+        let mut documents = self.documents.lock().unwrap();
+        documents.push(bytes.to_vec());
+        Ok((format!("memory://{}", documents.len() - 1), metadata_hash(bytes)))
+    }
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_upload_and_finalize() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8919, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 5, 8, 3)
+        .await
+        .unwrap();
+
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    for i in 1u8..=10 {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
+    }
+
+    let storage = InMemoryStorage::default();
+    batch_mint_client
+        .upload_and_finalize(&storage, &payer, &batch_mint_builder, &tree_creator, &payer, &[])
+        .await
+        .unwrap();
+
+    // the uploaded document is the batch mint the tree is finalized with
+    let documents = storage.documents.lock().unwrap();
+    assert_eq!(documents.len(), 1);
+    let uploaded_batch_mint = BatchMint::read_as_json(documents[0].as_slice()).unwrap();
+    let batch_mint = batch_mint_builder.build_batch_mint().unwrap();
+    assert_eq!(uploaded_batch_mint.tree_id, batch_mint.tree_id);
+    assert_eq!(uploaded_batch_mint.merkle_root, batch_mint.merkle_root);
+    assert!(batch_mint_client
+        .verify_finalized(&batch_mint_builder)
+        .await
+        .unwrap()
+        .is_valid());
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_finalize_deep_tree_with_trimmed_proof() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8909, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    // the minimal canopy for a tree of depth 20
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 20, 64, 3)
+        .await
+        .unwrap();
+
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    for i in 1u8..=10 {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
+    }
+
+    // only the proof nodes below the canopy are sent
+    assert_eq!(batch_mint_builder.trimmed_rightmost_proof().len(), 17);
+
+    batch_mint_client
+        .finalize_tree(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let verification = batch_mint_client.verify_finalized(&batch_mint_builder).await.unwrap();
+    assert!(verification.is_valid(), "{verification:?}");
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_finalize_without_mining_account() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8899, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
+
+    let batch_mint_client = BatchMintClient::new(solana_client.clone());
+
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 5, 8, 3)
+        .await
+        .unwrap();
+
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    batch_mint_builder
+        .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(1u8))
+        .unwrap();
+
+    // the staker has never set up rewards
+    let staker = Keypair::new();
+    let result = batch_mint_client
+        .finalize_tree(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &staker,
+            &[],
+        )
+        .await;
+    match result {
+        Err(BatchMintError::MiningAccountNotInitialized {
+            mining_account,
+            staker: error_staker,
+        }) => {
+            assert_eq!(mining_account, pubkey_util::get_mining_key(&staker.pubkey()));
+            assert_eq!(error_staker, staker.pubkey());
+        }
+        r => panic!("Unexpected result: {:?}", r),
+    }
+
+    // nothing has been uploaded
+    assert_eq!(
+        batch_mint_client
+            .upload_canopy(&payer, &batch_mint_builder, &tree_creator)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+#[cfg(not(any(skip_integration_tests)))]
+#[serial_test::serial]
+async fn test_finalize_tree_resumable() {
+    // Prepare env
+    let (_validator, solana_client, payer, tree_creator, tree_data_account) =
+        prepare_bubblegum_test_env(8929, MINIMUM_WEIGHTED_STAKE / LockupPeriod::OneYear.multiplier()).await;
 
-    unsafe {
-        let (orig_tree_ptr, _vtable_ptr): (*const u8, *const u8) =
-            std::mem::transmute(Box::into_raw(batch_mint_builder.merkle));
-        let original: *const ConcurrentMerkleTree<DEPTH, BUFFER> = std::mem::transmute(orig_tree_ptr);
+    // a single canopy leaf node per AddCanopy transaction, so each start index is sent in its own transaction
+    let batch_mint_client = BatchMintClient::new(solana_client.clone())
+        .with_canopy_chunk_size(1)
+        .unwrap();
 
-        let acc_tree_ptr = account_raw_bytes.as_ptr().add(header_size);
-        let created: *const ConcurrentMerkleTree<DEPTH, BUFFER> = std::mem::transmute(acc_tree_ptr);
+    batch_mint_client
+        .prepare_tree(&payer, &tree_creator, &tree_data_account, 5, 8, 3)
+        .await
+        .unwrap();
 
-        // Thought the batch mint contains multiple assets, from the perspective of bubblegum merkle tree,
-        // it is only one node added
-        assert_eq!(1, (*created).sequence_number);
-        assert_eq!((*original).rightmost_proof, (*created).rightmost_proof);
+    let mut batch_mint_builder = batch_mint_client
+        .create_batch_mint_builder(&tree_data_account.pubkey())
+        .await
+        .unwrap();
+    for i in 1u8..=20 {
+        batch_mint_builder
+            .add_asset(&payer.pubkey(), &payer.pubkey(), &make_test_metadata(i))
+            .unwrap();
     }
+    let canopy_len = batch_mint_builder.canopy_leaves.len() as u32;
+    assert_eq!(canopy_len, 5);
+
+    let checkpoint_file =
+        TempFile(std::env::temp_dir().join(format!("{}.checkpoint.json", tree_data_account.pubkey())));
+    let checkpoint_path = checkpoint_file.0.as_path();
+    let write_checkpoint = |next_canopy_index: u32| {
+        FinalizeCheckpoint {
+            tree_id: tree_data_account.pubkey(),
+            next_canopy_index,
+        }
+        .write_as_json(&mut std::fs::File::create(checkpoint_path).unwrap())
+        .unwrap();
+    };
+    let read_checkpoint = || FinalizeCheckpoint::read_as_json(std::fs::File::open(checkpoint_path).unwrap()).unwrap();
 
-    unsafe {
-        let canopy_segment_offset = account_raw_bytes.as_ptr().add(header_size + tree_size);
-        let canopy_ptr = canopy_segment_offset as *const [u8; 32];
-        for canopy_ind in 0..canopy_size / 32 {
-            assert_eq!(*canopy_ptr.add(canopy_ind), [0u8; 32]);
+    // the checkpoint claims the canopy is uploaded, while the tree has no canopy yet
+    write_checkpoint(canopy_len);
+    assert!(matches!(
+        batch_mint_client
+            .finalize_tree_resumable(
+                &payer,
+                "http://mymetadata.ololo/",
+                "mymetadatahash",
+                &batch_mint_builder,
+                &tree_creator,
+                &payer,
+                &[],
+                checkpoint_path,
+            )
+            .await,
+        Err(BatchMintError::FinalizeCheckpointMismatch {
+            checkpoint_uploaded,
+            onchain_uploaded: 0,
+        }) if checkpoint_uploaded == canopy_len as usize
+    ));
+
+    // a previous run uploaded the first two canopy chunks and crashed
+    let events = batch_mint_client
+        .finalize_tree_stream(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+        )
+        .take(2)
+        .collect::<Vec<_>>()
+        .await;
+    for (expected_index, event) in events.iter().enumerate() {
+        match event {
+            Ok(FinalizeEvent::CanopyChunkConfirmed { index, signature: _ }) => assert_eq!(*index, expected_index),
+            e => panic!("Unexpected event: {:?}", e),
         }
     }
+    write_checkpoint(2);
+
+    // the final transaction does not fit the compute unit limit, so only the canopy upload goes through
+    let signatures_before = count_confirmed_signatures(&solana_client, &tree_data_account.pubkey()).await;
+    let failing_client = BatchMintClient::new(solana_client.clone())
+        .with_canopy_chunk_size(1)
+        .unwrap()
+        .with_compute_unit_limit(1_000)
+        .with_canopy_compute_unit_limit(DEFAULT_CANOPY_COMPUTE_UNIT_LIMIT);
+    assert!(failing_client
+        .finalize_tree_resumable(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+            checkpoint_path,
+        )
+        .await
+        .is_err());
+
+    // only the chunks starting at canopy indexes 2, 3 and 4 are sent, and the checkpoint is advanced past them
+    assert_eq!(
+        count_confirmed_signatures(&solana_client, &tree_data_account.pubkey()).await - signatures_before,
+        (canopy_len - 2) as usize
+    );
+    assert_eq!(read_checkpoint().next_canopy_index, canopy_len);
+
+    // nothing is left to upload, only the final transaction is sent
+    let signatures_before = count_confirmed_signatures(&solana_client, &tree_data_account.pubkey()).await;
+    batch_mint_client
+        .finalize_tree_resumable(
+            &payer,
+            "http://mymetadata.ololo/",
+            "mymetadatahash",
+            &batch_mint_builder,
+            &tree_creator,
+            &payer,
+            &[],
+            checkpoint_path,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        count_confirmed_signatures(&solana_client, &tree_data_account.pubkey()).await - signatures_before,
+        1
+    );
+
+    assert!(!checkpoint_path.exists());
+    assert!(batch_mint_client
+        .verify_finalized(&batch_mint_builder)
+        .await
+        .unwrap()
+        .is_valid());
 }
 
 #[tokio::test]
@@ -521,9 +1353,9 @@ async fn test_finalize_canopy_tree_without_canopy_setup() {
                 tree_creator.pubkey(),
             )
             .unwrap();
-        let mut signing_keypairs = [&payer, &tree_creator, &payer].to_vec();
+        let mut signing_keypairs: Vec<&dyn Signer> = vec![&payer, &tree_creator, &payer];
         if let Some(ref collection_config) = batch_mint_builder.collection_config {
-            signing_keypairs.push(&collection_config.collection_authority);
+            signing_keypairs.push(collection_config.collection_authority.signer().unwrap());
         }
 
         let compute_budget = ComputeBudgetInstruction::set_compute_unit_limit(1000000);
@@ -586,9 +1418,20 @@ where
 async fn prepare_bubblegum_test_env(
     port: u32,
     stake_amount: u64,
+) -> (ChildProcess, Arc<RpcClient>, Keypair, Keypair, Keypair) {
+    let payer = Keypair::from_bytes(TEST_PAYER).unwrap();
+    prepare_bubblegum_test_env_with_staker(port, stake_amount, &payer.pubkey()).await
+}
+
+/// Same as [prepare_bubblegum_test_env], but the staking accounts are created for the given staker
+/// instead of the payer.
+async fn prepare_bubblegum_test_env_with_staker(
+    port: u32,
+    stake_amount: u64,
+    staker: &Pubkey,
 ) -> (ChildProcess, Arc<RpcClient>, Keypair, Keypair, Keypair) {
     // Preparing account for test
-    let test_accounts = prepare_test_accounts(stake_amount);
+    let test_accounts = prepare_test_accounts(stake_amount, staker);
 
     // Launching solana-test-validator with registrar and voter test accounts
     let mut tvr = TestValidatorRunner::new(port);
@@ -653,159 +1496,58 @@ struct TestAccounts {
     mining: AccountInit,
 }
 
-/// FinalizeTreeWithRoot instruction, which is the final step for creating a batch mint
-/// requires registrar, voter and mining accounts that are not easy to create.
-/// That's why for the testing purposes we manually create these accounts,
-/// by pushing them directly to solana-test-validator.
-///
-/// The code of accounts initialization is taken from bubblegum program tests.
-fn prepare_test_accounts(stake_amount: u64) -> TestAccounts {
+/// FinalizeTreeWithRoot instruction requires registrar, voter and mining accounts,
+/// that are pushed directly to solana-test-validator, see [make_staking_accounts].
+fn prepare_test_accounts(stake_amount: u64, staker: &Pubkey) -> TestAccounts {
     let tree_creator = Keypair::from_bytes(TREE_CREATOR.as_ref()).unwrap();
 
     let tree_key = Keypair::from_bytes(TREE_KEY.as_ref()).unwrap();
 
     let payer: Keypair = Keypair::from_bytes(TEST_PAYER).unwrap();
 
-    let governance_program_id = Pubkey::from_str("CuyWCRdHT8pZLG793UR5R9z31AC49d47ZW9ggN6P7qZ4").unwrap();
-    let realm_authority = Pubkey::from_str("Euec5oQGN3Y9kqVrz6PQRfTpYSn6jK3k1JonDiMTzAtA").unwrap();
-    let voter_authority = payer.pubkey();
-
-    let mplx_mint_key = Pubkey::new_unique();
-    let grant_authority = Pubkey::new_unique();
-    let mining_key = get_mining_key(&payer.pubkey());
-    let reward_pool_key = REWARD_POOL_ADDRESS;
-
-    let registrar_key = Pubkey::find_program_address(
-        &[DAO_PUBKEY.as_ref(), b"registrar".as_ref(), DAO_GOVERNING_MINT.as_ref()],
-        &mplx_staking_states::ID,
-    )
-    .0;
-
-    let (voter_key, voter_bump) = Pubkey::find_program_address(
-        &[
-            registrar_key.to_bytes().as_ref(),
-            b"voter".as_ref(),
-            voter_authority.to_bytes().as_ref(),
-        ],
-        &mplx_staking_states::ID,
-    );
-
-    // init structs for Registrar and Voter and fill it in with data
-    let voting_mint_config = VotingMintConfig {
-        mint: mplx_mint_key,
-        grant_authority,
-    };
-
-    let registrar = Registrar {
-        governance_program_id,
-        realm: Pubkey::new_from_array(DAO_PUBKEY),
-        realm_governing_token_mint: Pubkey::new_from_array(DAO_GOVERNING_MINT),
-        realm_authority,
-        voting_mints: [voting_mint_config, voting_mint_config],
-        padding: [0, 0, 0, 0, 0, 0, 0],
-        bump: 0,
-        reward_pool: reward_pool_key,
-    };
-
-    let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
-
-    let lockup = Lockup {
-        start_ts: 0,
-        end_ts: current_time + Duration::from_secs(1000).as_millis() as u64,
-        cooldown_ends_at: 0,
-        cooldown_requested: false,
-        kind: LockupKind::Constant,
-        period: LockupPeriod::OneYear,
-        _reserved0: [0; 16],
-        _reserved1: [0; 5],
-    };
-
-    let deposit_entry = DepositEntry {
-        lockup: lockup.clone(),
-        delegate: Pubkey::new_unique(),
-        amount_deposited_native: 0,
-        voting_mint_config_idx: 0,
-        is_used: true,
-        _reserved0: [0; 32],
-        _reserved1: [0; 6],
-        delegate_last_update_ts: 0,
-    };
-
-    let mut deposit_entries = [deposit_entry; 32];
-    deposit_entries[0] = DepositEntry {
-        lockup: lockup.clone(),
-        delegate: Pubkey::new_unique(),
-        amount_deposited_native: stake_amount,
-        voting_mint_config_idx: 0,
-        is_used: true,
-        _reserved0: [0; 32],
-        _reserved1: [0; 6],
-        delegate_last_update_ts: 0,
-    };
-
-    let voter = Voter {
-        deposits: deposit_entries,
-        voter_authority,
-        registrar: registrar_key,
-        voter_bump,
-        voter_weight_record_bump: 0,
-        _reserved1: [0; 14],
-    };
-
-    let registrar_acc_data = [REGISTRAR_DISCRIMINATOR.as_ref(), bytemuck::bytes_of(&registrar)].concat();
-    let voter_acc_data = [VOTER_DISCRIMINATOR.as_ref(), bytemuck::bytes_of(&voter)].concat();
-
-    // for next two accounts set arbitrary balance because it doesn't meter for test
-    let mut registrar_account =
-        AccountSharedData::new(10000000000000000, registrar_acc_data.len(), &mplx_staking_states::ID);
-    registrar_account.set_data_from_slice(registrar_acc_data.as_ref());
-
-    let mut voter_account = AccountSharedData::new(10000000000000000, voter_acc_data.len(), &mplx_staking_states::ID);
-    voter_account.set_data_from_slice(voter_acc_data.as_ref());
-    let mut mining_acc_data = [0; mplx_rewards::state::WrappedMining::LEN];
-    mining_acc_data[32..64].copy_from_slice(&voter_authority.to_bytes());
-
-    let mut mining_account = AccountSharedData::new(10000000000000000, mining_acc_data.len(), &mplx_rewards::ID);
-    mining_account.set_data_from_slice(mining_acc_data.as_ref());
+    let staking_accounts = make_staking_accounts(staker, stake_amount, LockupPeriod::OneYear);
 
     TestAccounts {
         payer,
         tree_creator,
         tree_data_account: tree_key,
-        registrar: AccountInit {
-            name: "registrar.json".to_string(),
-            pubkey: registrar_key,
-            data: registrar_acc_data,
-            owner: mplx_staking_states::ID,
-        },
-        voter: AccountInit {
-            name: "voter.json".to_string(),
-            pubkey: voter_key,
-            data: voter_acc_data,
-            owner: mplx_staking_states::ID,
-        },
-        mining: AccountInit {
-            name: "mining.json".to_string(),
-            pubkey: mining_key,
-            data: mining_acc_data.as_ref().to_vec(),
-            owner: mplx_rewards::ID,
-        },
+        registrar: staking_accounts.registrar.into(),
+        voter: staking_accounts.voter.into(),
+        mining: staking_accounts.mining.into(),
     }
 }
 
-fn make_test_metadata(index: u8) -> MetadataArgs {
-    MetadataArgs {
-        name: format!("{index}"),
-        symbol: format!("symbol-{index}"),
-        uri: format!("https://immutable-storage/asset/{index}"),
-        seller_fee_basis_points: 0,
-        primary_sale_happened: false,
-        is_mutable: false,
-        edition_nonce: None,
-        token_standard: Some(mpl_bubblegum::types::TokenStandard::NonFungible),
-        collection: None,
-        uses: None,
-        token_program_version: mpl_bubblegum::types::TokenProgramVersion::Original,
-        creators: Vec::new(),
+/// Number of successful transactions that reference the given account, at confirmed commitment.
+async fn count_confirmed_signatures(solana_client: &RpcClient, address: &Pubkey) -> usize {
+    solana_client
+        .get_signatures_for_address_with_config(
+            address,
+            GetConfirmedSignaturesForAddress2Config {
+                commitment: Some(CommitmentConfig::confirmed()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|signature| signature.err.is_none())
+        .count()
+}
+
+/// Removes the file when dropped, so that a failed test does not leave it behind for the next run.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
     }
 }
+
+fn make_test_metadata(index: u8) -> MetadataArgs {
+    MetadataArgsBuilder::new(
+        format!("{index}"),
+        format!("symbol-{index}"),
+        format!("https://immutable-storage/asset/{index}"),
+    )
+    .build()
+}
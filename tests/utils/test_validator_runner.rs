@@ -114,6 +114,17 @@ pub struct AccountInit {
     pub owner: Pubkey,
 }
 
+impl From<bubblegum_batch_sdk::testing::AccountInit> for AccountInit {
+    fn from(account: bubblegum_batch_sdk::testing::AccountInit) -> Self {
+        AccountInit {
+            name: account.name,
+            pubkey: account.pubkey,
+            data: account.data,
+            owner: account.owner,
+        }
+    }
+}
+
 impl AccountInit {
     pub fn to_json(&self) -> String {
         let pubkey = self.pubkey;